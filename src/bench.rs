@@ -0,0 +1,535 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::hint::black_box;
+use std::io::{self, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use flate2::write::GzEncoder;
+
+use crate::decode::headpack_decode;
+use crate::encode::headpack_encode;
+use crate::object::{Object, Value};
+
+/*
+    Size and speed of HeadPack next to the formats it's meant to replace, over a corpus of JSON
+    and NDJSON files. Every document is encoded on its own, and all of a file's documents are
+    compressed together, the way a log or a batch of messages would be.
+
+    JSON is pretty-printed with two spaces, minified JSON has no whitespace at all. Throughput is
+    in megabytes of minified JSON per second for every format, so it says how fast the same data
+    goes through rather than how fast each format's own bytes do.
+*/
+
+// only files with these extensions are picked up when benchmarking a directory
+pub const CORPUS_EXTENSIONS: [&str; 3] = ["json", "ndjson", "jsonl"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    MinifiedJson,
+    MessagePack,
+    Cbor,
+    HeadPack,
+}
+
+impl Format {
+    pub const ALL: [Format; 5] = [
+        Format::Json,
+        Format::MinifiedJson,
+        Format::MessagePack,
+        Format::Cbor,
+        Format::HeadPack,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Format::Json => "JSON",
+            Format::MinifiedJson => "minified JSON",
+            Format::MessagePack => "MessagePack",
+            Format::Cbor => "CBOR",
+            Format::HeadPack => "HeadPack",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct BenchOptions {
+    pub gzip: bool,
+    pub zstd: bool,
+    // how many times every file is encoded and decoded, the times are averaged
+    pub iterations: usize,
+}
+
+impl Default for BenchOptions {
+    fn default() -> Self {
+        BenchOptions {
+            gzip: false,
+            zstd: false,
+            iterations: 10,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Measurement {
+    pub size: usize,
+    // sizes after compression, if it was asked for
+    pub gzip: Option<usize>,
+    pub zstd: Option<usize>,
+    // time to encode or decode every document once
+    pub encode: Duration,
+    pub decode: Duration,
+}
+
+impl Measurement {
+    fn add(&mut self, other: &Measurement) {
+        let sum = |a: Option<usize>, b: Option<usize>| Some(a.unwrap_or(0) + b?);
+
+        self.size += other.size;
+        self.gzip = sum(self.gzip, other.gzip);
+        self.zstd = sum(self.zstd, other.zstd);
+        self.encode += other.encode;
+        self.decode += other.decode;
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FileReport {
+    pub path: PathBuf,
+    pub documents: usize,
+    pub measurements: Vec<(Format, Measurement)>,
+}
+
+impl FileReport {
+    pub fn measurement(&self, format: Format) -> &Measurement {
+        &self
+            .measurements
+            .iter()
+            .find(|(f, _)| *f == format)
+            .expect("every format is measured")
+            .1
+    }
+
+    // megabytes of minified JSON per second
+    pub fn throughput(&self, time: Duration) -> f64 {
+        let size = self.measurement(Format::MinifiedJson).size;
+
+        match time.is_zero() {
+            true => f64::INFINITY,
+            false => size as f64 / 1_000_000.0 / time.as_secs_f64(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct BenchReport {
+    pub files: Vec<FileReport>,
+    pub gzip: bool,
+    pub zstd: bool,
+}
+
+// benchmark a single file, or every JSON and NDJSON file in a directory and its subdirectories
+pub fn bench_corpus(path: &Path, options: &BenchOptions) -> io::Result<BenchReport> {
+    let mut files = Vec::new();
+
+    for file in corpus_files(path)? {
+        files.push(bench_file(&file, options)?);
+    }
+
+    Ok(BenchReport {
+        files,
+        gzip: options.gzip,
+        zstd: options.zstd,
+    })
+}
+
+pub fn bench_file(path: &Path, options: &BenchOptions) -> io::Result<FileReport> {
+    let with_path = |e: io::Error| io::Error::new(e.kind(), format!("{}: {}", path.display(), e));
+
+    let data = fs::read(path).map_err(with_path)?;
+
+    let documents: Vec<serde_json::Value> = serde_json::Deserializer::from_slice(&data)
+        .into_iter()
+        .collect::<Result<_, _>>()
+        .map_err(|e| with_path(e.into()))?;
+
+    let objects: Vec<Object> = documents.iter().cloned().map(Object::from_json).collect();
+
+    for (i, object) in objects.iter().enumerate() {
+        if !matches!(object.value, Value::Map(_) | Value::List(_)) {
+            return Err(with_path(io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "document {} is a {}, HeadPack messages have to be a Map or a List",
+                    i,
+                    object.value.type_name()
+                ),
+            )));
+        }
+    }
+
+    let mut measurements = Vec::with_capacity(Format::ALL.len());
+
+    for format in Format::ALL {
+        measurements.push((format, measure(format, &documents, &objects, options)?));
+    }
+
+    Ok(FileReport {
+        path: path.to_path_buf(),
+        documents: documents.len(),
+        measurements,
+    })
+}
+
+fn measure(
+    format: Format,
+    documents: &[serde_json::Value],
+    objects: &[Object],
+    options: &BenchOptions,
+) -> io::Result<Measurement> {
+    let iterations = options.iterations.max(1);
+
+    let encoded = encode(format, documents, objects.to_vec());
+    let concatenated = encoded.concat();
+
+    let mut measurement = Measurement {
+        size: concatenated.len(),
+        ..Default::default()
+    };
+
+    if options.gzip {
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&concatenated)?;
+        measurement.gzip = Some(encoder.finish()?.len());
+    }
+
+    if options.zstd {
+        measurement.zstd = Some(zstd::encode_all(concatenated.as_slice(), 0)?.len());
+    }
+
+    for _ in 0..iterations {
+        // HeadPack consumes what it reads and writes, so the copies are made before the clock starts
+        let objects = objects.to_vec();
+        let messages = encoded.clone();
+
+        let start = Instant::now();
+        black_box(encode(format, documents, objects));
+        measurement.encode += start.elapsed();
+
+        let start = Instant::now();
+        decode(format, messages)?;
+        measurement.decode += start.elapsed();
+    }
+
+    measurement.encode /= iterations as u32;
+    measurement.decode /= iterations as u32;
+
+    Ok(measurement)
+}
+
+fn encode(format: Format, documents: &[serde_json::Value], objects: Vec<Object>) -> Vec<Vec<u8>> {
+    match format {
+        Format::Json => documents
+            .iter()
+            .map(|document| serde_json::to_vec_pretty(document).unwrap())
+            .collect(),
+        Format::MinifiedJson => documents
+            .iter()
+            .map(|document| serde_json::to_vec(document).unwrap())
+            .collect(),
+        Format::MessagePack => documents
+            .iter()
+            .map(|document| rmp_serde::to_vec(document).unwrap())
+            .collect(),
+        Format::Cbor => documents
+            .iter()
+            .map(|document| {
+                let mut buf = Vec::new();
+                ciborium::into_writer(document, &mut buf).unwrap();
+                buf
+            })
+            .collect(),
+        Format::HeadPack => objects.into_iter().map(headpack_encode).collect(),
+    }
+}
+
+fn decode(format: Format, messages: Vec<Vec<u8>>) -> io::Result<()> {
+    let invalid = |e: String| io::Error::new(ErrorKind::InvalidData, e);
+
+    for message in messages {
+        match format {
+            Format::Json | Format::MinifiedJson => {
+                black_box(serde_json::from_slice::<serde_json::Value>(&message)?);
+            }
+            Format::MessagePack => {
+                black_box(
+                    rmp_serde::from_slice::<serde_json::Value>(&message)
+                        .map_err(|e| invalid(e.to_string()))?,
+                );
+            }
+            Format::Cbor => {
+                black_box(
+                    ciborium::from_reader::<serde_json::Value, _>(message.as_slice())
+                        .map_err(|e| invalid(e.to_string()))?,
+                );
+            }
+            Format::HeadPack => {
+                black_box(headpack_decode(VecDeque::from(message)));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// every JSON and NDJSON file at `path`, sorted, or just `path` if it's a file
+pub(crate) fn corpus_files(path: &Path) -> io::Result<Vec<PathBuf>> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+
+    let mut files = Vec::new();
+    let mut entries: Vec<PathBuf> = fs::read_dir(path)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<_, _>>()?;
+
+    entries.sort();
+
+    for entry in entries {
+        if entry.is_dir() {
+            files.extend(corpus_files(&entry)?);
+        } else if entry
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| CORPUS_EXTENSIONS.contains(&extension))
+        {
+            files.push(entry);
+        }
+    }
+
+    Ok(files)
+}
+
+impl BenchReport {
+    // every file added together
+    pub fn total(&self) -> FileReport {
+        let mut total = FileReport {
+            path: PathBuf::from("total"),
+            documents: 0,
+            measurements: Format::ALL
+                .iter()
+                .map(|format| (*format, Measurement::default()))
+                .collect(),
+        };
+
+        for file in &self.files {
+            total.documents += file.documents;
+
+            for (i, (_, measurement)) in file.measurements.iter().enumerate() {
+                total.measurements[i].1.add(measurement);
+            }
+        }
+
+        total
+    }
+
+    // one row per file and format, sizes in bytes and as a share of minified JSON
+    pub fn to_table(&self) -> String {
+        let total = self.total();
+        let reports: Vec<&FileReport> = self.files.iter().chain([&total]).collect();
+
+        let width = reports
+            .iter()
+            .map(|report| report.path.display().to_string().len())
+            .max()
+            .unwrap_or(0)
+            .max("file".len());
+
+        let mut header = format!(
+            "{:<width$}  {:>6}  {:<13}  {:>10}  {:>7}",
+            "file", "docs", "format", "bytes", "vs min"
+        );
+
+        if self.gzip {
+            header += &format!("  {:>10}", "gzip");
+        }
+
+        if self.zstd {
+            header += &format!("  {:>10}", "zstd");
+        }
+
+        header += &format!("  {:>11}  {:>11}", "encode MB/s", "decode MB/s");
+
+        let mut table = header + "\n";
+
+        for report in reports {
+            let minified = report.measurement(Format::MinifiedJson).size;
+
+            table.push('\n');
+
+            for (i, (format, measurement)) in report.measurements.iter().enumerate() {
+                let (path, documents) = match i {
+                    0 => (
+                        report.path.display().to_string(),
+                        report.documents.to_string(),
+                    ),
+                    _ => (String::new(), String::new()),
+                };
+
+                let share = match minified {
+                    0 => "-".to_string(),
+                    _ => format!("{:.1}%", measurement.size as f64 / minified as f64 * 100.0),
+                };
+
+                table += &format!(
+                    "{:<width$}  {:>6}  {:<13}  {:>10}  {:>7}",
+                    path,
+                    documents,
+                    format.name(),
+                    measurement.size,
+                    share
+                );
+
+                for compressed in [measurement.gzip, measurement.zstd].into_iter().flatten() {
+                    table += &format!("  {:>10}", compressed);
+                }
+
+                table += &format!(
+                    "  {:>11.1}  {:>11.1}\n",
+                    report.throughput(measurement.encode),
+                    report.throughput(measurement.decode)
+                );
+            }
+        }
+
+        table
+    }
+
+    // one row per file and format, with the totals last
+    pub fn to_csv(&self) -> String {
+        let mut csv = "file,documents,format,bytes".to_string();
+
+        if self.gzip {
+            csv += ",gzip_bytes";
+        }
+
+        if self.zstd {
+            csv += ",zstd_bytes";
+        }
+
+        csv += ",encode_mb_per_s,decode_mb_per_s\n";
+
+        let total = self.total();
+
+        for report in self.files.iter().chain([&total]) {
+            let mut path = report.path.display().to_string();
+
+            if path.contains([',', '"', '\n']) {
+                path = format!("\"{}\"", path.replace('"', "\"\""));
+            }
+
+            for (format, measurement) in &report.measurements {
+                csv += &format!(
+                    "{},{},{},{}",
+                    path,
+                    report.documents,
+                    format.name(),
+                    measurement.size
+                );
+
+                for compressed in [measurement.gzip, measurement.zstd].into_iter().flatten() {
+                    csv += &format!(",{}", compressed);
+                }
+
+                csv += &format!(
+                    ",{:.3},{:.3}\n",
+                    report.throughput(measurement.encode),
+                    report.throughput(measurement.decode)
+                );
+            }
+        }
+
+        csv
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a fresh directory for one test's corpus
+    fn corpus(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("headpack-bench-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        for (file, contents) in files {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+
+        dir
+    }
+
+    fn options() -> BenchOptions {
+        BenchOptions {
+            gzip: true,
+            zstd: true,
+            iterations: 1,
+        }
+    }
+
+    #[test]
+    fn every_file_and_format_is_measured() {
+        let dir = corpus(
+            "measured",
+            &[
+                ("b.ndjson", "{\"a\": 1}\n{\"a\": 2}\n"),
+                ("a/c.json", "[1, 2, 3]"),
+                ("notes.txt", "not JSON"),
+            ],
+        );
+
+        let report = bench_corpus(&dir, &options()).unwrap();
+        let paths: Vec<_> = report.files.iter().map(|f| f.path.clone()).collect();
+        assert_eq!(paths, vec![dir.join("a/c.json"), dir.join("b.ndjson")]);
+
+        let ndjson = &report.files[1];
+        assert_eq!(ndjson.documents, 2);
+        assert_eq!(ndjson.measurement(Format::MinifiedJson).size, 14);
+        assert!(ndjson.measurement(Format::HeadPack).size < 14);
+        assert!(ndjson.measurement(Format::Cbor).gzip.is_some());
+
+        let total = report.total();
+        assert_eq!(total.documents, 3);
+        assert_eq!(total.measurement(Format::MinifiedJson).size, 14 + 7);
+
+        // a header, then the totals and each file as a block of one row per format
+        assert_eq!(report.to_csv().lines().count(), 1 + 3 * Format::ALL.len());
+        assert!(report.to_table().contains("minified JSON"));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn files_that_are_not_messages_are_errors() {
+        let dir = corpus(
+            "errors",
+            &[("scalar.json", "[1]\n2\n"), ("broken.json", "{\"a\": ")],
+        );
+
+        for (file, kind) in [
+            ("scalar.json", ErrorKind::InvalidData),
+            ("broken.json", ErrorKind::UnexpectedEof),
+            ("missing.json", ErrorKind::NotFound),
+        ] {
+            let error = bench_file(&dir.join(file), &options()).unwrap_err();
+            assert_eq!(error.kind(), kind, "{}", error);
+            assert!(error.to_string().contains(file), "{}", error);
+        }
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
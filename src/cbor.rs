@@ -0,0 +1,714 @@
+use std::collections::VecDeque;
+use std::fmt::{self, Display, Formatter};
+
+use crate::decode::{headpack_try_decode, DecodeError};
+use crate::encode::headpack_encode;
+use crate::object::{Object, Value};
+use crate::pointer::escape_pointer_token;
+
+/*
+    CBOR (RFC 8949) <-> HeadPack.
+
+    CBOR                                     HeadPack
+    unsigned and negative integers           UInt and SInt
+    byte strings, text strings               Bytes, String
+    arrays, maps (with text keys)            List, Map
+    false, true, null, undefined             Bool, Bool, Null, Null
+    half and single floats, double floats    Float32, Float64
+    tag 1 (epoch time)                       Timestamp32, if it's whole seconds from 1970 to 2106
+    tags 2 and 3 (bignums)                   UInt and SInt, if they fit in 128 bits
+
+    An epoch time that doesn't fit a Timestamp32 is kept as the number it was, and any other tag
+    is dropped, leaving just the value it was on. Indefinite-length strings, arrays and maps are
+    read like any other. CBOR has a single kind of non-negative integer, so SInts that aren't
+    negative come back as UInts. Paths in errors are JSON pointers, the root is "".
+*/
+
+pub const TAG_EPOCH_TIME: u64 = 1;
+pub const TAG_POSITIVE_BIGNUM: u64 = 2;
+pub const TAG_NEGATIVE_BIGNUM: u64 = 3;
+
+// how deep arrays, maps and tags can nest, the same limit serde_json has for arrays and maps
+pub const MAX_DEPTH: usize = 128;
+
+// major types, the top 3 bits of the first byte of every item
+const UNSIGNED: u8 = 0;
+const NEGATIVE: u8 = 1;
+const BYTES: u8 = 2;
+const TEXT: u8 = 3;
+const ARRAY: u8 = 4;
+const MAP: u8 = 5;
+const TAG: u8 = 6;
+const SIMPLE: u8 = 7;
+
+// additional info that means the length is unknown, or the end of something that had one
+const INDEFINITE: u8 = 31;
+const BREAK: u8 = 0xff;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CborError {
+    // the input ends in the middle of an item
+    Truncated,
+    // additional info 28 to 30, or an indefinite length where there can't be one
+    Malformed { offset: usize },
+    // there's more input after the item
+    TrailingBytes { offset: usize },
+    InvalidUtf8 { path: String },
+    NonStringKey { path: String },
+    // a bignum that doesn't fit in 128 bits
+    BignumOutOfRange { path: String },
+    // a simple value other than false, true, null and undefined
+    UnsupportedSimpleValue { path: String, value: u8 },
+    // a type that has nothing to stand for it in CBOR
+    UnsupportedType { path: String, found: &'static str },
+    // HeadPack messages have to be a map or a list
+    NotAMessage { found: &'static str },
+    // arrays, maps and tags nested more than MAX_DEPTH deep
+    TooDeep { path: String },
+    // the HeadPack message to convert doesn't decode
+    HeadPack(DecodeError),
+}
+
+impl Display for CborError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let at = |path: &str| match path.is_empty() {
+            true => "at the root".to_string(),
+            false => format!("at {}", path),
+        };
+
+        match self {
+            CborError::Truncated => write!(f, "CBOR data is truncated"),
+            CborError::Malformed { offset } => write!(f, "malformed CBOR item at byte {}", offset),
+            CborError::TrailingBytes { offset } => {
+                write!(f, "unexpected data after the CBOR item at byte {}", offset)
+            }
+            CborError::InvalidUtf8 { path } => write!(f, "invalid UTF-8 {}", at(path)),
+            CborError::NonStringKey { path } => write!(f, "map key {} isn't a string", at(path)),
+            CborError::BignumOutOfRange { path } => {
+                write!(f, "bignum {} doesn't fit in 128 bits", at(path))
+            }
+            CborError::UnsupportedSimpleValue { path, value } => {
+                write!(f, "unsupported simple value {} {}", value, at(path))
+            }
+            CborError::UnsupportedType { path, found } => {
+                write!(f, "{} {} can't be written as CBOR", found, at(path))
+            }
+            CborError::NotAMessage { found } => write!(
+                f,
+                "can't encode a {} on its own, messages have to be a Map or a List",
+                found
+            ),
+            CborError::TooDeep { path } => write!(
+                f,
+                "arrays, maps and tags nest more than {} deep {}",
+                MAX_DEPTH,
+                at(path)
+            ),
+            CborError::HeadPack(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for CborError {}
+
+impl From<DecodeError> for CborError {
+    fn from(e: DecodeError) -> Self {
+        CborError::HeadPack(e)
+    }
+}
+
+// CBOR to a HeadPack message
+pub fn transcode_from_cbor(data: &[u8]) -> Result<Vec<u8>, CborError> {
+    let object = Object::from_cbor(data)?;
+
+    if !matches!(object.value, Value::Map(_) | Value::List(_)) {
+        return Err(CborError::NotAMessage {
+            found: object.value.type_name(),
+        });
+    }
+
+    Ok(headpack_encode(object))
+}
+
+// a HeadPack message to CBOR
+pub fn transcode_to_cbor(message: &[u8]) -> Result<Vec<u8>, CborError> {
+    headpack_try_decode(VecDeque::from(message.to_vec()))?.to_cbor()
+}
+
+// every CBOR item in `data`, one after the other (a CBOR sequence, RFC 8742)
+pub fn read_cbor_values(data: &[u8]) -> Result<Vec<Object>, CborError> {
+    let mut reader = Reader::new(data);
+    let mut objects = Vec::new();
+
+    while reader.position < data.len() {
+        objects.push(reader.read("")?);
+    }
+
+    Ok(objects)
+}
+
+impl Object {
+    pub fn from_cbor(data: &[u8]) -> Result<Self, CborError> {
+        let mut reader = Reader::new(data);
+        let object = reader.read("")?;
+
+        if reader.position != data.len() {
+            return Err(CborError::TrailingBytes {
+                offset: reader.position,
+            });
+        }
+
+        Ok(object)
+    }
+
+    pub fn to_cbor(&self) -> Result<Vec<u8>, CborError> {
+        let mut buf = Vec::new();
+        write(self, "", &mut buf)?;
+
+        Ok(buf)
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+    // how many arrays, maps and tags the item being read is inside of
+    depth: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Reader {
+            data,
+            position: 0,
+            depth: 0,
+        }
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8], CborError> {
+        let bytes = self
+            .position
+            .checked_add(length)
+            .and_then(|end| self.data.get(self.position..end))
+            .ok_or(CborError::Truncated)?;
+
+        self.position += length;
+        Ok(bytes)
+    }
+
+    fn peek(&self) -> Result<u8, CborError> {
+        self.data
+            .get(self.position)
+            .copied()
+            .ok_or(CborError::Truncated)
+    }
+
+    // the argument that follows the initial byte, None for an indefinite length
+    fn argument(&mut self, info: u8, offset: usize) -> Result<Option<u64>, CborError> {
+        let width = match info {
+            0..=23 => return Ok(Some(info as u64)),
+            24 => 1,
+            25 => 2,
+            26 => 4,
+            27 => 8,
+            INDEFINITE => return Ok(None),
+            _ => return Err(CborError::Malformed { offset }),
+        };
+
+        Ok(Some(
+            self.take(width)?
+                .iter()
+                .fold(0, |n, byte| n << 8 | *byte as u64),
+        ))
+    }
+
+    fn read(&mut self, path: &str) -> Result<Object, CborError> {
+        let offset = self.position;
+        let initial = self.take(1)?[0];
+        let (major, info) = (initial >> 5, initial & 0b1_1111);
+
+        if major == SIMPLE {
+            return self.simple(info, path, offset);
+        }
+
+        let argument = self.argument(info, offset)?;
+        let definite = |argument: Option<u64>| argument.ok_or(CborError::Malformed { offset });
+        let nested = matches!(major, ARRAY | MAP | TAG);
+
+        if nested {
+            if self.depth == MAX_DEPTH {
+                return Err(CborError::TooDeep {
+                    path: path.to_string(),
+                });
+            }
+
+            self.depth += 1;
+        }
+
+        let object = match major {
+            UNSIGNED => Object::uint(definite(argument)? as u128),
+            NEGATIVE => Object::sint(-1 - definite(argument)? as i128),
+            BYTES => Object::bytes(self.string(BYTES, argument, offset)?),
+            TEXT => {
+                let bytes = self.string(TEXT, argument, offset)?;
+
+                Object::string(
+                    String::from_utf8(bytes).map_err(|_| CborError::InvalidUtf8 {
+                        path: path.to_string(),
+                    })?,
+                )
+            }
+            ARRAY => {
+                let mut elements = Vec::new();
+                let mut remaining = argument.map(|n| n as usize);
+
+                while self.more(&mut remaining)? {
+                    elements.push(self.read(&format!("{}/{}", path, elements.len()))?);
+                }
+
+                Object::list(elements)
+            }
+            MAP => {
+                let mut items = Vec::new();
+                let mut remaining = argument.map(|n| n as usize);
+
+                while self.more(&mut remaining)? {
+                    let key = match self.read(path)?.value {
+                        Value::String { string, .. } => string,
+                        _ => {
+                            return Err(CborError::NonStringKey {
+                                path: path.to_string(),
+                            })
+                        }
+                    };
+
+                    let value = self.read(&format!("{}/{}", path, escape_pointer_token(&key)))?;
+                    items.push((key, value));
+                }
+
+                Object::map(items)
+            }
+            TAG => self.tagged(definite(argument)?, path)?,
+            _ => unreachable!("the major type only has 3 bits"),
+        };
+
+        if nested {
+            self.depth -= 1;
+        }
+
+        Ok(object)
+    }
+
+    // whether there's another element in an array or map, eating the break at the end of an
+    // indefinite one
+    fn more(&mut self, remaining: &mut Option<usize>) -> Result<bool, CborError> {
+        match remaining {
+            Some(0) => Ok(false),
+            Some(n) => {
+                *n -= 1;
+                Ok(true)
+            }
+            None if self.peek()? == BREAK => {
+                self.position += 1;
+                Ok(false)
+            }
+            None => Ok(true),
+        }
+    }
+
+    // the contents of a byte or text string, joining the chunks of an indefinite one
+    fn string(
+        &mut self,
+        major: u8,
+        length: Option<u64>,
+        offset: usize,
+    ) -> Result<Vec<u8>, CborError> {
+        if let Some(length) = length {
+            return Ok(self.take(length as usize)?.to_vec());
+        }
+
+        let mut bytes = Vec::new();
+
+        while self.peek()? != BREAK {
+            let chunk_offset = self.position;
+            let initial = self.take(1)?[0];
+
+            // chunks have to be definite strings of the same type
+            if initial >> 5 != major || initial & 0b1_1111 == INDEFINITE {
+                return Err(CborError::Malformed {
+                    offset: chunk_offset,
+                });
+            }
+
+            let length = self.argument(initial & 0b1_1111, chunk_offset)?;
+            bytes.extend_from_slice(
+                self.take(length.ok_or(CborError::Malformed { offset })? as usize)?,
+            );
+        }
+
+        self.position += 1;
+        Ok(bytes)
+    }
+
+    fn simple(&mut self, info: u8, path: &str, offset: usize) -> Result<Object, CborError> {
+        let object = match info {
+            20 => Object::bool(false),
+            21 => Object::bool(true),
+            22 | 23 => Object::null(),
+            24 => {
+                let value = self.take(1)?[0];
+                return Err(CborError::UnsupportedSimpleValue {
+                    path: path.to_string(),
+                    value,
+                });
+            }
+            25 => {
+                let half = u16::from_be_bytes(self.take(2)?.try_into().unwrap());
+                Object::float32(half_to_f32(half))
+            }
+            26 => Object::float32(f32::from_be_bytes(self.take(4)?.try_into().unwrap())),
+            27 => Object::float64(f64::from_be_bytes(self.take(8)?.try_into().unwrap())),
+            0..=19 => {
+                return Err(CborError::UnsupportedSimpleValue {
+                    path: path.to_string(),
+                    value: info,
+                })
+            }
+            // a break where no item can end, or a reserved value
+            _ => return Err(CborError::Malformed { offset }),
+        };
+
+        Ok(object)
+    }
+
+    fn tagged(&mut self, tag: u64, path: &str) -> Result<Object, CborError> {
+        let object = self.read(path)?;
+
+        let tagged = match (tag, &object.value) {
+            (TAG_EPOCH_TIME, Value::UInt(seconds)) => {
+                u32::try_from(*seconds).ok().map(Object::timestamp32)
+            }
+            (TAG_EPOCH_TIME, Value::Float32(_) | Value::Float64(_)) => {
+                let seconds = match object.value {
+                    Value::Float32(f) => f as f64,
+                    Value::Float64(f) => f,
+                    _ => unreachable!(),
+                };
+
+                (seconds.fract() == 0.0 && (0.0..=u32::MAX as f64).contains(&seconds))
+                    .then(|| Object::timestamp32(seconds as u32))
+            }
+            (TAG_POSITIVE_BIGNUM | TAG_NEGATIVE_BIGNUM, Value::Bytes(bytes)) => {
+                let out_of_range = || CborError::BignumOutOfRange {
+                    path: path.to_string(),
+                };
+
+                let significant: Vec<u8> = bytes
+                    .iter()
+                    .copied()
+                    .skip_while(|byte| *byte == 0)
+                    .collect();
+
+                if significant.len() > 16 {
+                    return Err(out_of_range());
+                }
+
+                let n = significant
+                    .iter()
+                    .fold(0u128, |n, byte| n << 8 | *byte as u128);
+
+                Some(match tag {
+                    TAG_POSITIVE_BIGNUM => Object::uint(n),
+                    _ => Object::sint(-1 - i128::try_from(n).map_err(|_| out_of_range())?),
+                })
+            }
+            _ => None,
+        };
+
+        Ok(tagged.unwrap_or(object))
+    }
+}
+
+// IEEE 754 half precision, every one of which is exactly a single precision float
+fn half_to_f32(half: u16) -> f32 {
+    let sign = if half >> 15 == 1 { -1.0 } else { 1.0 };
+    let exponent = ((half >> 10) & 0b1_1111) as i32;
+    let mantissa = (half & 0x3ff) as f32;
+
+    sign * match exponent {
+        0 => mantissa * 2f32.powi(-24),
+        31 if mantissa == 0.0 => f32::INFINITY,
+        31 => f32::NAN,
+        _ => (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+fn write_head(major: u8, argument: u64, buf: &mut Vec<u8>) {
+    let major = major << 5;
+
+    match argument {
+        0..=23 => buf.push(major | argument as u8),
+        24..=0xff => buf.extend([major | 24, argument as u8]),
+        0x100..=0xffff => {
+            buf.push(major | 25);
+            buf.extend((argument as u16).to_be_bytes());
+        }
+        0x1_0000..=0xffff_ffff => {
+            buf.push(major | 26);
+            buf.extend((argument as u32).to_be_bytes());
+        }
+        _ => {
+            buf.push(major | 27);
+            buf.extend(argument.to_be_bytes());
+        }
+    }
+}
+
+// a bignum's bytes, without leading zeros
+fn write_bignum(tag: u64, n: u128, buf: &mut Vec<u8>) {
+    let bytes: Vec<u8> = n
+        .to_be_bytes()
+        .into_iter()
+        .skip_while(|byte| *byte == 0)
+        .collect();
+
+    write_head(TAG, tag, buf);
+    write_head(BYTES, bytes.len() as u64, buf);
+    buf.extend(bytes);
+}
+
+fn write(object: &Object, path: &str, buf: &mut Vec<u8>) -> Result<(), CborError> {
+    match &object.value {
+        Value::Null => buf.push(SIMPLE << 5 | 22),
+        Value::Bool(b) => buf.push(SIMPLE << 5 | if *b { 21 } else { 20 }),
+        Value::UInt(u) => match u64::try_from(*u) {
+            Ok(u) => write_head(UNSIGNED, u, buf),
+            Err(_) => write_bignum(TAG_POSITIVE_BIGNUM, *u, buf),
+        },
+        Value::SInt(i) if *i >= 0 => match u64::try_from(*i) {
+            Ok(u) => write_head(UNSIGNED, u, buf),
+            Err(_) => write_bignum(TAG_POSITIVE_BIGNUM, *i as u128, buf),
+        },
+        Value::SInt(i) => {
+            // negative numbers are stored as -1 - n
+            let n = (-1 - *i) as u128;
+
+            match u64::try_from(n) {
+                Ok(n) => write_head(NEGATIVE, n, buf),
+                Err(_) => write_bignum(TAG_NEGATIVE_BIGNUM, n, buf),
+            }
+        }
+        Value::Float32(f) => {
+            buf.push(SIMPLE << 5 | 26);
+            buf.extend(f.to_be_bytes());
+        }
+        Value::Float64(f) => {
+            buf.push(SIMPLE << 5 | 27);
+            buf.extend(f.to_be_bytes());
+        }
+        Value::String { string, .. } => {
+            write_head(TEXT, string.len() as u64, buf);
+            buf.extend(string.as_bytes());
+        }
+        Value::Bytes(bytes) => {
+            write_head(BYTES, bytes.len() as u64, buf);
+            buf.extend(bytes);
+        }
+        Value::Timestamp32(t) => {
+            write_head(TAG, TAG_EPOCH_TIME, buf);
+            write_head(UNSIGNED, *t as u64, buf);
+        }
+        Value::Map(items) => {
+            write_head(MAP, items.len() as u64, buf);
+
+            for (key, value) in items {
+                write_head(TEXT, key.len() as u64, buf);
+                buf.extend(key.as_bytes());
+                write(
+                    value,
+                    &format!("{}/{}", path, escape_pointer_token(key)),
+                    buf,
+                )?;
+            }
+        }
+        Value::List(elements) | Value::IntSeq { elements, .. } => {
+            write_head(ARRAY, elements.len() as u64, buf);
+
+            for (i, element) in elements.iter().enumerate() {
+                write(element, &format!("{}/{}", path, i), buf)?;
+            }
+        }
+        Value::UserDefined { .. } => {
+            return Err(CborError::UnsupportedType {
+                path: path.to_string(),
+                found: object.value.type_name(),
+            })
+        }
+        Value::Run(_) => unreachable!("runs only exist inside encoded messages"),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // one of every type CBOR can hold, as it comes back
+    fn message() -> Object {
+        Object::map(vec![
+            ("null".to_string(), Object::null()),
+            ("bool".to_string(), Object::bool(false)),
+            ("uint".to_string(), Object::uint(u64::MAX as u128)),
+            ("negative".to_string(), Object::sint(-70_000)),
+            ("big".to_string(), Object::uint(1 << 100)),
+            ("big_negative".to_string(), Object::sint(-(1 << 100))),
+            ("float32".to_string(), Object::float32(0.5)),
+            ("float64".to_string(), Object::float64(0.1)),
+            ("string".to_string(), Object::string("é".to_string())),
+            ("bytes".to_string(), Object::bytes(vec![0, 255])),
+            ("timestamp".to_string(), Object::timestamp32(1_700_000_000)),
+            (
+                "list".to_string(),
+                Object::list(vec![Object::map(Vec::new()), Object::list(Vec::new())]),
+            ),
+        ])
+    }
+
+    #[test]
+    fn objects_round_trip() {
+        let cbor = message().to_cbor().unwrap();
+        assert_eq!(Object::from_cbor(&cbor), Ok(message()));
+
+        let transcoded = transcode_from_cbor(&cbor).unwrap();
+        assert_eq!(transcode_to_cbor(&transcoded), Ok(cbor.clone()));
+
+        let values = read_cbor_values(&[cbor.clone(), cbor].concat()).unwrap();
+        assert_eq!(values, vec![message(), message()]);
+
+        // non-negative SInts come back as UInts
+        let sint = Object::list(vec![Object::sint(5)]).to_cbor().unwrap();
+        assert_eq!(
+            Object::from_cbor(&sint),
+            Ok(Object::list(vec![Object::uint(5)]))
+        );
+    }
+
+    #[test]
+    fn other_encoders_are_understood() {
+        let json = serde_json::json!({"a": [1, -1, 300, "x", null, 2.5], "b": {"c": false}});
+        let mut cbor = Vec::new();
+        ciborium::into_writer(&json, &mut cbor).unwrap();
+
+        assert_eq!(Object::from_cbor(&cbor).unwrap().into_json(), json);
+
+        for (data, object) in [
+            // a half float, undefined, and an epoch time as a float
+            (vec![0xf9, 0x3c, 0x00], Object::float32(1.0)),
+            (vec![0xf7], Object::null()),
+            (
+                vec![0xc1, 0xfb, 0x41, 0xd0, 0, 0, 0, 0, 0, 0],
+                Object::timestamp32(1 << 30),
+            ),
+            // an epoch time before 1970 stays a number, an unknown tag is dropped
+            (vec![0xc1, 0x20], Object::sint(-1)),
+            (
+                vec![0xd8, 0x20, 0x61, b'u'],
+                Object::string("u".to_string()),
+            ),
+            // indefinite-length strings, arrays and maps
+            (
+                vec![0x7f, 0x61, b'a', 0x61, b'b', 0xff],
+                Object::string("ab".to_string()),
+            ),
+            (vec![0x5f, 0x41, 1, 0x40, 0xff], Object::bytes(vec![1])),
+            (
+                vec![0x9f, 0x01, 0x9f, 0xff, 0xff],
+                Object::list(vec![Object::uint(1), Object::list(Vec::new())]),
+            ),
+            (
+                vec![0xbf, 0x61, b'a', 0xf5, 0xff],
+                Object::map(vec![("a".to_string(), Object::bool(true))]),
+            ),
+        ] {
+            assert_eq!(Object::from_cbor(&data), Ok(object), "{:02x?}", data);
+        }
+    }
+
+    #[test]
+    fn malformed_cbor_is_an_error() {
+        let path = |p: &str| p.to_string();
+        let nested = [vec![0x81; MAX_DEPTH + 1], vec![0xf6]].concat();
+        let huge_bignum = [vec![0xc2, 0x51], vec![1; 17]].concat();
+
+        for (data, error) in [
+            (vec![], CborError::Truncated),
+            (vec![0x82, 0x01], CborError::Truncated),
+            (vec![0x9f, 0x01], CborError::Truncated),
+            (
+                vec![0x5b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff],
+                CborError::Truncated,
+            ),
+            (vec![0x81, 0x1c], CborError::Malformed { offset: 1 }),
+            (vec![0x1f], CborError::Malformed { offset: 0 }),
+            (vec![0xff], CborError::Malformed { offset: 0 }),
+            (
+                vec![0x7f, 0x41, 0x00, 0xff],
+                CborError::Malformed { offset: 1 },
+            ),
+            (vec![0xf6, 0xf6], CborError::TrailingBytes { offset: 1 }),
+            (
+                vec![0x81, 0x61, 0xff],
+                CborError::InvalidUtf8 { path: path("/0") },
+            ),
+            (
+                vec![0xa1, 0x01, 0x01],
+                CborError::NonStringKey { path: path("") },
+            ),
+            (huge_bignum, CborError::BignumOutOfRange { path: path("") }),
+            (
+                vec![0xa1, 0x61, b'a', 0xf0],
+                CborError::UnsupportedSimpleValue {
+                    path: path("/a"),
+                    value: 16,
+                },
+            ),
+            (
+                nested,
+                CborError::TooDeep {
+                    path: path(&"/0".repeat(MAX_DEPTH)),
+                },
+            ),
+        ] {
+            assert_eq!(Object::from_cbor(&data), Err(error), "{:02x?}", data);
+        }
+
+        assert_eq!(
+            transcode_from_cbor(&[0x01]),
+            Err(CborError::NotAMessage { found: "UInt" })
+        );
+
+        let user_defined = Object {
+            length: 39,
+            value: Value::UserDefined {
+                id: 39,
+                data: vec![0; 39],
+            },
+        };
+        assert_eq!(
+            Object::list(vec![user_defined]).to_cbor(),
+            Err(CborError::UnsupportedType {
+                path: path("/0"),
+                found: "UserDefined",
+            })
+        );
+    }
+
+    #[test]
+    fn malformed_headpack_messages_are_errors() {
+        assert!(matches!(
+            transcode_to_cbor(&[0xff, 0xff, 0xff]),
+            Err(CborError::HeadPack(DecodeError::Truncated))
+        ));
+    }
+}
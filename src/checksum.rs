@@ -0,0 +1,56 @@
+// CRC-32C (Castagnoli), reflected polynomial
+const POLYNOMIAL: u32 = 0x82f63b78;
+
+const TABLE: [u32; 256] = make_table();
+
+// length of the checksum trailer in bytes
+pub const CHECKSUM_LENGTH: usize = 4;
+
+const fn make_table() -> [u32; 256] {
+    let mut table = [0; 256];
+
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ POLYNOMIAL
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+
+        table[i] = crc;
+        i += 1;
+    }
+
+    table
+}
+
+pub fn crc32c(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+
+    for byte in data {
+        crc = TABLE[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc32c_matches_known_values() {
+        // the check value from the CRC catalogue, and test vectors from RFC 3720 (iSCSI)
+        assert_eq!(crc32c(b"123456789"), 0xe3069283);
+        assert_eq!(crc32c(&[0; 32]), 0x8a9136aa);
+        assert_eq!(crc32c(&[0xff; 32]), 0x62a8ab43);
+        assert_eq!(crc32c(&(0..32).collect::<Vec<u8>>()), 0x46dd794e);
+        assert_eq!(crc32c(&[]), 0);
+    }
+}
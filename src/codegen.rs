@@ -0,0 +1,848 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+
+use crate::object::{Object, Value};
+use crate::pointer::escape_pointer_token;
+use crate::schema::{Schema, SchemaError, SchemaType, Violation, ViolationKind};
+
+/*
+    Generates Rust types from a schema: a struct for every map with properties and an enum for
+    every string with an "enum" of strings. Each type gets `TryFrom<&Object>` (failing with the
+    first Violation found, whose path points into the object) and `From<T> for Object`.
+
+    Fields that are optional or nullable become Options (an optional field that can be null becomes
+    an Option<Option<T>>, None when it's missing and Some(None) when it's null), maps without
+    properties, mixed types and untyped values stay Objects. The generated code refers to this
+    crate as `mvencode`, and calls the conversion helpers at the bottom of this file.
+
+    An "integer" field is an i128 and a "number" field an f64, whichever kind of number was read
+    into them, so they are written back as an SInt and a Float64: a UInt or a Float32 keeps its
+    value but not its type. Fields that need to keep it should use the exact types instead.
+*/
+
+// generate the Rust types for `schema`, naming the root type `root_name`
+pub fn generate_rust(schema: &Schema, root_name: &str) -> Result<String, SchemaError> {
+    let mut generator = Generator::default();
+
+    if !is_struct(schema) {
+        return Err(SchemaError {
+            path: String::new(),
+            message: "the root of the schema must be a map with properties".to_string(),
+        });
+    }
+
+    let root_name = generator.type_name(root_name);
+    generator.generate_struct(schema, &root_name, "");
+
+    let mut code = String::from("// generated from a HeadPack schema, do not edit\n");
+
+    for definition in generator.definitions {
+        code.push('\n');
+        code.push_str(&definition);
+    }
+
+    Ok(code)
+}
+
+// for build scripts: read the JSON schema at `schema_path` and write the generated code to `out_path`
+pub fn generate_rust_file(
+    schema_path: impl AsRef<Path>,
+    root_name: &str,
+    out_path: impl AsRef<Path>,
+) -> io::Result<()> {
+    let json: serde_json::Value = serde_json::from_slice(&fs::read(schema_path)?)?;
+
+    let schema = Schema::from_json(&json).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+
+    let code =
+        generate_rust(&schema, root_name).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+
+    fs::write(out_path, code)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum RustType {
+    String,
+    SInt,
+    UInt,
+    Integer,
+    Float32,
+    Float64,
+    Number,
+    Bool,
+    Bytes,
+    Timestamp32,
+    Object,
+    List(Box<RustType>),
+    Struct(String),
+    Enum(String),
+    Option(Box<RustType>),
+}
+
+const OBJECT: &str = "::mvencode::object::Object";
+const VIOLATION: &str = "::mvencode::schema::Violation";
+const CODEGEN: &str = "::mvencode::codegen";
+
+impl RustType {
+    fn name(&self) -> String {
+        match self {
+            RustType::String => "String".to_string(),
+            RustType::SInt | RustType::Integer => "i128".to_string(),
+            RustType::UInt => "u128".to_string(),
+            RustType::Float32 => "f32".to_string(),
+            RustType::Float64 | RustType::Number => "f64".to_string(),
+            RustType::Bool => "bool".to_string(),
+            RustType::Bytes => "Vec<u8>".to_string(),
+            RustType::Timestamp32 => "u32".to_string(),
+            RustType::Object => OBJECT.to_string(),
+            RustType::List(items) => format!("Vec<{}>", items.name()),
+            RustType::Struct(name) | RustType::Enum(name) => name.clone(),
+            RustType::Option(inner) => format!("Option<{}>", inner.name()),
+        }
+    }
+
+    // an expression turning `value` (an &Object) at `path` (an &str) into a Result<Self, Violation>
+    fn read_expression(&self, value: &str, path: &str, depth: usize) -> String {
+        let helper = |name: &str| format!("{}::{}({}, {})", CODEGEN, name, value, path);
+
+        match self {
+            RustType::String => helper("to_string"),
+            RustType::SInt => helper("to_sint"),
+            RustType::UInt => helper("to_uint"),
+            RustType::Integer => helper("to_integer"),
+            RustType::Float32 => helper("to_float32"),
+            RustType::Float64 => helper("to_float64"),
+            RustType::Number => helper("to_number"),
+            RustType::Bool => helper("to_bool"),
+            RustType::Bytes => helper("to_bytes"),
+            RustType::Timestamp32 => helper("to_timestamp32"),
+            RustType::Object => helper("to_object"),
+            RustType::List(items) => {
+                let (i, element, element_path) = (
+                    format!("i{}", depth),
+                    format!("e{}", depth),
+                    format!("p{}", depth),
+                );
+
+                format!(
+                    "{}.and_then(|elements| elements.iter().enumerate().map(|({}, {})| {{ let {} = &format!(\"{{}}/{{}}\", {}, {}); {} }}).collect::<Result<Vec<_>, _>>())",
+                    helper("to_list"),
+                    i,
+                    element,
+                    element_path,
+                    path,
+                    i,
+                    items.read_expression(&element, &element_path, depth + 1)
+                )
+            }
+            RustType::Struct(name) | RustType::Enum(name) => {
+                format!("{}::from_object_at({}, {})", name, value, path)
+            }
+            RustType::Option(inner) => format!(
+                "if {}::is_null({}) {{ Ok(None) }} else {{ {}.map(Some) }}",
+                CODEGEN,
+                value,
+                inner.read_expression(value, path, depth)
+            ),
+        }
+    }
+
+    // an expression turning `value` (a Self) into an Object
+    fn write_expression(&self, value: &str, depth: usize) -> String {
+        let constructor = |name: &str| format!("{}::{}({})", OBJECT, name, value);
+
+        match self {
+            RustType::String => constructor("string"),
+            // the field doesn't remember if an Integer was a UInt or a Number was a Float32
+            RustType::SInt | RustType::Integer => constructor("sint"),
+            RustType::UInt => constructor("uint"),
+            RustType::Float32 => constructor("float32"),
+            RustType::Float64 | RustType::Number => constructor("float64"),
+            RustType::Bool => constructor("bool"),
+            RustType::Bytes => constructor("bytes"),
+            RustType::Timestamp32 => constructor("timestamp32"),
+            RustType::Object => value.to_string(),
+            RustType::List(items) => {
+                let element = format!("e{}", depth);
+
+                let convert = match **items {
+                    RustType::Struct(_) | RustType::Enum(_) => format!("{}::from", OBJECT),
+                    _ => format!(
+                        "|{}| {}",
+                        element,
+                        items.write_expression(&element, depth + 1)
+                    ),
+                };
+
+                format!(
+                    "{}::list({}.into_iter().map({}).collect())",
+                    OBJECT, value, convert
+                )
+            }
+            RustType::Struct(_) | RustType::Enum(_) => constructor("from"),
+            RustType::Option(inner) => {
+                let some = format!("x{}", depth);
+
+                format!(
+                    "match {} {{ Some({}) => {}, None => {}::null() }}",
+                    value,
+                    some,
+                    inner.write_expression(&some, depth + 1),
+                    OBJECT
+                )
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct Generator {
+    definitions: Vec<String>,
+    type_names: Vec<String>,
+}
+
+impl Generator {
+    // a type name that isn't taken yet
+    fn type_name(&mut self, hint: &str) -> String {
+        let mut name = pascal_case(hint);
+
+        if !is_type_ident(&name) {
+            name = format!("Type{}", name);
+        }
+
+        let mut unique = name.clone();
+        let mut n = 2;
+
+        while self.type_names.contains(&unique) {
+            unique = format!("{}{}", name, n);
+            n += 1;
+        }
+
+        self.type_names.push(unique.clone());
+        unique
+    }
+
+    fn rust_type(&mut self, schema: &Schema, hint: &str, path: &str) -> RustType {
+        let non_null: Vec<SchemaType> = schema
+            .types
+            .iter()
+            .copied()
+            .filter(|t| *t != SchemaType::Null)
+            .collect();
+
+        let base = match non_null.as_slice() {
+            _ if is_struct(schema) => {
+                let name = self.type_name(hint);
+                self.generate_struct(schema, &name, path);
+                RustType::Struct(name)
+            }
+            [SchemaType::String] if string_enum(schema).is_some() => {
+                let name = self.type_name(hint);
+                self.generate_enum(&string_enum(schema).unwrap(), &name);
+                RustType::Enum(name)
+            }
+            [SchemaType::List] => match &schema.items {
+                Some(items) => RustType::List(Box::new(self.rust_type(
+                    items,
+                    &format!("{}Item", hint),
+                    &format!("{}/items", path),
+                ))),
+                None => RustType::List(Box::new(RustType::Object)),
+            },
+            [SchemaType::String] => RustType::String,
+            [SchemaType::SInt] => RustType::SInt,
+            [SchemaType::UInt] => RustType::UInt,
+            [SchemaType::Integer] => RustType::Integer,
+            [SchemaType::Float32] => RustType::Float32,
+            [SchemaType::Float64] => RustType::Float64,
+            [SchemaType::Number] => RustType::Number,
+            [SchemaType::Bool] => RustType::Bool,
+            [SchemaType::Bytes] => RustType::Bytes,
+            [SchemaType::Timestamp32] => RustType::Timestamp32,
+            // anything else (or anything at all) can only be kept as is
+            _ => return RustType::Object,
+        };
+
+        if schema.types.contains(&SchemaType::Null) {
+            RustType::Option(Box::new(base))
+        } else {
+            base
+        }
+    }
+
+    fn generate_struct(&mut self, schema: &Schema, name: &str, path: &str) {
+        struct Field {
+            key: String,
+            ident: String,
+            // the type of the value when it's there
+            value_type: RustType,
+            required: bool,
+        }
+
+        // keep a place for this struct ahead of the types it contains
+        let index = self.definitions.len();
+        self.definitions.push(String::new());
+
+        let mut fields: Vec<Field> = Vec::new();
+
+        for (key, property) in &schema.properties {
+            let mut ident = snake_case(key);
+
+            if ident.is_empty() || ident.starts_with(|c: char| c.is_ascii_digit()) {
+                ident = format!("field_{}", ident);
+            }
+
+            let base = ident.clone();
+            let mut n = 2;
+
+            while fields.iter().any(|f| f.ident == ident) {
+                ident = format!("{}_{}", base, n);
+                n += 1;
+            }
+
+            let property_path = format!("{}/properties/{}", path, escape_pointer_token(key));
+            let value_type = self.rust_type(property, &format!("{}_{}", name, key), &property_path);
+
+            fields.push(Field {
+                key: key.clone(),
+                ident,
+                value_type,
+                required: schema.is_required(key),
+            });
+        }
+
+        // the type of each field, an optional field that can be null is an Option<Option<T>>
+        // so that a null that's there doesn't turn into a field that isn't
+        let field_type = |field: &Field| match field.required {
+            true => field.value_type.clone(),
+            false => RustType::Option(Box::new(field.value_type.clone())),
+        };
+
+        let mut code = String::new();
+
+        writeln!(code, "#[derive(Clone, Debug, PartialEq)]").unwrap();
+        writeln!(code, "pub struct {} {{", name).unwrap();
+
+        for field in &fields {
+            writeln!(
+                code,
+                "    pub {}: {},",
+                raw_ident(&field.ident),
+                field_type(field).name()
+            )
+            .unwrap();
+        }
+
+        writeln!(code, "}}\n").unwrap();
+
+        writeln!(code, "impl {} {{", name).unwrap();
+        writeln!(
+            code,
+            "    pub fn from_object_at(object: &{}, path: &str) -> Result<Self, {}> {{",
+            OBJECT, VIOLATION
+        )
+        .unwrap();
+        writeln!(
+            code,
+            "        let items = {}::to_map(object, path)?;\n",
+            CODEGEN
+        )
+        .unwrap();
+        writeln!(code, "        Ok({} {{", name).unwrap();
+
+        for field in &fields {
+            let value_path = format!(
+                "&format!(\"{{}}/{{}}\", path, {:?})",
+                escape_pointer_token(&field.key)
+            );
+
+            let read = field.value_type.read_expression("value", "path", 0);
+
+            let present = match field.required {
+                true => format!("{}?", read),
+                false => format!("Some({}?)", read),
+            };
+
+            let missing = match field.required {
+                true => format!(
+                    "return Err({}::missing_field(path, {:?}))",
+                    CODEGEN, field.key
+                ),
+                false => "None".to_string(),
+            };
+
+            writeln!(
+                code,
+                "            {}: match {}::field(items, {:?}) {{\n                Some(value) => {{\n                    let path = {};\n                    {}\n                }}\n                None => {},\n            }},",
+                raw_ident(&field.ident),
+                CODEGEN,
+                field.key,
+                value_path,
+                present,
+                missing
+            )
+            .unwrap();
+        }
+
+        writeln!(code, "        }})\n    }}\n}}\n").unwrap();
+
+        write_try_from(&mut code, name);
+
+        writeln!(code, "impl From<{}> for {} {{", name, OBJECT).unwrap();
+        writeln!(code, "    fn from(value: {}) -> Self {{", name).unwrap();
+        writeln!(code, "        let mut items = Vec::new();\n").unwrap();
+
+        for field in &fields {
+            let member = format!("value.{}", raw_ident(&field.ident));
+
+            match field.required {
+                true => writeln!(
+                    code,
+                    "        items.push(({:?}.to_string(), {}));",
+                    field.key,
+                    field.value_type.write_expression(&member, 0)
+                ),
+                // an optional field that's None is left out
+                false => writeln!(
+                    code,
+                    "        if let Some(v) = {} {{\n            items.push(({:?}.to_string(), {}));\n        }}",
+                    member,
+                    field.key,
+                    field.value_type.write_expression("v", 0)
+                ),
+            }
+            .unwrap();
+        }
+
+        writeln!(code, "\n        {}::map(items)\n    }}\n}}", OBJECT).unwrap();
+
+        self.definitions[index] = code;
+    }
+
+    fn generate_enum(&mut self, values: &[&str], name: &str) {
+        let mut variants: Vec<String> = Vec::new();
+
+        for value in values {
+            let mut variant = pascal_case(value);
+
+            if !is_type_ident(&variant) {
+                variant = format!("V{}", variant);
+            }
+
+            let base = variant.clone();
+            let mut n = 2;
+
+            while variants.contains(&variant) {
+                variant = format!("{}{}", base, n);
+                n += 1;
+            }
+
+            variants.push(variant);
+        }
+
+        let mut code = String::new();
+
+        writeln!(code, "#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]").unwrap();
+        writeln!(code, "pub enum {} {{", name).unwrap();
+
+        for variant in &variants {
+            writeln!(code, "    {},", variant).unwrap();
+        }
+
+        writeln!(code, "}}\n").unwrap();
+
+        writeln!(code, "impl {} {{", name).unwrap();
+        writeln!(code, "    pub fn as_str(&self) -> &'static str {{").unwrap();
+        writeln!(code, "        match self {{").unwrap();
+
+        for (variant, value) in variants.iter().zip(values) {
+            writeln!(code, "            {}::{} => {:?},", name, variant, value).unwrap();
+        }
+
+        writeln!(code, "        }}\n    }}\n").unwrap();
+
+        writeln!(
+            code,
+            "    pub fn from_object_at(object: &{}, path: &str) -> Result<Self, {}> {{",
+            OBJECT, VIOLATION
+        )
+        .unwrap();
+        writeln!(
+            code,
+            "        match {}::to_string(object, path)?.as_str() {{",
+            CODEGEN
+        )
+        .unwrap();
+
+        for (variant, value) in variants.iter().zip(values) {
+            writeln!(
+                code,
+                "            {:?} => Ok({}::{}),",
+                value, name, variant
+            )
+            .unwrap();
+        }
+
+        writeln!(
+            code,
+            "            _ => Err({}::not_in_enum(path)),\n        }}\n    }}\n}}\n",
+            CODEGEN
+        )
+        .unwrap();
+
+        write_try_from(&mut code, name);
+
+        writeln!(code, "impl From<{}> for {} {{", name, OBJECT).unwrap();
+        writeln!(code, "    fn from(value: {}) -> Self {{", name).unwrap();
+        writeln!(code, "        {}::from(value.as_str())\n    }}\n}}", OBJECT).unwrap();
+
+        self.definitions.push(code);
+    }
+}
+
+fn write_try_from(code: &mut String, name: &str) {
+    writeln!(code, "impl TryFrom<&{}> for {} {{", OBJECT, name).unwrap();
+    writeln!(code, "    type Error = {};\n", VIOLATION).unwrap();
+    writeln!(
+        code,
+        "    fn try_from(object: &{}) -> Result<Self, Self::Error> {{",
+        OBJECT
+    )
+    .unwrap();
+    writeln!(
+        code,
+        "        {}::from_object_at(object, \"\")\n    }}\n}}\n",
+        name
+    )
+    .unwrap();
+}
+
+fn is_struct(schema: &Schema) -> bool {
+    let map_only = schema
+        .types
+        .iter()
+        .all(|t| matches!(t, SchemaType::Map | SchemaType::Null));
+
+    !schema.properties.is_empty() && map_only && schema.types != [SchemaType::Null]
+}
+
+// the distinct values of an enum made of nothing but strings
+fn string_enum(schema: &Schema) -> Option<Vec<&str>> {
+    let mut values = Vec::new();
+
+    for value in schema.enum_values.as_ref()? {
+        match &value.value {
+            Value::String { string, .. } if values.contains(&string.as_str()) => {}
+            Value::String { string, .. } => values.push(string.as_str()),
+            _ => return None,
+        }
+    }
+
+    Some(values)
+}
+
+fn words(s: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut previous_lowercase = false;
+
+    for c in s.chars() {
+        if !c.is_ascii_alphanumeric() {
+            if !word.is_empty() {
+                words.push(std::mem::take(&mut word));
+            }
+
+            previous_lowercase = false;
+            continue;
+        }
+
+        // a new word starts at every lowercase to uppercase change, as in camelCase
+        if c.is_ascii_uppercase() && previous_lowercase {
+            words.push(std::mem::take(&mut word));
+        }
+
+        previous_lowercase = c.is_ascii_lowercase() || c.is_ascii_digit();
+        word.push(c);
+    }
+
+    if !word.is_empty() {
+        words.push(word);
+    }
+
+    words
+}
+
+fn pascal_case(s: &str) -> String {
+    words(s)
+        .iter()
+        .map(|w| {
+            let lower = w.to_ascii_lowercase();
+            lower[..1].to_ascii_uppercase() + &lower[1..]
+        })
+        .collect()
+}
+
+fn snake_case(s: &str) -> String {
+    words(s)
+        .iter()
+        .map(|w| w.to_ascii_lowercase())
+        .collect::<Vec<_>>()
+        .join("_")
+}
+
+// if pascal_case gave something that can be used as a type or variant name as it is
+fn is_type_ident(name: &str) -> bool {
+    // `Self` is the only keyword that can come out of pascal_case, and it can't be a raw identifier
+    !name.is_empty() && !name.starts_with(|c: char| c.is_ascii_digit()) && name != "Self"
+}
+
+fn raw_ident(ident: &str) -> String {
+    const KEYWORDS: [&str; 38] = [
+        "as", "async", "await", "break", "const", "continue", "dyn", "else", "enum", "extern",
+        "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut",
+        "pub", "ref", "return", "static", "struct", "trait", "true", "type", "unsafe", "use",
+        "where", "while", "abstract", "become", "box", "try",
+    ];
+
+    match ident {
+        // these can't be raw identifiers
+        "self" | "super" | "crate" => format!("{}_", ident),
+        _ if KEYWORDS.contains(&ident) => format!("r#{}", ident),
+        _ => ident.to_string(),
+    }
+}
+
+/*
+    Helpers for the generated code, each checks one value and says what's wrong with it.
+*/
+
+fn wrong_type(object: &Object, path: &str, expected: SchemaType) -> Violation {
+    Violation {
+        path: path.to_string(),
+        kind: ViolationKind::WrongType {
+            expected: vec![expected],
+            found: object.value.type_name(),
+        },
+    }
+}
+
+pub fn missing_field(path: &str, name: &str) -> Violation {
+    Violation {
+        path: path.to_string(),
+        kind: ViolationKind::MissingField(name.to_string()),
+    }
+}
+
+pub fn not_in_enum(path: &str) -> Violation {
+    Violation {
+        path: path.to_string(),
+        kind: ViolationKind::NotInEnum,
+    }
+}
+
+pub fn is_null(object: &Object) -> bool {
+    matches!(object.value, Value::Null)
+}
+
+pub fn field<'a>(items: &'a [(String, Object)], name: &str) -> Option<&'a Object> {
+    items.iter().find(|(k, _)| k == name).map(|(_, v)| v)
+}
+
+pub fn to_object(object: &Object, _path: &str) -> Result<Object, Violation> {
+    Ok(object.clone())
+}
+
+pub fn to_map<'a>(object: &'a Object, path: &str) -> Result<&'a [(String, Object)], Violation> {
+    match &object.value {
+        Value::Map(items) => Ok(items),
+        _ => Err(wrong_type(object, path, SchemaType::Map)),
+    }
+}
+
+pub fn to_list<'a>(object: &'a Object, path: &str) -> Result<&'a [Object], Violation> {
+    match &object.value {
+        Value::List(elements) => Ok(elements),
+        _ => Err(wrong_type(object, path, SchemaType::List)),
+    }
+}
+
+pub fn to_string(object: &Object, path: &str) -> Result<String, Violation> {
+    match &object.value {
+        Value::String { string, .. } => Ok(string.clone()),
+        _ => Err(wrong_type(object, path, SchemaType::String)),
+    }
+}
+
+pub fn to_sint(object: &Object, path: &str) -> Result<i128, Violation> {
+    match object.value {
+        Value::SInt(i) => Ok(i),
+        _ => Err(wrong_type(object, path, SchemaType::SInt)),
+    }
+}
+
+pub fn to_uint(object: &Object, path: &str) -> Result<u128, Violation> {
+    match object.value {
+        Value::UInt(u) => Ok(u),
+        _ => Err(wrong_type(object, path, SchemaType::UInt)),
+    }
+}
+
+pub fn to_integer(object: &Object, path: &str) -> Result<i128, Violation> {
+    match object.value {
+        Value::SInt(i) => Ok(i),
+        Value::UInt(u) if u <= i128::MAX as u128 => Ok(u as i128),
+        _ => Err(wrong_type(object, path, SchemaType::Integer)),
+    }
+}
+
+pub fn to_float32(object: &Object, path: &str) -> Result<f32, Violation> {
+    match object.value {
+        Value::Float32(f) => Ok(f),
+        _ => Err(wrong_type(object, path, SchemaType::Float32)),
+    }
+}
+
+pub fn to_float64(object: &Object, path: &str) -> Result<f64, Violation> {
+    match object.value {
+        Value::Float32(f) => Ok(f as f64),
+        Value::Float64(f) => Ok(f),
+        _ => Err(wrong_type(object, path, SchemaType::Float64)),
+    }
+}
+
+pub fn to_number(object: &Object, path: &str) -> Result<f64, Violation> {
+    match object.value {
+        Value::SInt(i) => Ok(i as f64),
+        Value::UInt(u) => Ok(u as f64),
+        Value::Float32(f) => Ok(f as f64),
+        Value::Float64(f) => Ok(f),
+        _ => Err(wrong_type(object, path, SchemaType::Number)),
+    }
+}
+
+pub fn to_bool(object: &Object, path: &str) -> Result<bool, Violation> {
+    match object.value {
+        Value::Bool(b) => Ok(b),
+        _ => Err(wrong_type(object, path, SchemaType::Bool)),
+    }
+}
+
+pub fn to_bytes(object: &Object, path: &str) -> Result<Vec<u8>, Violation> {
+    match &object.value {
+        Value::Bytes(bytes) => Ok(bytes.clone()),
+        _ => Err(wrong_type(object, path, SchemaType::Bytes)),
+    }
+}
+
+pub fn to_timestamp32(object: &Object, path: &str) -> Result<u32, Violation> {
+    match object.value {
+        Value::Timestamp32(t) => Ok(t),
+        _ => Err(wrong_type(object, path, SchemaType::Timestamp32)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn generate(schema: serde_json::Value) -> String {
+        generate_rust(&Schema::from_json(&schema).unwrap(), "Message").unwrap()
+    }
+
+    #[test]
+    fn optional_nullable_fields_keep_their_nulls() {
+        let code = generate(json!({
+            "type": "object",
+            "properties": {
+                "a": {"type": ["string", "null"]},
+                "b": {"type": ["string", "null"]},
+                "c": {"type": "string"}
+            },
+            "required": ["b"]
+        }));
+
+        assert!(code.contains("pub a: Option<Option<String>>,"));
+        assert!(code.contains("pub b: Option<String>,"));
+        assert!(code.contains("pub c: Option<String>,"));
+
+        // a Some(None) is written as a null rather than left out
+        assert!(code.contains(
+            "if let Some(v) = value.a {\n            items.push((\"a\".to_string(), match v { Some(x0) => ::mvencode::object::Object::string(x0), None => ::mvencode::object::Object::null() }));"
+        ));
+    }
+
+    #[test]
+    fn every_map_and_string_enum_gets_a_type() {
+        let code = generate(json!({
+            "properties": {
+                "id": {"type": "uint"},
+                "type": {"type": "string", "enum": ["small", "big-one"]},
+                "author": {"properties": {"name": {"type": "string"}}, "required": ["name"]},
+                "tags": {"type": "array", "items": {"type": "string"}},
+                "extra": {}
+            },
+            "required": ["id", "type", "tags"]
+        }));
+
+        for line in [
+            "pub struct Message {",
+            "    pub id: u128,",
+            "    pub r#type: MessageType,",
+            "    pub author: Option<MessageAuthor>,",
+            "    pub tags: Vec<String>,",
+            "    pub extra: Option<::mvencode::object::Object>,",
+            "pub enum MessageType {",
+            "    BigOne,",
+            "            MessageType::BigOne => \"big-one\",",
+            "pub struct MessageAuthor {",
+            "    pub name: String,",
+            "impl TryFrom<&::mvencode::object::Object> for MessageAuthor {",
+            "impl From<MessageAuthor> for ::mvencode::object::Object {",
+        ] {
+            assert!(code.lines().any(|l| l == line), "{:?} in\n{}", line, code);
+        }
+    }
+
+    #[test]
+    fn roots_that_are_not_structs_are_errors() {
+        for schema in [json!({"type": "array"}), json!({"type": "object"})] {
+            let schema = Schema::from_json(&schema).unwrap();
+            assert!(generate_rust(&schema, "Message").is_err());
+        }
+    }
+
+    #[test]
+    fn objects_of_the_wrong_type_are_violations() {
+        let object = Object::list(vec![Object::sint(-1)]);
+
+        assert_eq!(to_list(&object, "").map(|l| l.len()), Ok(1));
+        assert_eq!(to_sint(&Object::sint(-1), "/0"), Ok(-1));
+        assert_eq!(
+            to_map(&object, "/a").unwrap_err(),
+            Violation {
+                path: "/a".to_string(),
+                kind: ViolationKind::WrongType {
+                    expected: vec![SchemaType::Map],
+                    found: "List",
+                },
+            }
+        );
+        assert_eq!(
+            to_uint(&Object::sint(-1), "/0").unwrap_err().kind,
+            ViolationKind::WrongType {
+                expected: vec![SchemaType::UInt],
+                found: "SInt",
+            }
+        );
+        assert_eq!(
+            missing_field("/a", "b"),
+            Violation {
+                path: "/a".to_string(),
+                kind: ViolationKind::MissingField("b".to_string()),
+            }
+        );
+    }
+}
@@ -0,0 +1,620 @@
+use std::fmt::{self, Display, Formatter};
+
+use serde_json::json;
+
+use crate::object::{Object, Value};
+use crate::patch::json_equal;
+use crate::pointer::escape_pointer_token;
+use crate::schema::{Schema, SchemaType};
+
+/*
+    Compares an old schema with a new one:
+
+    backward compatible: readers using the new schema can read everything written with the old one
+    forward compatible: readers still using the old schema can read everything written with the new one
+
+    Map keys a schema doesn't declare are ignored by readers, so dropping an optional field or adding one
+    breaks nothing. Paths are JSON pointers into the data, with "*" standing for every element of a list.
+
+    That only holds for messages with keys. A keyless message (see `Schema::strip_keys`) finds each
+    value by its position among the properties and the presence bitmap, so a change to which
+    properties a map declares, or which of them are required, can break either direction.
+    `check_keyless_compatibility` reports every such change as breaking both. The order properties are declared in doesn't matter to
+    either, keyless values are ordered by name.
+*/
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Backward,
+    Forward,
+    Both,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum IssueKind {
+    // a field the old schema required is gone, old readers will miss it
+    RemovedRequiredField(String),
+    // a new field is required, old data doesn't have it
+    AddedRequiredField(String),
+    FieldBecameRequired(String),
+    FieldBecameOptional(String),
+    // an optional field, which only breaks keyless messages
+    AddedOptionalField(String),
+    RemovedOptionalField(String),
+    // a field that disappeared and one that appeared with exactly the same schema
+    RenamedField {
+        from: String,
+        to: String,
+    },
+    // the new schema accepts fewer types, more types, or just different ones
+    TypeNarrowed {
+        old: Vec<SchemaType>,
+        new: Vec<SchemaType>,
+    },
+    TypeWidened {
+        old: Vec<SchemaType>,
+        new: Vec<SchemaType>,
+    },
+    TypeChanged {
+        old: Vec<SchemaType>,
+        new: Vec<SchemaType>,
+    },
+    MapBecameList,
+    ListBecameMap,
+    // allowed values were removed, added, or both
+    EnumNarrowed,
+    EnumWidened,
+    EnumChanged,
+    // "minimum", "maximum" or "maxLength" got stricter or looser
+    ConstraintNarrowed(&'static str),
+    ConstraintWidened(&'static str),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Issue {
+    pub path: String,
+    pub kind: IssueKind,
+    // which kind of compatibility this breaks
+    pub breaks: Direction,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CompatibilityReport {
+    pub issues: Vec<Issue>,
+}
+
+impl Direction {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Direction::Backward => "backward",
+            Direction::Forward => "forward",
+            Direction::Both => "both",
+        }
+    }
+
+    // whether breaking `self` breaks `other` too
+    pub fn breaks(&self, other: Direction) -> bool {
+        *self == Direction::Both || other == Direction::Both || *self == other
+    }
+
+    fn from_breaks(backward: bool, forward: bool) -> Option<Direction> {
+        match (backward, forward) {
+            (true, true) => Some(Direction::Both),
+            (true, false) => Some(Direction::Backward),
+            (false, true) => Some(Direction::Forward),
+            (false, false) => None,
+        }
+    }
+}
+
+impl IssueKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            IssueKind::RemovedRequiredField(_) => "removed_required_field",
+            IssueKind::AddedRequiredField(_) => "added_required_field",
+            IssueKind::FieldBecameRequired(_) => "field_became_required",
+            IssueKind::FieldBecameOptional(_) => "field_became_optional",
+            IssueKind::AddedOptionalField(_) => "added_optional_field",
+            IssueKind::RemovedOptionalField(_) => "removed_optional_field",
+            IssueKind::RenamedField { .. } => "renamed_field",
+            IssueKind::TypeNarrowed { .. } => "type_narrowed",
+            IssueKind::TypeWidened { .. } => "type_widened",
+            IssueKind::TypeChanged { .. } => "type_changed",
+            IssueKind::MapBecameList => "map_became_list",
+            IssueKind::ListBecameMap => "list_became_map",
+            IssueKind::EnumNarrowed => "enum_narrowed",
+            IssueKind::EnumWidened => "enum_widened",
+            IssueKind::EnumChanged => "enum_changed",
+            IssueKind::ConstraintNarrowed(_) => "constraint_narrowed",
+            IssueKind::ConstraintWidened(_) => "constraint_widened",
+        }
+    }
+}
+
+impl Display for IssueKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let types = |types: &[SchemaType]| match types {
+            [] => "anything".to_string(),
+            types => types
+                .iter()
+                .map(|t| t.name())
+                .collect::<Vec<_>>()
+                .join(" or "),
+        };
+
+        match self {
+            IssueKind::RemovedRequiredField(name) => {
+                write!(f, "required field {:?} was removed", name)
+            }
+            IssueKind::AddedRequiredField(name) => {
+                write!(f, "required field {:?} was added", name)
+            }
+            IssueKind::FieldBecameRequired(name) => {
+                write!(f, "field {:?} became required", name)
+            }
+            IssueKind::FieldBecameOptional(name) => {
+                write!(f, "field {:?} became optional", name)
+            }
+            IssueKind::AddedOptionalField(name) => {
+                write!(f, "optional field {:?} was added", name)
+            }
+            IssueKind::RemovedOptionalField(name) => {
+                write!(f, "optional field {:?} was removed", name)
+            }
+            IssueKind::RenamedField { from, to } => {
+                write!(f, "field {:?} was renamed to {:?}", from, to)
+            }
+            IssueKind::TypeNarrowed { old, new } => {
+                write!(f, "type narrowed from {} to {}", types(old), types(new))
+            }
+            IssueKind::TypeWidened { old, new } => {
+                write!(f, "type widened from {} to {}", types(old), types(new))
+            }
+            IssueKind::TypeChanged { old, new } => {
+                write!(f, "type changed from {} to {}", types(old), types(new))
+            }
+            IssueKind::MapBecameList => write!(f, "map became a list"),
+            IssueKind::ListBecameMap => write!(f, "list became a map"),
+            IssueKind::EnumNarrowed => write!(f, "allowed values were removed"),
+            IssueKind::EnumWidened => write!(f, "allowed values were added"),
+            IssueKind::EnumChanged => write!(f, "allowed values were added and removed"),
+            IssueKind::ConstraintNarrowed(keyword) => write!(f, "{} got stricter", keyword),
+            IssueKind::ConstraintWidened(keyword) => write!(f, "{} got looser", keyword),
+        }
+    }
+}
+
+impl Display for Issue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let path = if self.path.is_empty() {
+            "/"
+        } else {
+            &self.path
+        };
+
+        let breaks = match self.breaks {
+            Direction::Both => "backward and forward",
+            direction => direction.name(),
+        };
+
+        write!(
+            f,
+            "{}: {} (breaks {} compatibility)",
+            path, self.kind, breaks
+        )
+    }
+}
+
+impl CompatibilityReport {
+    pub fn is_backward_compatible(&self) -> bool {
+        self.is_compatible(Direction::Backward)
+    }
+
+    pub fn is_forward_compatible(&self) -> bool {
+        self.is_compatible(Direction::Forward)
+    }
+
+    // with `Direction::Both`, whether it's both backward and forward compatible
+    pub fn is_compatible(&self, direction: Direction) -> bool {
+        !self
+            .issues
+            .iter()
+            .any(|issue| issue.breaks.breaks(direction))
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let issues: Vec<serde_json::Value> = self
+            .issues
+            .iter()
+            .map(|issue| {
+                let mut json = json!({
+                    "path": issue.path,
+                    "kind": issue.kind.name(),
+                    "breaks": issue.breaks.name(),
+                    "message": issue.kind.to_string(),
+                });
+
+                let type_names =
+                    |types: &[SchemaType]| types.iter().map(|t| t.name()).collect::<Vec<_>>();
+
+                match &issue.kind {
+                    IssueKind::RemovedRequiredField(name)
+                    | IssueKind::AddedRequiredField(name)
+                    | IssueKind::FieldBecameRequired(name)
+                    | IssueKind::FieldBecameOptional(name)
+                    | IssueKind::AddedOptionalField(name)
+                    | IssueKind::RemovedOptionalField(name) => json["field"] = json!(name),
+                    IssueKind::RenamedField { from, to } => {
+                        json["from"] = json!(from);
+                        json["to"] = json!(to);
+                    }
+                    IssueKind::TypeNarrowed { old, new }
+                    | IssueKind::TypeWidened { old, new }
+                    | IssueKind::TypeChanged { old, new } => {
+                        json["old"] = json!(type_names(old));
+                        json["new"] = json!(type_names(new));
+                    }
+                    IssueKind::ConstraintNarrowed(keyword)
+                    | IssueKind::ConstraintWidened(keyword) => json["keyword"] = json!(keyword),
+                    _ => {}
+                }
+
+                json
+            })
+            .collect();
+
+        json!({
+            "backward_compatible": self.is_backward_compatible(),
+            "forward_compatible": self.is_forward_compatible(),
+            "issues": issues,
+        })
+    }
+}
+
+pub fn check_compatibility(old: &Schema, new: &Schema) -> CompatibilityReport {
+    let mut report = CompatibilityReport::default();
+    compare(old, new, String::new(), false, &mut report.issues);
+    report
+}
+
+// the same as `check_compatibility`, for keyless messages written with either schema
+pub fn check_keyless_compatibility(old: &Schema, new: &Schema) -> CompatibilityReport {
+    let mut report = CompatibilityReport::default();
+    compare(old, new, String::new(), true, &mut report.issues);
+    report
+}
+
+fn compare(old: &Schema, new: &Schema, path: String, keyless: bool, issues: &mut Vec<Issue>) {
+    let mut issue = |kind, breaks| {
+        issues.push(Issue {
+            path: path.clone(),
+            kind,
+            breaks,
+        })
+    };
+
+    let old_accepts = accepted(&old.types);
+    let new_accepts = accepted(&new.types);
+
+    let only_map =
+        |accepts: &[&'static str]| accepts.contains(&"Map") && !accepts.contains(&"List");
+    let only_list =
+        |accepts: &[&'static str]| accepts.contains(&"List") && !accepts.contains(&"Map");
+
+    if only_map(&old_accepts) && only_list(&new_accepts) {
+        issue(IssueKind::MapBecameList, Direction::Both);
+        return;
+    }
+
+    if only_list(&old_accepts) && only_map(&new_accepts) {
+        issue(IssueKind::ListBecameMap, Direction::Both);
+        return;
+    }
+
+    let narrowed = old_accepts.iter().any(|t| !new_accepts.contains(t));
+    let widened = new_accepts.iter().any(|t| !old_accepts.contains(t));
+
+    let (old_types, new_types) = (old.types.clone(), new.types.clone());
+
+    match (narrowed, widened) {
+        (true, true) => issue(
+            IssueKind::TypeChanged {
+                old: old_types,
+                new: new_types,
+            },
+            Direction::Both,
+        ),
+        (true, false) => issue(
+            IssueKind::TypeNarrowed {
+                old: old_types,
+                new: new_types,
+            },
+            Direction::Backward,
+        ),
+        (false, true) => issue(
+            IssueKind::TypeWidened {
+                old: old_types,
+                new: new_types,
+            },
+            Direction::Forward,
+        ),
+        (false, false) => {}
+    }
+
+    // values the other side allows but this one doesn't
+    let excluded = |this: &Option<Vec<Object>>, other: &Option<Vec<Object>>| match (this, other) {
+        (None, _) => false,
+        (Some(_), None) => true,
+        (Some(this), Some(other)) => other.iter().any(|o| !this.iter().any(|t| json_equal(t, o))),
+    };
+
+    let enum_narrowed = excluded(&new.enum_values, &old.enum_values);
+    let enum_widened = excluded(&old.enum_values, &new.enum_values);
+
+    if let Some(breaks) = Direction::from_breaks(enum_narrowed, enum_widened) {
+        let kind = match breaks {
+            Direction::Both => IssueKind::EnumChanged,
+            Direction::Backward => IssueKind::EnumNarrowed,
+            Direction::Forward => IssueKind::EnumWidened,
+        };
+
+        issue(kind, breaks);
+    }
+
+    // a lower bound is stricter when it's higher, an upper bound when it's lower
+    let bounds = [
+        ("minimum", old.minimum, new.minimum, false),
+        ("maximum", old.maximum, new.maximum, true),
+        (
+            "maxLength",
+            old.max_length.map(|l| l as f64),
+            new.max_length.map(|l| l as f64),
+            true,
+        ),
+    ];
+
+    for (keyword, old_bound, new_bound, upper) in bounds {
+        let stricter = |a: Option<f64>, b: Option<f64>| match (a, b) {
+            (_, None) => false,
+            (None, Some(_)) => true,
+            (Some(a), Some(b)) => (upper && b < a) || (!upper && b > a),
+        };
+
+        if stricter(old_bound, new_bound) {
+            issue(IssueKind::ConstraintNarrowed(keyword), Direction::Backward);
+        } else if stricter(new_bound, old_bound) {
+            issue(IssueKind::ConstraintWidened(keyword), Direction::Forward);
+        }
+    }
+
+    if old_accepts.contains(&"Map") && new_accepts.contains(&"Map") {
+        compare_properties(old, new, &path, keyless, issues);
+    }
+
+    let has_items = old.items.is_some() || new.items.is_some();
+
+    if old_accepts.contains(&"List") && new_accepts.contains(&"List") && has_items {
+        let any = Schema::default();
+
+        compare(
+            old.items.as_deref().unwrap_or(&any),
+            new.items.as_deref().unwrap_or(&any),
+            format!("{}/*", path),
+            keyless,
+            issues,
+        );
+    }
+}
+
+fn compare_properties(
+    old: &Schema,
+    new: &Schema,
+    path: &str,
+    keyless: bool,
+    issues: &mut Vec<Issue>,
+) {
+    let field_path = |name: &str| format!("{}/{}", path, escape_pointer_token(name));
+
+    // in a keyless message, any change to the fields moves the values after it
+    let breaks = |direction| match keyless {
+        true => Direction::Both,
+        false => direction,
+    };
+
+    let mut removed: Vec<&(String, Schema)> = old
+        .properties
+        .iter()
+        .filter(|(name, _)| new.property(name).is_none())
+        .collect();
+
+    let mut added: Vec<&(String, Schema)> = new
+        .properties
+        .iter()
+        .filter(|(name, _)| old.property(name).is_none())
+        .collect();
+
+    // a field that went away and one that showed up with the same schema are most likely the same field
+    let mut renamed = Vec::new();
+
+    for (from, schema) in removed.clone() {
+        let candidates: Vec<&String> = added
+            .iter()
+            .filter(|(_, s)| s == schema)
+            .map(|(name, _)| name)
+            .collect();
+
+        let claimed = removed.iter().filter(|(_, s)| s == schema).count();
+
+        if let ([to], 1) = (candidates.as_slice(), claimed) {
+            renamed.push((from.clone(), (*to).clone()));
+        }
+    }
+
+    for (from, to) in &renamed {
+        removed.retain(|(name, _)| name != from);
+        added.retain(|(name, _)| name != to);
+
+        issues.push(Issue {
+            path: field_path(from),
+            kind: IssueKind::RenamedField {
+                from: from.clone(),
+                to: to.clone(),
+            },
+            breaks: Direction::Both,
+        });
+    }
+
+    for (name, _) in removed {
+        if old.is_required(name) {
+            issues.push(Issue {
+                path: path.to_string(),
+                kind: IssueKind::RemovedRequiredField(name.clone()),
+                breaks: breaks(Direction::Forward),
+            });
+        } else if keyless {
+            issues.push(Issue {
+                path: path.to_string(),
+                kind: IssueKind::RemovedOptionalField(name.clone()),
+                breaks: Direction::Both,
+            });
+        }
+    }
+
+    for (name, _) in added {
+        if new.is_required(name) {
+            issues.push(Issue {
+                path: path.to_string(),
+                kind: IssueKind::AddedRequiredField(name.clone()),
+                breaks: breaks(Direction::Backward),
+            });
+        } else if keyless {
+            issues.push(Issue {
+                path: path.to_string(),
+                kind: IssueKind::AddedOptionalField(name.clone()),
+                breaks: Direction::Both,
+            });
+        }
+    }
+
+    for (name, old_property) in &old.properties {
+        let Some(new_property) = new.property(name) else {
+            continue;
+        };
+
+        match (old.is_required(name), new.is_required(name)) {
+            (false, true) => issues.push(Issue {
+                path: path.to_string(),
+                kind: IssueKind::FieldBecameRequired(name.clone()),
+                breaks: breaks(Direction::Backward),
+            }),
+            (true, false) => issues.push(Issue {
+                path: path.to_string(),
+                kind: IssueKind::FieldBecameOptional(name.clone()),
+                breaks: breaks(Direction::Forward),
+            }),
+            _ => {}
+        }
+
+        compare(
+            old_property,
+            new_property,
+            field_path(name),
+            keyless,
+            issues,
+        );
+    }
+}
+
+// the `Value::type_name`s of every kind of value `types` accepts
+fn accepted(types: &[SchemaType]) -> Vec<&'static str> {
+    let samples = [
+        Value::Null,
+        Value::Bool(false),
+        Value::String {
+            string: String::new(),
+            encode_class: true,
+        },
+        Value::Bytes(Vec::new()),
+        Value::Map(Vec::new()),
+        Value::List(Vec::new()),
+        Value::SInt(0),
+        Value::UInt(0),
+        Value::Float32(0.0),
+        Value::Float64(0.0),
+        Value::Timestamp32(0),
+        Value::UserDefined {
+            id: 0,
+            data: Vec::new(),
+        },
+    ];
+
+    samples
+        .iter()
+        .filter(|value| types.is_empty() || types.iter().any(|t| t.matches(value)))
+        .map(|value| value.type_name())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schema(json: serde_json::Value) -> Schema {
+        Schema::from_json(&json).unwrap()
+    }
+
+    fn kinds(report: &CompatibilityReport) -> Vec<(&str, Direction)> {
+        report
+            .issues
+            .iter()
+            .map(|issue| (issue.kind.name(), issue.breaks))
+            .collect()
+    }
+
+    #[test]
+    fn field_changes_only_break_keyed_messages_when_required() {
+        let old = schema(json!({
+            "properties": {"a": {}, "b": {}, "c": {}, "d": {}},
+            "required": ["a", "b"]
+        }));
+        let new = schema(json!({
+            "properties": {"e": {}, "d": {}, "b": {}, "a": {}, "f": {}},
+            "required": ["a", "d", "f"]
+        }));
+
+        assert_eq!(
+            kinds(&check_compatibility(&old, &new)),
+            vec![
+                ("added_required_field", Direction::Backward),
+                ("field_became_optional", Direction::Forward),
+                ("field_became_required", Direction::Backward),
+            ]
+        );
+
+        assert_eq!(
+            kinds(&check_keyless_compatibility(&old, &new)),
+            vec![
+                ("removed_optional_field", Direction::Both),
+                ("added_optional_field", Direction::Both),
+                ("added_required_field", Direction::Both),
+                ("field_became_optional", Direction::Both),
+                ("field_became_required", Direction::Both),
+            ]
+        );
+    }
+
+    #[test]
+    fn declaration_order_breaks_nothing() {
+        let old = schema(json!({
+            "properties": {"a": {"properties": {"x": {}, "y": {}}}, "b": {}},
+            "required": ["a"]
+        }));
+        let new = schema(json!({
+            "properties": {"b": {}, "a": {"properties": {"y": {}, "x": {}}}},
+            "required": ["a"]
+        }));
+
+        assert!(check_compatibility(&old, &new).issues.is_empty());
+        assert!(check_keyless_compatibility(&old, &new).issues.is_empty());
+    }
+}
@@ -0,0 +1,471 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use memmap2::Mmap;
+
+use crate::decode::headpack_try_decode;
+use crate::encode::{headpack_try_encode, read_varint, write_varint};
+use crate::object::Object;
+
+/*
+    A container holds many messages and can find any of them without reading the others:
+
+    file   = CONTAINER_MAGIC version(1 byte) batch*
+    batch  = message* index footer
+    index  = entry*
+    entry  = offset(varint) length(varint) key_length(varint) key
+    footer = index_offset(8 bytes) previous_footer(8 bytes) total_count(8 bytes) FOOTER_MAGIC
+
+    Offsets are from the start of the file and all fixed-size numbers are big endian.
+    key_length is 0 for messages without a key, otherwise it is the length of the key plus one.
+
+    Appending a batch never touches the ones before it: the new footer points back to the previous one
+    (previous_footer is 0 for the first batch), and total_count includes the messages of all earlier batches.
+*/
+
+pub const CONTAINER_MAGIC: [u8; 4] = [0x89, b'H', b'P', b'C'];
+
+pub const FOOTER_MAGIC: [u8; 4] = [0x89, b'H', b'P', b'F'];
+
+pub const CONTAINER_VERSION: u8 = 1;
+
+const HEADER_LENGTH: u64 = CONTAINER_MAGIC.len() as u64 + 1;
+
+const FOOTER_LENGTH: u64 = 8 * 3 + FOOTER_MAGIC.len() as u64;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub offset: u64,
+    pub length: u64,
+    pub key: Option<String>,
+}
+
+struct Footer {
+    index_offset: u64,
+    previous_footer: u64,
+    total_count: u64,
+}
+
+pub struct ContainerWriter<W: Write + Seek> {
+    inner: W,
+    // entries of the batch being written
+    entries: Vec<Entry>,
+    // offset of the last footer already in the file, 0 if there is none
+    previous_footer: u64,
+    // number of messages in earlier batches
+    previous_count: u64,
+    position: u64,
+}
+
+impl<W: Write + Seek> ContainerWriter<W> {
+    // start a new container at the current position of `inner`, which should be the start of the file
+    pub fn new(mut inner: W) -> io::Result<Self> {
+        inner.write_all(&CONTAINER_MAGIC)?;
+        inner.write_all(&[CONTAINER_VERSION])?;
+
+        let position = inner.stream_position()?;
+
+        Ok(ContainerWriter {
+            inner,
+            entries: Vec::new(),
+            previous_footer: 0,
+            previous_count: 0,
+            position,
+        })
+    }
+
+    // add a message, returning its position in the container
+    pub fn write(&mut self, object: Object, key: Option<&str>) -> io::Result<usize> {
+        self.write_encoded(&headpack_try_encode(object)?, key)
+    }
+
+    // add a message that is already encoded, returning its position in the container
+    pub fn write_encoded(&mut self, message: &[u8], key: Option<&str>) -> io::Result<usize> {
+        self.inner.write_all(message)?;
+
+        self.entries.push(Entry {
+            offset: self.position,
+            length: message.len() as u64,
+            key: key.map(str::to_string),
+        });
+
+        self.position += message.len() as u64;
+
+        Ok(self.previous_count as usize + self.entries.len() - 1)
+    }
+
+    // write the index and footer of this batch and return the underlying writer
+    pub fn finish(mut self) -> io::Result<W> {
+        let mut index = Vec::new();
+
+        for entry in &self.entries {
+            write_varint(entry.offset as u128, &mut index);
+            write_varint(entry.length as u128, &mut index);
+
+            match &entry.key {
+                Some(key) => {
+                    write_varint(key.len() as u128 + 1, &mut index);
+                    index.extend_from_slice(key.as_bytes());
+                }
+                None => write_varint(0, &mut index),
+            }
+        }
+
+        let total_count = self.previous_count + self.entries.len() as u64;
+
+        self.inner.write_all(&index)?;
+        self.inner.write_all(&self.position.to_be_bytes())?;
+        self.inner.write_all(&self.previous_footer.to_be_bytes())?;
+        self.inner.write_all(&total_count.to_be_bytes())?;
+        self.inner.write_all(&FOOTER_MAGIC)?;
+        self.inner.flush()?;
+
+        Ok(self.inner)
+    }
+}
+
+impl<W: Read + Write + Seek> ContainerWriter<W> {
+    // start a new batch at the end of an existing container
+    pub fn append(mut inner: W) -> io::Result<Self> {
+        let file_length = inner.seek(SeekFrom::End(0))?;
+
+        if file_length < HEADER_LENGTH + FOOTER_LENGTH {
+            return Err(invalid("container is too short"));
+        }
+
+        inner.seek(SeekFrom::Start(0))?;
+        let mut header = [0; HEADER_LENGTH as usize];
+        inner.read_exact(&mut header)?;
+        check_header(&header)?;
+
+        let previous_footer = file_length - FOOTER_LENGTH;
+
+        inner.seek(SeekFrom::Start(previous_footer))?;
+        let mut footer = [0; FOOTER_LENGTH as usize];
+        inner.read_exact(&mut footer)?;
+        let footer = parse_footer(&footer)?;
+
+        Ok(ContainerWriter {
+            inner,
+            entries: Vec::new(),
+            previous_footer,
+            previous_count: footer.total_count,
+            position: file_length,
+        })
+    }
+}
+
+impl ContainerWriter<File> {
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        ContainerWriter::new(File::create(path)?)
+    }
+
+    // append to the container at `path`, creating it if it doesn't exist
+    pub fn open_append(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+
+        if file.seek(SeekFrom::End(0))? == 0 {
+            ContainerWriter::new(file)
+        } else {
+            ContainerWriter::append(file)
+        }
+    }
+}
+
+pub struct ContainerReader<D: AsRef<[u8]>> {
+    data: D,
+    entries: Vec<Entry>,
+    keys: HashMap<String, usize>,
+}
+
+impl ContainerReader<Mmap> {
+    // memory-map the container at `path`
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = File::open(path)?;
+
+        // SAFETY: the file must not be modified while it is mapped, appending to it is fine
+        let data = unsafe { Mmap::map(&file)? };
+
+        ContainerReader::new(data)
+    }
+}
+
+impl<D: AsRef<[u8]>> ContainerReader<D> {
+    pub fn new(data: D) -> io::Result<Self> {
+        let bytes = data.as_ref();
+
+        if (bytes.len() as u64) < HEADER_LENGTH + FOOTER_LENGTH {
+            return Err(invalid("container is too short"));
+        }
+
+        check_header(&bytes[..HEADER_LENGTH as usize])?;
+
+        // walk the batches from the last one back to the first
+        let mut batches = Vec::new();
+        let mut footer_offset = bytes.len() as u64 - FOOTER_LENGTH;
+        let mut expected_count = None;
+
+        loop {
+            let footer = parse_footer(slice(bytes, footer_offset, FOOTER_LENGTH)?)?;
+
+            if footer.index_offset > footer_offset || footer.previous_footer >= footer_offset {
+                return Err(invalid("container footer points past itself"));
+            }
+
+            expected_count.get_or_insert(footer.total_count);
+
+            let index = slice(
+                bytes,
+                footer.index_offset,
+                footer_offset - footer.index_offset,
+            )?;
+            batches.push(parse_index(index, footer.index_offset)?);
+
+            if footer.previous_footer == 0 {
+                break;
+            }
+
+            footer_offset = footer.previous_footer;
+        }
+
+        let entries: Vec<Entry> = batches.into_iter().rev().flatten().collect();
+
+        if expected_count != Some(entries.len() as u64) {
+            return Err(invalid("container index doesn't match its message count"));
+        }
+
+        let mut keys = HashMap::new();
+
+        for (position, entry) in entries.iter().enumerate() {
+            slice(bytes, entry.offset, entry.length)?;
+
+            if let Some(key) = &entry.key {
+                // a key that was written again later refers to the newest message
+                keys.insert(key.clone(), position);
+            }
+        }
+
+        Ok(ContainerReader {
+            data,
+            entries,
+            keys,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    pub fn position_of(&self, key: &str) -> Option<usize> {
+        self.keys.get(key).copied()
+    }
+
+    // the encoded message at `position`
+    pub fn raw(&self, position: usize) -> Option<&[u8]> {
+        let entry = self.entries.get(position)?;
+        let start = entry.offset as usize;
+
+        Some(&self.data.as_ref()[start..start + entry.length as usize])
+    }
+
+    // a message that doesn't decode comes out as an InvalidData error
+    pub fn get(&self, position: usize) -> Option<io::Result<Object>> {
+        let raw = self.raw(position)?;
+
+        Some(
+            headpack_try_decode(VecDeque::from(raw.to_vec()))
+                .map_err(|e| io::Error::new(ErrorKind::InvalidData, e)),
+        )
+    }
+
+    pub fn get_by_key(&self, key: &str) -> Option<io::Result<Object>> {
+        self.get(self.position_of(key)?)
+    }
+}
+
+fn check_header(header: &[u8]) -> io::Result<()> {
+    if header[..CONTAINER_MAGIC.len()] != CONTAINER_MAGIC {
+        return Err(invalid("not a HeadPack container"));
+    }
+
+    let version = header[CONTAINER_MAGIC.len()];
+
+    if version != CONTAINER_VERSION {
+        return Err(invalid(&format!(
+            "unsupported container version {}",
+            version
+        )));
+    }
+
+    Ok(())
+}
+
+fn parse_footer(footer: &[u8]) -> io::Result<Footer> {
+    if footer[24..] != FOOTER_MAGIC {
+        return Err(invalid("container footer is missing"));
+    }
+
+    let number = |i: usize| u64::from_be_bytes(footer[i * 8..(i + 1) * 8].try_into().unwrap());
+
+    Ok(Footer {
+        index_offset: number(0),
+        previous_footer: number(1),
+        total_count: number(2),
+    })
+}
+
+fn parse_index(index: &[u8], index_offset: u64) -> io::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    let mut bytes = index.iter().copied().peekable();
+
+    while bytes.peek().is_some() {
+        let offset = next_number(&mut bytes)?;
+        let length = next_number(&mut bytes)?;
+        let key_length = next_number(&mut bytes)?;
+
+        let key = if key_length == 0 {
+            None
+        } else {
+            let key: Vec<u8> = bytes.by_ref().take(key_length as usize - 1).collect();
+
+            if key.len() as u64 != key_length - 1 {
+                return Err(invalid("container index is corrupt"));
+            }
+
+            Some(String::from_utf8(key).map_err(|_| invalid("container key is not UTF-8"))?)
+        };
+
+        if offset.saturating_add(length) > index_offset {
+            return Err(invalid("container entry points past its batch"));
+        }
+
+        entries.push(Entry {
+            offset,
+            length,
+            key,
+        });
+    }
+
+    Ok(entries)
+}
+
+fn next_number(bytes: &mut impl Iterator<Item = u8>) -> io::Result<u64> {
+    read_varint(bytes)
+        .and_then(|n| u64::try_from(n).ok())
+        .ok_or_else(|| invalid("container index is corrupt"))
+}
+
+fn slice(bytes: &[u8], offset: u64, length: u64) -> io::Result<&[u8]> {
+    let end = offset
+        .checked_add(length)
+        .filter(|end| *end <= bytes.len() as u64)
+        .ok_or_else(|| invalid("container is truncated"))?;
+
+    Ok(&bytes[offset as usize..end as usize])
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn message(n: i128) -> Object {
+        Object::list(vec![Object::sint(n)])
+    }
+
+    // two batches, the second one writing key "b" again
+    fn container() -> Vec<u8> {
+        let mut writer = ContainerWriter::new(Cursor::new(Vec::new())).unwrap();
+        writer.write(message(0), Some("a")).unwrap();
+        writer.write(message(1), None).unwrap();
+        writer.write(message(2), Some("b")).unwrap();
+
+        let mut writer = ContainerWriter::append(writer.finish().unwrap()).unwrap();
+        assert_eq!(writer.write(message(3), Some("b")).unwrap(), 3);
+
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn containers_round_trip() {
+        let reader = ContainerReader::new(container()).unwrap();
+
+        assert_eq!(reader.len(), 4);
+
+        for n in 0..4 {
+            assert_eq!(reader.get(n as usize).unwrap().unwrap(), message(n));
+        }
+
+        assert!(reader.get(4).is_none());
+        assert_eq!(reader.get_by_key("a").unwrap().unwrap(), message(0));
+        assert_eq!(reader.get_by_key("b").unwrap().unwrap(), message(3));
+        assert!(reader.get_by_key("c").is_none());
+    }
+
+    #[test]
+    fn malformed_containers_are_errors() {
+        let container = container();
+
+        let with = |i: usize, byte: u8| {
+            let mut container = container.clone();
+            container[i] = byte;
+            container
+        };
+
+        let cases = [
+            (Vec::new(), "container is too short"),
+            (with(0, 0), "not a HeadPack container"),
+            (with(4, 2), "unsupported container version 2"),
+            (with(container.len() - 1, 0), "container footer is missing"),
+            (
+                container[..container.len() - 1].to_vec(),
+                "container footer is missing",
+            ),
+            // the count in the last footer
+            (
+                with(container.len() - 5, 9),
+                "container index doesn't match its message count",
+            ),
+        ];
+
+        for (data, message) in cases {
+            let e = ContainerReader::new(data).err().unwrap();
+            assert_eq!(e.kind(), ErrorKind::InvalidData);
+            assert_eq!(e.to_string(), message);
+        }
+
+        // no corrupted byte makes the reader panic, and a cut off file is an error
+        for i in 0..container.len() {
+            for byte in [0x00, 0x01, 0x7f, 0x80, 0xff] {
+                if let Ok(reader) = ContainerReader::new(with(i, byte)) {
+                    (0..reader.len()).for_each(|n| drop(reader.get(n)));
+                }
+            }
+
+            // except where the first batch ends, which leaves a container with just that batch
+            if let Ok(reader) = ContainerReader::new(&container[..i]) {
+                assert_eq!(reader.len(), 3);
+            }
+        }
+    }
+}
@@ -1,38 +1,230 @@
+use std::fmt::{self, Display, Formatter};
+
 use base64::Engine;
+use serde::Deserialize;
 use serde_json::{json, Number};
+use toml::value::{Date, Datetime, Offset, Time};
 
 use crate::object;
-use crate::object::Object;
+use crate::object::{Object, FIRST_USER_DEFINED_ID, LAST_USER_DEFINED_ID};
+use crate::pointer::escape_pointer_token;
+
+/*
+    YAML and TOML both keep the order keys were written in. YAML keys that are numbers or bools
+    become strings, tags are dropped, and bytes and timestamps are written the way `into_json`
+    writes them, since serde_yaml_ng can't tell a quoted string from a timestamp or keep a !!binary
+    tag.
+
+    TOML datetimes with a UTC offset and whole seconds become Timestamp32 when they fit, and
+    Timestamp32 is written back as a UTC datetime. Anything else (local dates and times, fractions
+    of a second, or dates before 1970 or after 2106) stays a string. TOML has no null, so writing
+    one is an error, and the root has to be a Map.
+*/
+
+/*
+    Extended JSON tags the values plain JSON can't tell apart, so that a message converted to it and
+    back encodes to the same bytes. Everything `from_json` would read back as the same type is
+    written as plain JSON, and the rest becomes a map with a single tag:
+
+    {"$bytes": "<base64>"}                     Bytes
+    {"$ts": 1700000000}                        Timestamp32
+    {"$i128": "-170141183460469231731687"}     SInt outside the i64 range
+    {"$u128": "5"}                             UInt that plain JSON would read as an SInt, or beyond u64
+    {"$f32": "NaN"}                            Float32 that isn't finite
+    {"$f64": 1.5}                              Float64 that fits in a Float32, or isn't finite
+    {"$user": {"id": 39, "data": "<base64>"}}  UserDefined
+    {"$map": {...}}                            a map that has a single key starting with $
+
+    Maps with the same key twice can't be written, only the last value is kept. A `$user` id has to
+    be 39 to 63 with exactly that many bytes of data, since that's what the decoder reads for it.
+*/
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConvertError {
+    // the text isn't valid YAML or TOML, or serializing it failed
+    Syntax(String),
+    // a YAML key that's a null, a list or a map
+    UnsupportedKey {
+        path: String,
+    },
+    // a type that has nothing to stand for it in the format
+    UnsupportedType {
+        path: String,
+        found: &'static str,
+        format: &'static str,
+    },
+    // TOML documents have to be a table
+    NotATable {
+        found: &'static str,
+    },
+    // an extended JSON tag with a value that doesn't fit it
+    InvalidTag {
+        path: String,
+        tag: String,
+    },
+}
+
+impl Display for ConvertError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let at = |path: &str| match path.is_empty() {
+            true => "at the root".to_string(),
+            false => format!("at {}", path),
+        };
+
+        match self {
+            ConvertError::Syntax(message) => write!(f, "{}", message),
+            ConvertError::UnsupportedKey { path } => {
+                write!(f, "map key {} isn't a string, number or bool", at(path))
+            }
+            ConvertError::UnsupportedType {
+                path,
+                found,
+                format,
+            } => write!(f, "{} {} can't be written as {}", found, at(path), format),
+            ConvertError::NotATable { found } => write!(
+                f,
+                "can't write a {} as a TOML document, it has to be a Map",
+                found
+            ),
+            ConvertError::InvalidTag { path, tag } => {
+                write!(f, "invalid value for {} {}", tag, at(path))
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConvertError {}
+
+// every document in a YAML stream, separated by ---
+pub fn read_yaml_documents(text: &str) -> Result<Vec<Object>, ConvertError> {
+    let mut objects = Vec::new();
+
+    for document in serde_yaml_ng::Deserializer::from_str(text) {
+        objects.push(from_yaml_document(serde_yaml_ng::Value::deserialize(
+            document,
+        ))?);
+    }
+
+    Ok(objects)
+}
+
+// which floats to store as Float32 instead of Float64
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FloatNarrowing {
+    // only the ones that fit without losing anything, which is what `from_json` does
+    #[default]
+    Lossless,
+    // none of them
+    Never,
+    // all of them, rounding the ones that don't fit
+    Always,
+}
+
+// how `from_json` picks HeadPack types for JSON values, the default is what `from_json` does
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FromJsonOptions {
+    // non-negative integers become UInt instead of SInt
+    pub prefer_uint: bool,
+    pub floats: FloatNarrowing,
+    // floats without a fractional part become integers when they fit in 64 bits
+    pub integral_floats_as_ints: bool,
+    // strings that are valid padded base64 become Bytes
+    pub base64_as_bytes: bool,
+    // RFC 3339 date-times with whole seconds become Timestamp32 when they fit
+    pub rfc3339_as_timestamps: bool,
+    // map entries whose value is null are left out
+    pub drop_nulls: bool,
+}
 
 impl Object {
+    // re-pick the width of every float in the object
+    pub fn narrow_floats(&mut self, narrowing: FloatNarrowing) {
+        match self.value {
+            object::Value::Float32(f) if narrowing == FloatNarrowing::Never => {
+                *self = Object::float64(f as f64)
+            }
+            object::Value::Float64(f) => {
+                let fits = f == f as f32 as f64;
+
+                if narrowing == FloatNarrowing::Always
+                    || (narrowing == FloatNarrowing::Lossless && fits)
+                {
+                    *self = Object::float32(f as f32);
+                }
+            }
+            object::Value::Map(ref mut items) => {
+                for (_, value) in items {
+                    value.narrow_floats(narrowing);
+                }
+            }
+            object::Value::List(ref mut elements) => {
+                for element in elements {
+                    element.narrow_floats(narrowing);
+                }
+            }
+            _ => {}
+        }
+    }
+
     pub fn from_json(json: serde_json::Value) -> Self {
+        Self::from_json_with_options(json, &FromJsonOptions::default())
+    }
+
+    pub fn from_json_with_options(json: serde_json::Value, options: &FromJsonOptions) -> Self {
+        let integer = |i: i128| match options.prefer_uint && i >= 0 {
+            true => Self::uint(i as u128),
+            false => Self::sint(i),
+        };
+
         match json {
             serde_json::Value::Null => Self::null(),
             serde_json::Value::Bool(b) => Self::bool(b),
             serde_json::Value::Number(n) => {
                 if n.is_i64() {
-                    Self::sint(n.as_i64().unwrap() as i128)
+                    integer(n.as_i64().unwrap() as i128)
                 } else if n.is_u64() {
                     Self::uint(n.as_u64().unwrap() as u128)
                 } else if n.is_f64() {
                     let double = n.as_f64().unwrap();
 
-                    // check if f64 can be represented as a f32
-                    if double == double as f32 as f64 {
-                        Self::float32(double as f32)
-                    } else {
-                        Self::float64(double)
+                    // 2^64 is the first float past u64::MAX
+                    if options.integral_floats_as_ints
+                        && double.fract() == 0.0
+                        && double >= i64::MIN as f64
+                        && double < u64::MAX as f64
+                    {
+                        return integer(double as i128);
                     }
+
+                    let mut float = Self::float64(double);
+                    float.narrow_floats(options.floats);
+                    float
                 } else {
                     unreachable!()
                 }
             }
-            serde_json::Value::String(s) => Self::string(s),
+            serde_json::Value::String(s) => {
+                if options.rfc3339_as_timestamps {
+                    let timestamp = s.parse().ok().as_ref().and_then(datetime_to_timestamp);
+
+                    if let Some(t) = timestamp {
+                        return Self::timestamp32(t);
+                    }
+                }
+
+                if options.base64_as_bytes && !s.is_empty() {
+                    if let Ok(b) = base64::engine::general_purpose::STANDARD.decode(&s) {
+                        return Self::bytes(b);
+                    }
+                }
+
+                Self::string(s)
+            }
             serde_json::Value::Array(elements) => {
                 let mut array = Vec::with_capacity(elements.len());
 
                 for element in elements {
-                    array.push(Self::from_json(element));
+                    array.push(Self::from_json_with_options(element, options));
                 }
 
                 Object::list(array)
@@ -41,7 +233,11 @@ impl Object {
                 let mut pairs = Vec::with_capacity(map.len());
 
                 for (key, value) in map {
-                    pairs.push((key, Self::from_json(value)));
+                    if options.drop_nulls && value.is_null() {
+                        continue;
+                    }
+
+                    pairs.push((key, Self::from_json_with_options(value, options)));
                 }
 
                 Object::map(pairs)
@@ -51,7 +247,10 @@ impl Object {
 
     pub fn into_json(self) -> serde_json::Value {
         match self.value {
-            object::Value::String{ string, encode_class: _ } => serde_json::Value::String(string),
+            object::Value::String {
+                string,
+                encode_class: _,
+            } => serde_json::Value::String(string),
             object::Value::Bytes(b) => {
                 serde_json::Value::String(base64::engine::general_purpose::STANDARD.encode(&b))
             }
@@ -64,7 +263,7 @@ impl Object {
 
                 serde_json::Value::Object(json_map)
             }
-            object::Value::List(l) => {
+            object::Value::List(l) | object::Value::IntSeq { elements: l, .. } => {
                 let mut json_list = Vec::with_capacity(l.len());
 
                 for element in l {
@@ -106,6 +305,7 @@ impl Object {
             }
             object::Value::Null => serde_json::Value::Null,
             object::Value::Timestamp32(t) => serde_json::Value::Number(t.into()),
+            object::Value::Run(_) => unreachable!("runs only exist inside encoded messages"),
             object::Value::UserDefined { id, data } => {
                 json!({
                     "id": id,
@@ -114,4 +314,747 @@ impl Object {
             }
         }
     }
+
+    pub fn into_extended_json(self) -> serde_json::Value {
+        let tagged = |tag: &str, value: serde_json::Value| json!({ tag: value });
+        let special = |f: f64| match f {
+            f64::INFINITY => "Infinity",
+            f64::NEG_INFINITY => "-Infinity",
+            _ => "NaN",
+        };
+
+        match self.value {
+            object::Value::Bytes(b) => tagged(
+                "$bytes",
+                base64::engine::general_purpose::STANDARD.encode(b).into(),
+            ),
+            object::Value::Timestamp32(t) => tagged("$ts", t.into()),
+            object::Value::SInt(i) => match i64::try_from(i) {
+                Ok(i) => i.into(),
+                Err(_) => tagged("$i128", i.to_string().into()),
+            },
+            object::Value::UInt(u) if u <= i64::MAX as u128 || u > u64::MAX as u128 => {
+                tagged("$u128", u.to_string().into())
+            }
+            object::Value::Float32(f) if !f.is_finite() => tagged("$f32", special(f as f64).into()),
+            object::Value::Float64(f) if !f.is_finite() => tagged("$f64", special(f).into()),
+            object::Value::Float64(f) if f == f as f32 as f64 => tagged("$f64", f.into()),
+            object::Value::UserDefined { id, data } => tagged(
+                "$user",
+                json!({
+                    "id": id,
+                    "data": base64::engine::general_purpose::STANDARD.encode(data),
+                }),
+            ),
+            object::Value::Map(m) => {
+                let needs_wrapping = m.len() == 1 && m[0].0.starts_with('$');
+                let mut json_map = serde_json::Map::with_capacity(m.len());
+
+                for (key, value) in m {
+                    json_map.insert(key, value.into_extended_json());
+                }
+
+                match needs_wrapping {
+                    true => tagged("$map", serde_json::Value::Object(json_map)),
+                    false => serde_json::Value::Object(json_map),
+                }
+            }
+            object::Value::List(l) | object::Value::IntSeq { elements: l, .. } => {
+                serde_json::Value::Array(l.into_iter().map(Self::into_extended_json).collect())
+            }
+            // the rest reads back the same from plain JSON
+            value => Object {
+                length: self.length,
+                value,
+            }
+            .into_json(),
+        }
+    }
+
+    // the inverse of `into_extended_json`, maps with a single unknown $ key are read as maps
+    pub fn from_extended_json(json: serde_json::Value) -> Result<Self, ConvertError> {
+        from_extended_json_value(json, String::new())
+    }
+
+    pub fn from_yaml(text: &str) -> Result<Self, ConvertError> {
+        from_yaml_document(serde_yaml_ng::from_str(text))
+    }
+
+    pub fn to_yaml(&self) -> Result<String, ConvertError> {
+        serde_yaml_ng::to_string(&to_yaml_value(self))
+            .map_err(|e| ConvertError::Syntax(e.to_string()))
+    }
+
+    pub fn from_toml(text: &str) -> Result<Self, ConvertError> {
+        let table: toml::Table =
+            toml::from_str(text).map_err(|e| ConvertError::Syntax(e.to_string()))?;
+
+        Ok(from_toml_value(toml::Value::Table(table)))
+    }
+
+    pub fn to_toml(&self) -> Result<String, ConvertError> {
+        let toml::Value::Table(table) = to_toml_value(self, String::new())? else {
+            return Err(ConvertError::NotATable {
+                found: self.value.type_name(),
+            });
+        };
+
+        toml::to_string(&table).map_err(|e| ConvertError::Syntax(e.to_string()))
+    }
+}
+
+fn from_extended_json_value(json: serde_json::Value, path: String) -> Result<Object, ConvertError> {
+    let map = match json {
+        serde_json::Value::Array(elements) => {
+            let mut list = Vec::with_capacity(elements.len());
+
+            for (i, element) in elements.into_iter().enumerate() {
+                list.push(from_extended_json_value(
+                    element,
+                    format!("{}/{}", path, i),
+                )?);
+            }
+
+            return Ok(Object::list(list));
+        }
+        serde_json::Value::Object(map) => map,
+        json => return Ok(Object::from_json(json)),
+    };
+
+    let read_map = |map: serde_json::Map<String, serde_json::Value>, path: &str| {
+        let mut pairs = Vec::with_capacity(map.len());
+
+        for (key, value) in map {
+            let value_path = format!("{}/{}", path, escape_pointer_token(&key));
+            pairs.push((key, from_extended_json_value(value, value_path)?));
+        }
+
+        Ok(Object::map(pairs))
+    };
+
+    let tag = match map.iter().next() {
+        Some((key, _)) if map.len() == 1 && key.starts_with('$') => key.clone(),
+        _ => return read_map(map, &path),
+    };
+
+    let value = &map[&tag];
+    let invalid = || ConvertError::InvalidTag {
+        path: path.clone(),
+        tag: tag.clone(),
+    };
+    let base64 = |value: &serde_json::Value| {
+        let encoded = value.as_str().ok_or_else(invalid)?;
+        base64::engine::general_purpose::STANDARD
+            .decode(encoded)
+            .map_err(|_| invalid())
+    };
+    let float = |value: &serde_json::Value| match value {
+        serde_json::Value::Number(n) => n.as_f64().ok_or_else(invalid),
+        serde_json::Value::String(s) => match s.as_str() {
+            "NaN" => Ok(f64::NAN),
+            "Infinity" => Ok(f64::INFINITY),
+            "-Infinity" => Ok(f64::NEG_INFINITY),
+            _ => Err(invalid()),
+        },
+        _ => Err(invalid()),
+    };
+
+    match tag.as_str() {
+        "$bytes" => Ok(Object::bytes(base64(value)?)),
+        "$ts" => {
+            let t = value.as_u64().and_then(|t| u32::try_from(t).ok());
+            Ok(Object::timestamp32(t.ok_or_else(invalid)?))
+        }
+        "$i128" => {
+            let i = value.as_str().and_then(|s| s.parse().ok());
+            Ok(Object::sint(i.ok_or_else(invalid)?))
+        }
+        "$u128" => {
+            let u = value.as_str().and_then(|s| s.parse().ok());
+            Ok(Object::uint(u.ok_or_else(invalid)?))
+        }
+        "$f32" => Ok(Object::float32(float(value)? as f32)),
+        "$f64" => Ok(Object::float64(float(value)?)),
+        "$user" => {
+            let id = value["id"]
+                .as_u64()
+                .and_then(|id| u8::try_from(id).ok())
+                .filter(|id| (FIRST_USER_DEFINED_ID..=LAST_USER_DEFINED_ID).contains(id))
+                .ok_or_else(invalid)?;
+            let data = base64(&value["data"])?;
+
+            if data.len() != id as usize {
+                return Err(invalid());
+            }
+
+            Ok(Object {
+                length: data.len(),
+                value: object::Value::UserDefined { id, data },
+            })
+        }
+        "$map" => match map.into_iter().next() {
+            Some((_, serde_json::Value::Object(inner))) => read_map(inner, &path),
+            _ => Err(invalid()),
+        },
+        _ => read_map(map, &path),
+    }
+}
+
+// Float32 if it fits exactly, like `from_json`
+fn float(double: f64) -> Object {
+    if double == double as f32 as f64 {
+        Object::float32(double as f32)
+    } else {
+        Object::float64(double)
+    }
+}
+
+fn from_yaml_document(
+    yaml: Result<serde_yaml_ng::Value, serde_yaml_ng::Error>,
+) -> Result<Object, ConvertError> {
+    let mut yaml = yaml.map_err(|e| ConvertError::Syntax(e.to_string()))?;
+
+    // resolve << merge keys everywhere before looking at the keys themselves
+    yaml.apply_merge()
+        .map_err(|e| ConvertError::Syntax(e.to_string()))?;
+
+    from_yaml_value(yaml, String::new())
+}
+
+fn from_yaml_value(yaml: serde_yaml_ng::Value, path: String) -> Result<Object, ConvertError> {
+    match yaml {
+        serde_yaml_ng::Value::Null => Ok(Object::null()),
+        serde_yaml_ng::Value::Bool(b) => Ok(Object::bool(b)),
+        serde_yaml_ng::Value::Number(n) => {
+            if let Some(i) = n.as_i64() {
+                Ok(Object::sint(i as i128))
+            } else if let Some(u) = n.as_u64() {
+                Ok(Object::uint(u as u128))
+            } else {
+                Ok(float(n.as_f64().unwrap()))
+            }
+        }
+        serde_yaml_ng::Value::String(s) => Ok(Object::string(s)),
+        serde_yaml_ng::Value::Sequence(elements) => {
+            let mut list = Vec::with_capacity(elements.len());
+
+            for (i, element) in elements.into_iter().enumerate() {
+                list.push(from_yaml_value(element, format!("{}/{}", path, i))?);
+            }
+
+            Ok(Object::list(list))
+        }
+        serde_yaml_ng::Value::Mapping(mapping) => {
+            let mut pairs = Vec::with_capacity(mapping.len());
+
+            for (key, value) in mapping {
+                let key = match key {
+                    serde_yaml_ng::Value::String(s) => s,
+                    serde_yaml_ng::Value::Number(n) => n.to_string(),
+                    serde_yaml_ng::Value::Bool(b) => b.to_string(),
+                    _ => return Err(ConvertError::UnsupportedKey { path }),
+                };
+
+                let value_path = format!("{}/{}", path, escape_pointer_token(&key));
+                pairs.push((key, from_yaml_value(value, value_path)?));
+            }
+
+            Ok(Object::map(pairs))
+        }
+        serde_yaml_ng::Value::Tagged(tagged) => from_yaml_value(tagged.value, path),
+    }
+}
+
+fn to_yaml_value(object: &Object) -> serde_yaml_ng::Value {
+    match &object.value {
+        object::Value::Map(items) => {
+            let mut mapping = serde_yaml_ng::Mapping::with_capacity(items.len());
+
+            for (key, value) in items {
+                mapping.insert(key.clone().into(), to_yaml_value(value));
+            }
+
+            serde_yaml_ng::Value::Mapping(mapping)
+        }
+        object::Value::List(elements) | object::Value::IntSeq { elements, .. } => {
+            serde_yaml_ng::Value::Sequence(elements.iter().map(to_yaml_value).collect())
+        }
+        object::Value::Bool(b) => serde_yaml_ng::Value::Bool(*b),
+        object::Value::Null => serde_yaml_ng::Value::Null,
+        object::Value::SInt(i) => match i64::try_from(*i) {
+            Ok(i) => i.into(),
+            Err(_) => i.to_string().into(),
+        },
+        object::Value::UInt(u) => match u64::try_from(*u) {
+            Ok(u) => u.into(),
+            Err(_) => u.to_string().into(),
+        },
+        object::Value::Float32(f) => (*f as f64).into(),
+        object::Value::Float64(f) => (*f).into(),
+        object::Value::Timestamp32(t) => (*t).into(),
+        // the same as JSON: strings, base64 and {"id", "data"}
+        _ => serde_yaml_ng::to_value(object.clone().into_json()).unwrap(),
+    }
+}
+
+fn from_toml_value(toml: toml::Value) -> Object {
+    match toml {
+        toml::Value::String(s) => Object::string(s),
+        toml::Value::Integer(i) => Object::sint(i as i128),
+        toml::Value::Float(f) => float(f),
+        toml::Value::Boolean(b) => Object::bool(b),
+        toml::Value::Datetime(datetime) => match datetime_to_timestamp(&datetime) {
+            Some(t) => Object::timestamp32(t),
+            None => Object::string(datetime.to_string()),
+        },
+        toml::Value::Array(elements) => {
+            Object::list(elements.into_iter().map(from_toml_value).collect())
+        }
+        toml::Value::Table(table) => Object::map(
+            table
+                .into_iter()
+                .map(|(key, value)| (key, from_toml_value(value)))
+                .collect(),
+        ),
+    }
+}
+
+fn to_toml_value(object: &Object, path: String) -> Result<toml::Value, ConvertError> {
+    match &object.value {
+        object::Value::Map(items) => {
+            let mut table = toml::Table::with_capacity(items.len());
+
+            for (key, value) in items {
+                let value_path = format!("{}/{}", path, escape_pointer_token(key));
+                table.insert(key.clone(), to_toml_value(value, value_path)?);
+            }
+
+            Ok(toml::Value::Table(table))
+        }
+        object::Value::List(elements) | object::Value::IntSeq { elements, .. } => {
+            let mut array = Vec::with_capacity(elements.len());
+
+            for (i, element) in elements.iter().enumerate() {
+                array.push(to_toml_value(element, format!("{}/{}", path, i))?);
+            }
+
+            Ok(toml::Value::Array(array))
+        }
+        object::Value::Null => Err(ConvertError::UnsupportedType {
+            path,
+            found: object.value.type_name(),
+            format: "TOML",
+        }),
+        object::Value::Bool(b) => Ok(toml::Value::Boolean(*b)),
+        object::Value::SInt(i) => Ok(match i64::try_from(*i) {
+            Ok(i) => toml::Value::Integer(i),
+            Err(_) => toml::Value::String(i.to_string()),
+        }),
+        object::Value::UInt(u) => Ok(match i64::try_from(*u) {
+            Ok(i) => toml::Value::Integer(i),
+            Err(_) => toml::Value::String(u.to_string()),
+        }),
+        object::Value::Float32(f) => Ok(toml::Value::Float(*f as f64)),
+        object::Value::Float64(f) => Ok(toml::Value::Float(*f)),
+        object::Value::Timestamp32(t) => Ok(toml::Value::Datetime(timestamp_to_datetime(*t))),
+        object::Value::String { string, .. } => Ok(toml::Value::String(string.clone())),
+        object::Value::Bytes(b) => Ok(toml::Value::String(
+            base64::engine::general_purpose::STANDARD.encode(b),
+        )),
+        object::Value::UserDefined { id, data } => {
+            let mut table = toml::Table::new();
+            table.insert("id".to_string(), toml::Value::Integer(*id as i64));
+            table.insert(
+                "data".to_string(),
+                toml::Value::String(base64::engine::general_purpose::STANDARD.encode(data)),
+            );
+
+            Ok(toml::Value::Table(table))
+        }
+        object::Value::Run(_) => unreachable!("runs only exist inside encoded messages"),
+    }
+}
+
+// seconds since 1970 for an offset date-time with whole seconds that fits in a u32
+fn datetime_to_timestamp(datetime: &Datetime) -> Option<u32> {
+    let (Some(date), Some(time), Some(offset)) = (datetime.date, datetime.time, datetime.offset)
+    else {
+        return None;
+    };
+
+    if time.nanosecond != 0 {
+        return None;
+    }
+
+    let offset_minutes = match offset {
+        Offset::Z => 0,
+        Offset::Custom { minutes } => minutes as i64,
+    };
+
+    let days = days_from_civil(date.year as i64, date.month as i64, date.day as i64);
+    let seconds =
+        days * 86400 + time.hour as i64 * 3600 + time.minute as i64 * 60 + time.second as i64
+            - offset_minutes * 60;
+
+    u32::try_from(seconds).ok()
+}
+
+fn timestamp_to_datetime(timestamp: u32) -> Datetime {
+    let days = timestamp as i64 / 86400;
+    let seconds = timestamp as i64 % 86400;
+    let (year, month, day) = civil_from_days(days);
+
+    Datetime {
+        date: Some(Date {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+        }),
+        time: Some(Time {
+            hour: (seconds / 3600) as u8,
+            minute: (seconds / 60 % 60) as u8,
+            second: (seconds % 60) as u8,
+            nanosecond: 0,
+        }),
+        offset: Some(Offset::Z),
+    }
+}
+
+// days since 1970-01-01 in the proleptic Gregorian calendar (Howard Hinnant's algorithm)
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146097 + day_of_era - 719468
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map(items: Vec<(&str, Object)>) -> Object {
+        Object::map(items.into_iter().map(|(k, v)| (k.to_string(), v)).collect())
+    }
+
+    #[test]
+    fn yaml_round_trips() {
+        let object = map(vec![
+            ("name", Object::string("x".to_string())),
+            ("count", Object::sint(-3)),
+            ("ratio", Object::float32(0.5)),
+            ("precise", Object::float64(0.1)),
+            ("on", Object::bool(true)),
+            ("none", Object::null()),
+            ("list", Object::list(vec![Object::sint(1), map(Vec::new())])),
+        ]);
+
+        let yaml = object.to_yaml().unwrap();
+        assert_eq!(Object::from_yaml(&yaml), Ok(object.clone()));
+
+        let stream = format!("{}---\n{}", yaml, yaml);
+        assert_eq!(
+            read_yaml_documents(&stream),
+            Ok(vec![object.clone(), object])
+        );
+    }
+
+    #[test]
+    fn yaml_keys_tags_and_merges_are_resolved() {
+        let yaml = "base: &base {a: 1}\nmerged: {<<: *base, b: 2}\n1: !tagged x\ntrue: [~]\n";
+
+        assert_eq!(
+            Object::from_yaml(yaml),
+            Ok(map(vec![
+                ("base", map(vec![("a", Object::sint(1))])),
+                (
+                    "merged",
+                    map(vec![("b", Object::sint(2)), ("a", Object::sint(1))])
+                ),
+                ("1", Object::string("x".to_string())),
+                ("true", Object::list(vec![Object::null()])),
+            ]))
+        );
+    }
+
+    #[test]
+    fn malformed_yaml_is_an_error() {
+        assert!(matches!(
+            Object::from_yaml("a: [1"),
+            Err(ConvertError::Syntax(_))
+        ));
+        assert!(matches!(
+            read_yaml_documents("a: 1\n---\nb: [\n"),
+            Err(ConvertError::Syntax(_))
+        ));
+        assert_eq!(
+            Object::from_yaml("a:\n  ? [1]\n  : 2\n"),
+            Err(ConvertError::UnsupportedKey {
+                path: "/a".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn toml_round_trips() {
+        let object = map(vec![
+            ("name", Object::string("x".to_string())),
+            ("count", Object::sint(-3)),
+            ("ratio", Object::float32(0.5)),
+            ("at", Object::timestamp32(1_700_000_000)),
+            ("list", Object::list(vec![Object::bool(true)])),
+            ("table", map(vec![("a", Object::sint(1))])),
+        ]);
+
+        let toml = object.to_toml().unwrap();
+        assert!(toml.contains("at = 2023-11-14T22:13:20Z"), "{}", toml);
+        assert_eq!(Object::from_toml(&toml), Ok(object));
+
+        // datetimes that aren't a Timestamp32 stay strings
+        assert_eq!(
+            Object::from_toml(
+                "a = 1979-05-27\nb = 1969-12-31T23:59:59Z\nc = 2000-01-01T00:00:00+01:00"
+            ),
+            Ok(map(vec![
+                ("a", Object::string("1979-05-27".to_string())),
+                ("b", Object::string("1969-12-31T23:59:59Z".to_string())),
+                ("c", Object::timestamp32(946_681_200)),
+            ]))
+        );
+    }
+
+    #[test]
+    fn malformed_toml_is_an_error() {
+        assert!(matches!(
+            Object::from_toml("a = "),
+            Err(ConvertError::Syntax(_))
+        ));
+        assert!(matches!(
+            Object::from_toml("a = 1\na = 2"),
+            Err(ConvertError::Syntax(_))
+        ));
+        assert_eq!(
+            Object::list(Vec::new()).to_toml(),
+            Err(ConvertError::NotATable { found: "List" })
+        );
+        assert_eq!(
+            map(vec![("a", Object::list(vec![Object::null()]))]).to_toml(),
+            Err(ConvertError::UnsupportedType {
+                path: "/a/0".to_string(),
+                found: "Null",
+                format: "TOML",
+            })
+        );
+    }
+
+    #[test]
+    fn extended_json_round_trips() {
+        let user_defined = Object {
+            length: 39,
+            value: object::Value::UserDefined {
+                id: 39,
+                data: vec![1; 39],
+            },
+        };
+
+        let object = map(vec![
+            ("sint", Object::sint(-1)),
+            ("huge", Object::sint(i128::MIN)),
+            ("uint", Object::uint(1)),
+            ("big", Object::uint(u64::MAX as u128)),
+            ("bigger", Object::uint(u128::MAX)),
+            ("float32", Object::float32(0.1)),
+            ("infinity", Object::float32(f32::NEG_INFINITY)),
+            ("float64", Object::float64(1.5)),
+            ("bytes", Object::bytes(vec![0, 255])),
+            ("empty", Object::bytes(Vec::new())),
+            ("timestamp", Object::timestamp32(1_700_000_000)),
+            ("user", user_defined),
+            (
+                "tagged",
+                map(vec![("$ts", Object::string("x".to_string()))]),
+            ),
+            (
+                "list",
+                Object::list(vec![Object::null(), Object::bool(false)]),
+            ),
+        ]);
+
+        let json = object.clone().into_extended_json();
+        assert_eq!(json["uint"], json!({"$u128": "1"}));
+        assert_eq!(json["big"], json!(u64::MAX));
+        assert_eq!(json["float64"], json!({"$f64": 1.5}));
+        assert_eq!(json["tagged"], json!({"$map": {"$ts": "x"}}));
+
+        let text = serde_json::to_string(&json).unwrap();
+        let read = Object::from_extended_json(serde_json::from_str(&text).unwrap()).unwrap();
+        assert_eq!(read, object);
+        assert_eq!(
+            crate::encode::headpack_encode(read),
+            crate::encode::headpack_encode(object)
+        );
+
+        let nan = Object::float64(f64::NAN).into_extended_json();
+        assert_eq!(nan, json!({"$f64": "NaN"}));
+        assert!(matches!(
+            Object::from_extended_json(nan).unwrap().value,
+            object::Value::Float64(f) if f.is_nan()
+        ));
+
+        // an unknown tag, or a $ key next to others, is just a map
+        for json in [json!({"$other": 1}), json!({"$ts": 1, "a": 2})] {
+            let read = Object::from_extended_json(json.clone()).unwrap();
+            assert_eq!(read.into_json(), json);
+        }
+    }
+
+    #[test]
+    fn malformed_extended_json_is_an_error() {
+        let user = |id: u8, length: usize| {
+            let data = base64::engine::general_purpose::STANDARD.encode(vec![0; length]);
+            json!({"$user": {"id": id, "data": data}})
+        };
+
+        for (json, tag) in [
+            (json!({"$bytes": "not base64!"}), "$bytes"),
+            (json!({"$bytes": 1}), "$bytes"),
+            (json!({"$ts": -1}), "$ts"),
+            (json!({"$ts": 4_294_967_296u64}), "$ts"),
+            (json!({"$i128": 5}), "$i128"),
+            (json!({"$i128": "1e3"}), "$i128"),
+            (json!({"$u128": "-1"}), "$u128"),
+            (json!({"$f32": "nan"}), "$f32"),
+            (json!({"$f64": null}), "$f64"),
+            (json!({"$user": 39}), "$user"),
+            (user(38, 38), "$user"),
+            (user(64, 64), "$user"),
+            (user(39, 38), "$user"),
+            (json!({"$map": [1]}), "$map"),
+        ] {
+            let nested = json!({"a": [1, json.clone()]});
+
+            assert_eq!(
+                Object::from_extended_json(nested),
+                Err(ConvertError::InvalidTag {
+                    path: "/a/1".to_string(),
+                    tag: tag.to_string(),
+                }),
+                "{}",
+                json
+            );
+        }
+    }
+
+    #[test]
+    fn json_options_pick_the_types() {
+        let json = json!({
+            "int": 1,
+            "negative": -1,
+            "half": 0.5,
+            "tenth": 0.1,
+            "whole": 2.0,
+            "huge": 1e20,
+            "base64": "AAE=",
+            "text": "abc",
+            "time": "2023-11-14T22:13:20Z",
+            "fraction": "2023-11-14T22:13:20.5Z",
+            "null": null,
+            "list": [null, {"null": null}]
+        });
+
+        let read = |options: FromJsonOptions| {
+            let object = Object::from_json_with_options(json.clone(), &options);
+            move |key: &str| object.pointer(&format!("/{}", key)).cloned()
+        };
+
+        let default = read(FromJsonOptions::default());
+        assert_eq!(default("int"), Some(Object::sint(1)));
+        assert_eq!(default("half"), Some(Object::float32(0.5)));
+        assert_eq!(default("tenth"), Some(Object::float64(0.1)));
+        assert_eq!(default("whole"), Some(Object::float32(2.0)));
+        assert_eq!(default("base64"), Some(Object::string("AAE=".to_string())));
+        assert_eq!(
+            default("time"),
+            Some(Object::string("2023-11-14T22:13:20Z".to_string()))
+        );
+        assert_eq!(default("null"), Some(Object::null()));
+
+        let all = read(FromJsonOptions {
+            prefer_uint: true,
+            floats: FloatNarrowing::Always,
+            integral_floats_as_ints: true,
+            base64_as_bytes: true,
+            rfc3339_as_timestamps: true,
+            drop_nulls: true,
+        });
+        assert_eq!(all("int"), Some(Object::uint(1)));
+        assert_eq!(all("negative"), Some(Object::sint(-1)));
+        assert_eq!(all("tenth"), Some(Object::float32(0.1)));
+        assert_eq!(all("whole"), Some(Object::uint(2)));
+        assert_eq!(all("huge"), Some(Object::float32(1e20)));
+        assert_eq!(all("base64"), Some(Object::bytes(vec![0, 1])));
+        assert_eq!(all("text"), Some(Object::string("abc".to_string())));
+        assert_eq!(all("time"), Some(Object::timestamp32(1_700_000_000)));
+        assert_eq!(
+            all("fraction"),
+            Some(Object::string("2023-11-14T22:13:20.5Z".to_string()))
+        );
+        assert_eq!(all("null"), None);
+        assert_eq!(
+            all("list"),
+            Some(Object::list(vec![Object::null(), Object::map(Vec::new())]))
+        );
+
+        let never = read(FromJsonOptions {
+            floats: FloatNarrowing::Never,
+            integral_floats_as_ints: true,
+            ..Default::default()
+        });
+        assert_eq!(never("half"), Some(Object::float64(0.5)));
+        assert_eq!(never("whole"), Some(Object::sint(2)));
+    }
+
+    #[test]
+    fn floats_can_be_narrowed_again() {
+        let mut object = Object::list(vec![Object::float32(0.5), Object::float64(0.1)]);
+
+        object.narrow_floats(FloatNarrowing::Lossless);
+        assert_eq!(
+            object,
+            Object::list(vec![Object::float32(0.5), Object::float64(0.1)])
+        );
+
+        object.narrow_floats(FloatNarrowing::Never);
+        assert_eq!(
+            object,
+            Object::list(vec![Object::float64(0.5), Object::float64(0.1)])
+        );
+
+        object.narrow_floats(FloatNarrowing::Always);
+        assert_eq!(
+            object,
+            Object::list(vec![Object::float32(0.5), Object::float32(0.1)])
+        );
+    }
 }
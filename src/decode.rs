@@ -1,144 +1,381 @@
 use std::collections::VecDeque;
+use std::fmt::{self, Display, Formatter};
 
 use crate::{
-    encode::{sint_from_bytes, uint_from_bytes},
-    object::{Object, Value, ValueClass},
+    checksum::{crc32c, CHECKSUM_LENGTH},
+    encode::{read_varint, sint_from_bytes, uint_from_bytes, unzigzag},
+    object::{Object, Value, ValueClass, MAX_RUN_EXPANSION},
+    schema::{Schema, SchemaMismatch},
+    version::{detect, Version, PREFIX_LENGTH},
 };
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    // the message doesn't start with the magic number
+    NotFramed,
+    // the message was written by a newer (or unknown) version of the format
+    UnsupportedVersion(Version),
+    // the message is too short to contain what it should
+    Truncated,
+    // the checksum trailer doesn't match the message, so it was corrupted along the way
+    ChecksumMismatch { expected: u32, actual: u32 },
+    // the message doesn't follow the format, e.g. it uses an unknown extended object kind
+    Malformed(String),
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::NotFramed => write!(f, "not a framed HeadPack message"),
+            DecodeError::UnsupportedVersion(v) => {
+                write!(f, "unsupported HeadPack format version {}", v.0)
+            }
+            DecodeError::Truncated => write!(f, "HeadPack message is truncated"),
+            DecodeError::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "HeadPack checksum mismatch: expected {:08x}, got {:08x}",
+                expected, actual
+            ),
+            DecodeError::Malformed(message) => write!(f, "malformed HeadPack message: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+// how deep maps and lists can nest, the root included, the same limit serde_json has
+pub const MAX_DEPTH: usize = 128;
+
+// decode a message written by `headpack_encode_with_checksum`
+pub fn headpack_decode_with_checksum(mut buf: VecDeque<u8>) -> Result<Object, DecodeError> {
+    if buf.len() < CHECKSUM_LENGTH {
+        return Err(DecodeError::Truncated);
+    }
+
+    let trailer: Vec<u8> = buf.drain(buf.len() - CHECKSUM_LENGTH..).collect();
+    let expected = u32::from_be_bytes(trailer.try_into().unwrap());
+    let actual = crc32c(buf.make_contiguous());
+
+    if expected != actual {
+        return Err(DecodeError::ChecksumMismatch { expected, actual });
+    }
+
+    // a matching checksum only means the bytes arrived as they were written
+    headpack_try_decode(buf)
+}
+
+// decode a message written by `headpack_encode_framed`
+pub fn headpack_decode_framed(mut buf: VecDeque<u8>) -> Result<Object, DecodeError> {
+    let prefix: Vec<u8> = buf.iter().take(PREFIX_LENGTH).copied().collect();
+
+    let version = detect(&prefix).ok_or(DecodeError::NotFramed)?;
+
+    if !version.is_supported() {
+        return Err(DecodeError::UnsupportedVersion(version));
+    }
+
+    buf.drain(..PREFIX_LENGTH);
+
+    headpack_try_decode(buf)
+}
+
+// decode a message written by `headpack_encode_with_schema` with the same schema
+pub fn headpack_decode_with_schema(
+    buf: VecDeque<u8>,
+    schema: &Schema,
+) -> Result<Object, SchemaMismatch> {
+    schema.restore_keys(headpack_try_decode(buf)?)
+}
+
 /// warning: mutates `data`
-pub fn headpack_decode(mut buf: VecDeque<u8>) -> Object {
-    let (classes, is_root_map) = decode_classes_section(&mut buf);
+/// panics if the message is truncated or malformed, see `headpack_try_decode`
+pub fn headpack_decode(buf: VecDeque<u8>) -> Object {
+    headpack_try_decode(buf).unwrap_or_else(|e| panic!("{}", e))
+}
+
+// the same as `headpack_decode`, for messages that might not be well-formed,
+// e.g. ones read from a file or the network
+pub fn headpack_try_decode(mut buf: VecDeque<u8>) -> Result<Object, DecodeError> {
+    headpack_decode_partial(&mut buf)
+}
+
+// decode one message from the front of `buf`, leaving any bytes that come after it
+// the number of bytes the message took up is how much shorter `buf` got
+pub fn headpack_decode_partial(buf: &mut VecDeque<u8>) -> Result<Object, DecodeError> {
+    let (mut objects, is_root_map) = decode_header(buf)?;
+
+    read_data(&mut objects, buf)?;
+
+    let mut collapsed = Vec::with_capacity(objects.len());
+
+    collapse_collections(&mut objects.into_iter(), &mut collapsed, -1)?;
+
+    if is_root_map {
+        if collapsed.len() % 2 != 0 {
+            return Err(DecodeError::Malformed(
+                "the root map has a key without a value".to_string(),
+            ));
+        }
+
+        // populate the root map
+        let mut map_items = Vec::with_capacity(collapsed.len() / 2);
+
+        while collapsed.len() >= 2 {
+            let value = collapsed.pop().unwrap();
+            let key_string_obj = collapsed.pop().unwrap();
+
+            if let Value::String { string, .. } = key_string_obj.value {
+                map_items.push((string, value));
+            } else {
+                return Err(DecodeError::Malformed(
+                    "the root map has a key that isn't a string".to_string(),
+                ));
+            }
+        }
+
+        map_items.reverse(); // to preserve order, can theoretically be skipped
+        Ok(Object::map(map_items))
+    } else {
+        // the root is a list of objects so just make it right here
+        Ok(Object::list(collapsed))
+    }
+}
+
+// read the CLASS and LENGTH sections into a flat list of empty objects (map keys included),
+// in the order their data appears in the DATA section
+pub fn decode_header(buf: &mut VecDeque<u8>) -> Result<(Vec<Object>, bool), DecodeError> {
+    let (classes, is_root_map) = decode_classes_section(buf)?;
     let mut classes = VecDeque::from(classes);
 
     let mut length_chunks: VecDeque<(u8, bool)> = VecDeque::new();
     let mut objects: Vec<Object> = Vec::new();
 
     if is_root_map {
-        while classes.len() > 0 {
-            push_next_key(&mut objects, &mut buf, &mut length_chunks);
-            push_next_obj(&mut objects, &mut buf, &mut length_chunks, &mut classes);
+        while !classes.is_empty() {
+            push_next_key(&mut objects, buf, &mut length_chunks)?;
+            push_next_value(&mut objects, buf, &mut length_chunks, &mut classes, 1)?;
         }
     } else {
-        while classes.len() > 0 {
-            push_next_obj(&mut objects, &mut buf, &mut length_chunks, &mut classes);
+        while !classes.is_empty() {
+            push_next_obj(&mut objects, buf, &mut length_chunks, &mut classes, 1)?;
         }
     }
 
-    // copy data into string and bytes objects
+    Ok((objects, is_root_map))
+}
+
+// how many bytes of the DATA section an object from `decode_header` takes up
+pub fn data_length(object: &Object) -> usize {
+    match object.value {
+        Value::Map(_) | Value::List(_) | Value::Bool(_) | Value::Null | Value::Run(_) => 0,
+        _ => object.length,
+    }
+}
+
+// copy data from the DATA section into objects from `decode_header`
+pub fn read_data(objects: &mut [Object], buf: &mut VecDeque<u8>) -> Result<(), DecodeError> {
     for object in objects.iter_mut() {
+        let length = data_length(object);
+
+        if buf.len() < length {
+            return Err(DecodeError::Truncated);
+        }
+
+        let mut bytes = buf.drain(..length);
+        let type_name = object.value.type_name();
+        let wrong_length =
+            || DecodeError::Malformed(format!("{} with a length of {}", type_name, length));
+
         match object.value {
             Value::String {
                 ref mut string,
                 encode_class: _,
             } => {
-                *string = String::from_utf8(buf.drain(..object.length).collect()).unwrap();
+                *string = String::from_utf8(bytes.collect())
+                    .map_err(|_| DecodeError::Malformed("invalid UTF-8 in a string".to_string()))?;
             }
-            Value::Bytes(ref mut bytes) => {
-                bytes.extend(buf.drain(..object.length));
+            Value::Bytes(ref mut b) => {
+                b.extend(bytes);
             }
             Value::SInt(ref mut i) => {
-                *i = sint_from_bytes(buf.drain(..object.length));
+                *i = sint_from_bytes(bytes);
             }
             Value::UInt(ref mut u) => {
-                *u = uint_from_bytes(buf.drain(..object.length));
+                *u = uint_from_bytes(bytes);
             }
             Value::Float32(ref mut f) => {
-                let arr = buf
-                    .drain(..object.length)
-                    .collect::<Vec<u8>>()
-                    .try_into()
-                    .expect("invalid length for Float32");
-
-                *f = f32::from_be_bytes(arr);
+                *f = f32::from_be_bytes(
+                    bytes
+                        .collect::<Vec<u8>>()
+                        .try_into()
+                        .map_err(|_| wrong_length())?,
+                );
             }
             Value::Float64(ref mut f) => {
-                let arr = buf
-                    .drain(..object.length)
-                    .collect::<Vec<u8>>()
-                    .try_into()
-                    .expect("invalid length for Float64");
-
-                *f = f64::from_be_bytes(arr);
+                *f = f64::from_be_bytes(
+                    bytes
+                        .collect::<Vec<u8>>()
+                        .try_into()
+                        .map_err(|_| wrong_length())?,
+                );
             }
             Value::Timestamp32(ref mut t) => {
-                let arr = buf
-                    .drain(..object.length)
-                    .collect::<Vec<u8>>()
-                    .try_into()
-                    .expect("invalid length for Timestamp32");
-
-                *t = u32::from_be_bytes(arr);
+                *t = u32::from_be_bytes(
+                    bytes
+                        .collect::<Vec<u8>>()
+                        .try_into()
+                        .map_err(|_| wrong_length())?,
+                );
             }
             Value::UserDefined {
                 id: _,
                 ref mut data,
             } => {
-                data.extend(buf.drain(..object.length));
+                data.extend(bytes);
             }
-            Value::Map(_) | Value::List(_) | Value::Bool(_) | Value::Null => {}
-        };
-    }
+            Value::IntSeq {
+                signed,
+                ref mut elements,
+            } => {
+                let mut previous: i128 = 0;
 
-    let mut collapsed = Vec::with_capacity(objects.len());
+                while bytes.len() > 0 {
+                    let invalid = || {
+                        DecodeError::Malformed("invalid varint in an integer sequence".to_string())
+                    };
 
-    collapse_collections(&mut objects.into_iter(), &mut collapsed, -1);
+                    let delta = read_varint(&mut bytes).ok_or_else(invalid)?;
+                    previous = previous.checked_add(unzigzag(delta)).ok_or_else(invalid)?;
 
-    if is_root_map {
-        // populate the root map
-        let mut map_items = Vec::with_capacity(collapsed.len() / 2);
+                    elements.push(if signed {
+                        Object::sint(previous)
+                    } else {
+                        Object::uint(previous as u128)
+                    });
+                }
+            }
+            Value::Map(_) | Value::List(_) | Value::Bool(_) | Value::Null | Value::Run(_) => {}
+        };
+    }
 
-        while collapsed.len() >= 2 {
-            let value = collapsed.pop().unwrap();
-            let key_string_obj = collapsed.pop().unwrap();
+    Ok(())
+}
 
-            if let Value::String { string, .. } = key_string_obj.value {
-                map_items.push((string, value));
-            } else {
-                unreachable!();
-            }
+// returns how many list elements the pushed object stands for
+// `depth` is how many maps and lists the object is in, the root's included
+fn push_next_obj(
+    objects: &mut Vec<Object>,
+    data: &mut VecDeque<u8>,
+    length_chunks: &mut VecDeque<(u8, bool)>,
+    classes: &mut VecDeque<ValueClass>,
+    depth: usize,
+) -> Result<usize, DecodeError> {
+    let object = next_object(data, length_chunks, classes)?;
+
+    let count = match object.value {
+        Value::Run(count) => count,
+        _ => {
+            push_object(objects, object, data, length_chunks, classes, depth)?;
+            return Ok(1);
         }
+    };
 
-        map_items.reverse(); // to preserve order, can theoretically be skipped
-        Object::map(map_items)
-    } else {
-        // the root is a list of objects so just make it right here
-        Object::list(collapsed)
+    // the encoder only writes a run when something repeats
+    if count < 2 {
+        return Err(DecodeError::Malformed(format!(
+            "a run of {}, runs repeat their object at least twice",
+            count
+        )));
     }
+
+    // the object being repeated comes right after the run
+    let repeated = next_object(data, length_chunks, classes)?;
+
+    if let Value::Run(_) = repeated.value {
+        return Err(DecodeError::Malformed(
+            "a run repeats another run".to_string(),
+        ));
+    }
+
+    objects.push(object);
+    push_object(objects, repeated, data, length_chunks, classes, depth)?;
+
+    Ok(count)
 }
 
-fn push_next_obj(
+// the same as `push_next_obj` for a map value, which can't be a run
+fn push_next_value(
     objects: &mut Vec<Object>,
     data: &mut VecDeque<u8>,
     length_chunks: &mut VecDeque<(u8, bool)>,
     classes: &mut VecDeque<ValueClass>,
-) {
-    let mut length = next_len(length_chunks, data);
-    let class = match classes.pop_front() {
-        Some(c) => c,
-        None => return,
-    };
+    depth: usize,
+) -> Result<(), DecodeError> {
+    if push_next_obj(objects, data, length_chunks, classes, depth)? != 1 {
+        return Err(DecodeError::Malformed(
+            "a run in a map, runs can only be in lists".to_string(),
+        ));
+    }
 
-    let object = Object::from_class_and_length(class, &mut length);
+    Ok(())
+}
 
-    let mut is_object_map = false;
-    let mut is_collection = false;
+// the next object from the LENGTH and CLASS sections, without anything inside it
+fn next_object(
+    data: &mut VecDeque<u8>,
+    length_chunks: &mut VecDeque<(u8, bool)>,
+    classes: &mut VecDeque<ValueClass>,
+) -> Result<Object, DecodeError> {
+    let mut length = next_len(length_chunks, data)?;
+    let class = classes.pop_front().ok_or(DecodeError::Truncated)?;
 
-    match object.value {
-        Value::Map(_) => {
-            is_collection = true;
-            is_object_map = true;
-        }
-        Value::List(_) => {
-            is_collection = true;
+    let object = Object::from_class_and_length(class, &mut length)?;
+
+    // the rest of the message has to at least hold the object's data
+    if data_length(&object) > data.len() {
+        return Err(DecodeError::Truncated);
+    }
+
+    Ok(object)
+}
+
+// push `object`, followed by everything inside it if it's a map or list
+fn push_object(
+    objects: &mut Vec<Object>,
+    object: Object,
+    data: &mut VecDeque<u8>,
+    length_chunks: &mut VecDeque<(u8, bool)>,
+    classes: &mut VecDeque<ValueClass>,
+    depth: usize,
+) -> Result<(), DecodeError> {
+    let is_object_map = match object.value {
+        Value::Map(_) => true,
+        Value::List(_) => false,
+        _ => {
+            objects.push(object);
+            return Ok(());
         }
-        _ => {}
     };
 
+    if depth == MAX_DEPTH {
+        return Err(DecodeError::Malformed(format!(
+            "maps and lists nest more than {} deep",
+            MAX_DEPTH
+        )));
+    }
+
+    let length = object.length;
     objects.push(object);
 
-    if is_collection {
-        unpack(objects, data, length_chunks, is_object_map, length, classes)
-    }
+    unpack(
+        objects,
+        data,
+        length_chunks,
+        is_object_map,
+        length,
+        classes,
+        depth + 1,
+    )
 }
 
 fn unpack(
@@ -148,26 +385,39 @@ fn unpack(
     is_object_map: bool,
     length: usize,
     classes: &mut VecDeque<ValueClass>,
-) {
+    depth: usize,
+) -> Result<(), DecodeError> {
     if is_object_map {
         for _ in 0..length {
-            push_next_key(objects, data, length_chunks);
-            push_next_obj(objects, data, length_chunks, classes);
+            push_next_key(objects, data, length_chunks)?;
+            push_next_value(objects, data, length_chunks, classes, depth)?;
         }
     } else {
-        for _ in 0..length {
-            push_next_obj(objects, data, length_chunks, classes);
+        let mut remaining = length;
+
+        while remaining > 0 {
+            let taken = push_next_obj(objects, data, length_chunks, classes, depth)?;
+            remaining = remaining.saturating_sub(taken.max(1));
         }
     }
+
+    Ok(())
 }
 
 fn push_next_key(
     objects: &mut Vec<Object>,
     data: &mut VecDeque<u8>,
     length_chunks: &mut VecDeque<(u8, bool)>,
-) {
-    let length = next_len(length_chunks, data);
+) -> Result<(), DecodeError> {
+    let length = next_len(length_chunks, data)?;
+
+    // checked before the string is allocated
+    if length > data.len() {
+        return Err(DecodeError::Truncated);
+    }
+
     objects.push(Object::sized_string(length));
+    Ok(())
 }
 
 fn classes_split(byte: u8) -> (u8, u8, u8, u8) {
@@ -190,14 +440,13 @@ fn lengths_split(byte: u8) -> (u8, u8, u8, u8) {
     (a, b, c, d)
 }
 
-fn decode_classes_section(data: &mut VecDeque<u8>) -> (Vec<ValueClass>, bool) {
-    let first_byte = data.pop_front().unwrap();
+fn decode_classes_section(data: &mut VecDeque<u8>) -> Result<(Vec<ValueClass>, bool), DecodeError> {
+    let first_byte = data.pop_front().ok_or(DecodeError::Truncated)?;
 
     // check if this is an empty map/object
     // in this state, the first bit is 0, and the 3rd onwards are 001100
     if (first_byte & 0b10111111) == 0b00001100 {
-        data.push_front(first_byte);
-        return (vec![], first_byte & 0b01000000 == 0b01000000);
+        return Ok((vec![], first_byte & 0b01000000 == 0b01000000));
     }
 
     let (flags, obj1, obj2, chunk2_len) = classes_split(first_byte);
@@ -209,7 +458,7 @@ fn decode_classes_section(data: &mut VecDeque<u8>) -> (Vec<ValueClass>, bool) {
     // if this is set, then there's 2 class definitions in the first byte, otherwise it's 1
     if (flags & 0b10) == 0b00 {
         // just 1 class def
-        return (vec![obj1.into()], is_root_map);
+        return Ok((vec![obj1.into()], is_root_map));
     }
 
     let mut classes = vec![obj1.into(), obj2.into()];
@@ -217,7 +466,8 @@ fn decode_classes_section(data: &mut VecDeque<u8>) -> (Vec<ValueClass>, bool) {
     let mut next_len = chunk2_len;
 
     while next_len > 0 {
-        let (val1, val2, val3, val4) = classes_split(data.pop_front().unwrap());
+        let (val1, val2, val3, val4) =
+            classes_split(data.pop_front().ok_or(DecodeError::Truncated)?);
 
         classes.push(val1.into());
 
@@ -233,30 +483,40 @@ fn decode_classes_section(data: &mut VecDeque<u8>) -> (Vec<ValueClass>, bool) {
         }
     }
 
-    (classes, is_root_map)
+    Ok((classes, is_root_map))
 }
 
 // read as many length chunks as needed in order to return the next length
-fn next_len(length_chunks: &mut VecDeque<(u8, bool)>, data: &mut VecDeque<u8>) -> usize {
-    let mut length = 0;
+fn next_len(
+    length_chunks: &mut VecDeque<(u8, bool)>,
+    data: &mut VecDeque<u8>,
+) -> Result<usize, DecodeError> {
+    let mut length: usize = 0;
 
     loop {
-        let (length_chunk, continue_flag) = lengths_next_chunk(length_chunks, data);
+        let (length_chunk, continue_flag) = lengths_next_chunk(length_chunks, data)?;
+
+        if length > usize::MAX >> 3 {
+            return Err(DecodeError::Malformed("a length is too long".to_string()));
+        }
 
         // combine the length chunk with the current length
         length = (length << 3) | length_chunk as usize;
 
         // this length doesn't fit in the current amount of chunks
         if !continue_flag {
-            return length;
+            return Ok(length);
         }
     }
 }
 
 // read the next length available, while potentially buffering another one in `chunks`
-fn lengths_next_chunk(chunks: &mut VecDeque<(u8, bool)>, data: &mut VecDeque<u8>) -> (u8, bool) {
-    if chunks.len() == 0 {
-        let next_byte = data.pop_front().unwrap();
+fn lengths_next_chunk(
+    chunks: &mut VecDeque<(u8, bool)>,
+    data: &mut VecDeque<u8>,
+) -> Result<(u8, bool), DecodeError> {
+    if chunks.is_empty() {
+        let next_byte = data.pop_front().ok_or(DecodeError::Truncated)?;
 
         let (len1, cont1, len2, cont2) = lengths_split(next_byte);
 
@@ -264,14 +524,26 @@ fn lengths_next_chunk(chunks: &mut VecDeque<(u8, bool)>, data: &mut VecDeque<u8>
         chunks.push_back((len2, cont2 != 0));
     }
 
-    chunks.pop_front().unwrap()
+    Ok(chunks.pop_front().unwrap())
 }
 
+// turn the flat objects from `decode_header` (with their data) back into maps and lists
 pub fn collapse_collections(
     iter: &mut impl Iterator<Item = Object>,
     into: &mut Vec<Object>,
     limit: isize,
-) {
+) -> Result<(), DecodeError> {
+    let mut expansion = MAX_RUN_EXPANSION;
+    collapse(iter, into, limit, &mut expansion)
+}
+
+// `expansion` is how much more runs can expand to, see MAX_RUN_EXPANSION
+fn collapse(
+    iter: &mut impl Iterator<Item = Object>,
+    into: &mut Vec<Object>,
+    limit: isize,
+    expansion: &mut usize,
+) -> Result<(), DecodeError> {
     let mut taken = 0;
 
     loop {
@@ -283,15 +555,15 @@ pub fn collapse_collections(
         if object.is_none() && limit == -1 {
             break;
         }
-        let mut object = object.expect("not enough objects to satisfy collection");
+        let mut object = object.ok_or(DecodeError::Truncated)?;
 
         match object.value {
             Value::List(ref mut l) => {
-                collapse_collections(iter, l, object.length as isize);
+                collapse(iter, l, object.length as isize, expansion)?;
             }
             Value::Map(ref mut m) => {
-                let mut flat = Vec::with_capacity(object.length);
-                collapse_collections(iter, &mut flat, (object.length * 2) as isize);
+                let mut flat = Vec::new();
+                collapse(iter, &mut flat, (object.length * 2) as isize, expansion)?;
                 let mut flat = VecDeque::from(flat);
 
                 while flat.len() >= 2 {
@@ -304,11 +576,54 @@ pub fn collapse_collections(
                     {
                         m.push((key_string, value))
                     } else {
-                        unreachable!()
+                        return Err(DecodeError::Malformed(
+                            "a map has a key that isn't a string".to_string(),
+                        ));
                     }
                 }
+            }
+            Value::IntSeq {
+                ref mut elements, ..
+            } => {
+                // an IntSeq is only a more compact way of writing a list
+                object = Object::list(std::mem::take(elements));
+            }
+            Value::Run(count) => {
+                if limit != -1 && taken + count as isize > limit {
+                    return Err(DecodeError::Malformed(format!(
+                        "a run of {} elements in a list with {} left",
+                        count,
+                        limit - taken
+                    )));
+                }
+
+                // the next object stands for `count` elements of the current list
+                let mut repeated = Vec::with_capacity(1);
+                collapse(iter, &mut repeated, 1, expansion)?;
+                let repeated = repeated.pop().ok_or_else(|| {
+                    DecodeError::Malformed("a run that repeats nothing".to_string())
+                })?;
+
+                // checked before anything is copied
+                let size = (count - 1).saturating_mul(repeated.expanded_size());
+
+                if size > *expansion {
+                    return Err(DecodeError::Malformed(format!(
+                        "runs expand to more than {} objects and bytes",
+                        MAX_RUN_EXPANSION
+                    )));
+                }
+
+                *expansion -= size;
+                into.reserve(count);
 
-                assert_eq!(flat.len(), 0);
+                for _ in 1..count {
+                    into.push(repeated.clone());
+                }
+
+                into.push(repeated);
+                taken += count as isize;
+                continue;
             }
             _ => {}
         }
@@ -316,4 +631,226 @@ pub fn collapse_collections(
         into.push(object);
         taken += 1;
     }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode::{
+        encode_flat, headpack_encode, headpack_encode_framed, headpack_encode_with_checksum,
+        headpack_encode_with_schema,
+    };
+    use crate::version::MAGIC;
+
+    fn run(count: usize) -> Object {
+        Object {
+            value: Value::Run(count),
+            length: 0,
+        }
+    }
+
+    fn decode(message: Vec<u8>) -> Result<Object, DecodeError> {
+        headpack_try_decode(VecDeque::from(message))
+    }
+
+    fn nested_lists(depth: usize) -> Object {
+        (1..depth).fold(Object::list(Vec::new()), |inner, _| {
+            Object::list(vec![inner])
+        })
+    }
+
+    #[test]
+    fn runs_round_trip() {
+        let list = Object::list(vec![Object::string("same".to_string()); 1000]);
+        let message = headpack_encode(list.clone());
+
+        assert!(message.len() < 20);
+        assert_eq!(decode(message), Ok(list));
+    }
+
+    #[test]
+    fn runs_of_zero_or_one_are_rejected() {
+        for count in [0, 1] {
+            let message = encode_flat(vec![run(count), Object::null()], false);
+            assert!(matches!(decode(message), Err(DecodeError::Malformed(_))));
+        }
+    }
+
+    #[test]
+    fn runs_of_runs_are_rejected() {
+        let message = encode_flat(vec![run(2), run(2), Object::null()], false);
+        assert!(matches!(decode(message), Err(DecodeError::Malformed(_))));
+    }
+
+    #[test]
+    fn runs_in_maps_are_rejected() {
+        let objects = vec![Object::key_string("a".to_string()), run(2), Object::null()];
+
+        assert!(matches!(
+            decode(encode_flat(objects, true)),
+            Err(DecodeError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn huge_runs_are_rejected_without_expanding() {
+        let message = encode_flat(vec![run(1 << 40), Object::null()], false);
+        assert!(matches!(decode(message), Err(DecodeError::Malformed(_))));
+
+        // each run uses up half of the limit, so the third one goes over
+        let string = Object::string("x".repeat(999));
+        let mut objects = Vec::new();
+        for _ in 0..3 {
+            objects.extend([run(MAX_RUN_EXPANSION / 2000 + 1), string.clone()]);
+        }
+
+        assert!(matches!(
+            decode(encode_flat(objects, false)),
+            Err(DecodeError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn the_encoder_stays_within_the_run_limit() {
+        let string = Object::string("x".repeat(1000));
+        let list = Object::list(vec![string; MAX_RUN_EXPANSION / 1000 + 10]);
+
+        assert_eq!(decode(headpack_encode(list.clone())), Ok(list));
+    }
+
+    #[test]
+    fn nesting_is_limited() {
+        let deepest = nested_lists(MAX_DEPTH);
+        assert_eq!(decode(headpack_encode(deepest.clone())), Ok(deepest));
+
+        let too_deep = headpack_encode(nested_lists(MAX_DEPTH + 1));
+        assert!(matches!(decode(too_deep), Err(DecodeError::Malformed(_))));
+
+        // deep enough to overflow the stack without the limit
+        let list = Object {
+            value: Value::List(Vec::new()),
+            length: 1,
+        };
+        let message = encode_flat(vec![list; 200_000], false);
+        assert!(matches!(decode(message), Err(DecodeError::Malformed(_))));
+    }
+
+    fn message() -> Object {
+        Object::map(vec![
+            ("a".to_string(), Object::sint(-1)),
+            ("b".to_string(), Object::list(vec![Object::bool(true)])),
+        ])
+    }
+
+    #[test]
+    fn framed_messages_round_trip() {
+        let framed = headpack_encode_framed(message());
+
+        assert_eq!(detect(&framed), Some(Version::CURRENT));
+        assert_eq!(
+            headpack_decode_framed(VecDeque::from(framed)),
+            Ok(message())
+        );
+    }
+
+    #[test]
+    fn malformed_framed_messages_are_errors() {
+        let raw = headpack_encode(message());
+        let framed = headpack_encode_framed(message());
+
+        let with_version = |version: u8| {
+            let mut framed = framed.clone();
+            framed[MAGIC.len()] = version;
+            framed
+        };
+
+        let cases = [
+            (raw, DecodeError::NotFramed),
+            (framed[..PREFIX_LENGTH - 1].to_vec(), DecodeError::NotFramed),
+            (framed[..PREFIX_LENGTH].to_vec(), DecodeError::Truncated),
+            (with_version(0), DecodeError::UnsupportedVersion(Version(0))),
+            (with_version(2), DecodeError::UnsupportedVersion(Version(2))),
+        ];
+
+        for (message, error) in cases {
+            assert_eq!(headpack_decode_framed(VecDeque::from(message)), Err(error));
+        }
+    }
+
+    #[test]
+    fn checksummed_messages_round_trip() {
+        let checksummed = headpack_encode_with_checksum(message());
+
+        assert_eq!(
+            headpack_decode_with_checksum(VecDeque::from(checksummed)),
+            Ok(message())
+        );
+    }
+
+    #[test]
+    fn malformed_checksummed_messages_are_errors() {
+        let mut flipped = headpack_encode_with_checksum(message());
+        flipped[0] ^= 1;
+        assert!(matches!(
+            headpack_decode_with_checksum(VecDeque::from(flipped)),
+            Err(DecodeError::ChecksumMismatch { .. })
+        ));
+
+        assert_eq!(
+            headpack_decode_with_checksum(VecDeque::from(vec![0; CHECKSUM_LENGTH - 1])),
+            Err(DecodeError::Truncated)
+        );
+
+        // a correct checksum over a message that doesn't decode
+        let mut truncated = headpack_encode(message());
+        truncated.pop();
+        truncated.extend(crc32c(&truncated).to_be_bytes());
+        assert_eq!(
+            headpack_decode_with_checksum(VecDeque::from(truncated)),
+            Err(DecodeError::Truncated)
+        );
+    }
+
+    fn schema() -> Schema {
+        Schema::from_json(&serde_json::json!({
+            "properties": {"a": {"type": "integer"}, "b": {"items": {"type": "boolean"}}},
+            "required": ["a"]
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn keyless_messages_round_trip() {
+        let keyless = headpack_encode_with_schema(message(), &schema()).unwrap();
+
+        assert!(keyless.len() < headpack_encode(message()).len());
+        assert_eq!(
+            headpack_decode_with_schema(VecDeque::from(keyless), &schema()),
+            Ok(message())
+        );
+    }
+
+    #[test]
+    fn malformed_keyless_messages_are_errors() {
+        let mut truncated = headpack_encode_with_schema(message(), &schema()).unwrap();
+        truncated.pop();
+        assert_eq!(
+            headpack_decode_with_schema(VecDeque::from(truncated), &schema()),
+            Err(SchemaMismatch::Decode(DecodeError::Truncated))
+        );
+
+        // a message with keys isn't a keyless one
+        assert_eq!(
+            headpack_decode_with_schema(VecDeque::from(headpack_encode(message())), &schema()),
+            Err(SchemaMismatch::Malformed(String::new()))
+        );
+
+        let invalid = Object::map(vec![("a".to_string(), Object::bool(true))]);
+        assert!(matches!(
+            headpack_encode_with_schema(invalid, &schema()),
+            Err(SchemaMismatch::Invalid(_))
+        ));
+    }
 }
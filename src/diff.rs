@@ -0,0 +1,254 @@
+use std::fmt::{self, Display, Formatter};
+
+use crate::object::{Object, Value};
+use crate::patch::PatchOp;
+use crate::pointer::escape_pointer_token;
+
+// a single difference between two objects, located by a JSON pointer
+#[derive(Clone, Debug, PartialEq)]
+pub enum Change {
+    Added {
+        path: String,
+        value: Object,
+    },
+    Removed {
+        path: String,
+        value: Object,
+    },
+    // same type, different value
+    ValueChanged {
+        path: String,
+        from: Object,
+        to: Object,
+    },
+    // different types, e.g. SInt to Float64, or UserDefined objects with different ids
+    TypeChanged {
+        path: String,
+        from: Object,
+        to: Object,
+    },
+}
+
+impl Change {
+    pub fn path(&self) -> &str {
+        match self {
+            Change::Added { path, .. }
+            | Change::Removed { path, .. }
+            | Change::ValueChanged { path, .. }
+            | Change::TypeChanged { path, .. } => path,
+        }
+    }
+
+    // a JSON Patch that turns the first object of the diff into the second
+    pub fn to_patch(changes: &[Change]) -> Vec<PatchOp> {
+        changes
+            .iter()
+            .map(|change| match change {
+                Change::Added { path, value } => PatchOp::Add {
+                    path: path.clone(),
+                    value: value.clone(),
+                },
+                Change::Removed { path, .. } => PatchOp::Remove { path: path.clone() },
+                Change::ValueChanged { path, to, .. } | Change::TypeChanged { path, to, .. } => {
+                    PatchOp::Replace {
+                        path: path.clone(),
+                        value: to.clone(),
+                    }
+                }
+            })
+            .collect()
+    }
+}
+
+impl Display for Change {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Added { path, value } => {
+                write!(f, "+ {}: {}", path, value.clone().into_json())
+            }
+            Change::Removed { path, value } => {
+                write!(f, "- {}: {}", path, value.clone().into_json())
+            }
+            Change::ValueChanged { path, from, to } => write!(
+                f,
+                "~ {}: {} -> {}",
+                path,
+                from.clone().into_json(),
+                to.clone().into_json()
+            ),
+            Change::TypeChanged { path, from, to } => write!(
+                f,
+                "! {}: {} {} -> {} {}",
+                path,
+                from.value.type_name(),
+                from.clone().into_json(),
+                to.value.type_name(),
+                to.clone().into_json()
+            ),
+        }
+    }
+}
+
+impl Object {
+    // every change needed to get from `a` to `b`, in an order that can be applied as a patch
+    pub fn diff(a: &Object, b: &Object) -> Vec<Change> {
+        let mut changes = Vec::new();
+        diff_into(a, b, String::new(), &mut changes);
+        changes
+    }
+}
+
+fn diff_into(a: &Object, b: &Object, path: String, changes: &mut Vec<Change>) {
+    match (&a.value, &b.value) {
+        (Value::Map(a_items), Value::Map(b_items)) => {
+            for (key, a_value) in a_items {
+                let key_path = format!("{}/{}", path, escape_pointer_token(key));
+
+                match b_items.iter().find(|(k, _)| k == key) {
+                    Some((_, b_value)) => diff_into(a_value, b_value, key_path, changes),
+                    None => changes.push(Change::Removed {
+                        path: key_path,
+                        value: a_value.clone(),
+                    }),
+                }
+            }
+
+            for (key, b_value) in b_items {
+                if !a_items.iter().any(|(k, _)| k == key) {
+                    changes.push(Change::Added {
+                        path: format!("{}/{}", path, escape_pointer_token(key)),
+                        value: b_value.clone(),
+                    });
+                }
+            }
+        }
+        (Value::List(a_elements), Value::List(b_elements)) => {
+            for (i, (a_element, b_element)) in a_elements.iter().zip(b_elements).enumerate() {
+                diff_into(a_element, b_element, format!("{}/{}", path, i), changes);
+            }
+
+            // remove from the back, so that earlier removals don't shift the later ones
+            for i in (b_elements.len()..a_elements.len()).rev() {
+                changes.push(Change::Removed {
+                    path: format!("{}/{}", path, i),
+                    value: a_elements[i].clone(),
+                });
+            }
+
+            for (i, b_element) in b_elements.iter().enumerate().skip(a_elements.len()) {
+                changes.push(Change::Added {
+                    path: format!("{}/{}", path, i),
+                    value: b_element.clone(),
+                });
+            }
+        }
+        _ if !same_type(&a.value, &b.value) => changes.push(Change::TypeChanged {
+            path,
+            from: a.clone(),
+            to: b.clone(),
+        }),
+        _ if !same_value(&a.value, &b.value) => changes.push(Change::ValueChanged {
+            path,
+            from: a.clone(),
+            to: b.clone(),
+        }),
+        _ => {}
+    }
+}
+
+fn same_type(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::UserDefined { id: a, .. }, Value::UserDefined { id: b, .. }) => a == b,
+        _ => a.type_name() == b.type_name(),
+    }
+}
+
+// only called for values of the same type that aren't collections
+fn same_value(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::String { string: a, .. }, Value::String { string: b, .. }) => a == b,
+        // compare floats bit for bit, so that NaN is equal to itself and 0.0 isn't equal to -0.0
+        (Value::Float32(a), Value::Float32(b)) => a.to_bits() == b.to_bits(),
+        (Value::Float64(a), Value::Float64(b)) => a.to_bits() == b.to_bits(),
+        _ => a == b,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::patch::json_equal;
+
+    #[test]
+    fn diffs_list_every_change() {
+        let a = Object::from_json(json!({"a": 1, "b": [1, 2, 3], "c": {"d": "x"}, "e": 0.5}));
+        let b = Object::from_json(json!({"a": 2, "b": [1], "c": {"d": "x", "f": null}, "e": 1}));
+
+        let changes: Vec<String> = Object::diff(&a, &b).iter().map(|c| c.to_string()).collect();
+
+        assert_eq!(
+            changes,
+            vec![
+                "~ /a: 1 -> 2",
+                "- /b/2: 3",
+                "- /b/1: 2",
+                "+ /c/f: null",
+                "! /e: Float32 0.5 -> SInt 1",
+            ]
+        );
+
+        assert_eq!(Object::diff(&a, &a), vec![]);
+    }
+
+    #[test]
+    fn floats_and_user_defined_ids_compare_exactly() {
+        let zero = Object::list(vec![Object::float64(0.0), Object::float64(f64::NAN)]);
+        let negative_zero = Object::list(vec![Object::float64(-0.0), Object::float64(f64::NAN)]);
+
+        assert_eq!(Object::diff(&zero, &zero), vec![]);
+        assert_eq!(
+            Object::diff(&zero, &negative_zero)
+                .iter()
+                .map(Change::path)
+                .collect::<Vec<_>>(),
+            vec!["/0"]
+        );
+
+        let user_defined = |id: u8| Object {
+            length: id as usize,
+            value: Value::UserDefined {
+                id,
+                data: vec![0; id as usize],
+            },
+        };
+
+        assert!(matches!(
+            Object::diff(&user_defined(39), &user_defined(40))[..],
+            [Change::TypeChanged { .. }]
+        ));
+    }
+
+    #[test]
+    fn diffs_apply_as_patches() {
+        let pairs = [
+            (
+                json!({"a": [1, 2, 3, 4]}),
+                json!({"a": [4], "b": {"c": [true]}}),
+            ),
+            (json!([1, {"a": 2}]), json!([1, {"a": 3, "a~/b": 4}, 5, 6])),
+            (json!({"a/b": {"~": 1}}), json!({"a/b": {"~": "1"}})),
+            (json!([1, 2]), json!({"x": 1})),
+        ];
+
+        for (a, b) in pairs {
+            let (mut a, b) = (Object::from_json(a), Object::from_json(b));
+
+            let patch = Change::to_patch(&Object::diff(&a, &b));
+            a.apply_patch(&patch).unwrap();
+
+            assert!(json_equal(&a, &b), "{:?} != {:?}", a, b);
+        }
+    }
+}
@@ -1,24 +1,107 @@
+use std::fmt::{self, Display, Formatter};
 use std::io::{self, Write};
 
-use crate::object::{self, Object, Value};
+use crate::checksum::crc32c;
+use crate::object::{
+    Object, Value, EXTENDED_KIND_RUN, EXTENDED_KIND_SINT_SEQ, EXTENDED_KIND_UINT_SEQ,
+    EXTENDED_LENGTH_BASE, FIRST_USER_DEFINED_ID, LAST_USER_DEFINED_ID, MAX_RUN_EXPANSION,
+};
+use crate::pointer::escape_pointer_token;
+use crate::schema::{Schema, SchemaMismatch};
+use crate::version::{write_prefix, Version};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EncodeError {
+    // the root is neither a map nor a list
+    InvalidRoot(&'static str),
+    // a UserDefined object the decoder would read back as something else, or not at all
+    InvalidUserDefined { path: String, id: u8, length: usize },
+}
+
+impl Display for EncodeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            EncodeError::InvalidRoot(type_name) => write!(
+                f,
+                "can't encode a {} on its own, messages have to be a Map or a List",
+                type_name
+            ),
+            EncodeError::InvalidUserDefined { path, id, length } => write!(
+                f,
+                "UserDefined at {} has id {} with {} bytes of data, ids go from {} to {} with as many bytes of data as the id",
+                if path.is_empty() { "the root" } else { path },
+                id,
+                length,
+                FIRST_USER_DEFINED_ID,
+                LAST_USER_DEFINED_ID
+            ),
+        }
+    }
+}
+
+impl std::error::Error for EncodeError {}
 
+impl From<EncodeError> for io::Error {
+    fn from(e: EncodeError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidInput, e)
+    }
+}
+
+/// panics if `root` can't be encoded, see `headpack_try_encode`
 pub fn headpack_encode(root: Object) -> Vec<u8> {
+    headpack_try_encode(root).unwrap_or_else(|e| panic!("{}", e))
+}
+
+// the same as `headpack_encode`, for objects that might not make a valid message,
+// e.g. ones built by hand
+pub fn headpack_try_encode(root: Object) -> Result<Vec<u8>, EncodeError> {
+    check_message(&root)?;
+
+    Ok(encode_checked(root))
+}
+
+// check that `root` can be encoded into a message that decodes back to it
+pub fn check_message(root: &Object) -> Result<(), EncodeError> {
+    match root.value {
+        Value::Map(_) | Value::List(_) => check_object(root, ""),
+        _ => Err(EncodeError::InvalidRoot(root.value.type_name())),
+    }
+}
+
+fn check_object(object: &Object, path: &str) -> Result<(), EncodeError> {
+    match &object.value {
+        Value::Map(items) => items.iter().try_for_each(|(key, value)| {
+            check_object(value, &format!("{}/{}", path, escape_pointer_token(key)))
+        }),
+        Value::List(elements) => elements
+            .iter()
+            .enumerate()
+            .try_for_each(|(i, element)| check_object(element, &format!("{}/{}", path, i))),
+        Value::UserDefined { id, data }
+            if !(FIRST_USER_DEFINED_ID..=LAST_USER_DEFINED_ID).contains(id)
+                || data.len() != *id as usize =>
+        {
+            Err(EncodeError::InvalidUserDefined {
+                path: path.to_string(),
+                id: *id,
+                length: data.len(),
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+// encode a message that `check_message` accepted
+fn encode_checked(root: Object) -> Vec<u8> {
     // output buffer
     let mut buf = Vec::new();
 
     let mut objects: Vec<Object> = Vec::new();
 
-    let is_root_map = {
-        if let object::Value::Map(_) = root.value {
-            true
-        } else if let object::Value::List(_) = root.value {
-            false
-        } else {
-            panic!("root must be map or list")
-        }
-    };
+    let is_root_map = matches!(root.value, Value::Map(_));
 
-    flatten_map_or_list(root, &mut objects);
+    let mut expansion = MAX_RUN_EXPANSION;
+    flatten_map_or_list(root, &mut objects, &mut expansion);
 
     write_classes_section(&objects, &mut buf, is_root_map);
 
@@ -29,54 +112,85 @@ pub fn headpack_encode(root: Object) -> Vec<u8> {
     buf
 }
 
-fn flatten_map_or_list(map_or_list: Object, into: &mut Vec<Object>) {
+// same as `headpack_encode`, but followed by a CRC-32C of the whole message in big endian
+pub fn headpack_encode_with_checksum(root: Object) -> Vec<u8> {
+    let mut buf = headpack_encode(root);
+
+    let checksum = crc32c(&buf);
+    buf.extend(checksum.to_be_bytes());
+
+    buf
+}
+
+// same as `headpack_encode`, but prefixed with the magic number and current format version
+pub fn headpack_encode_framed(root: Object) -> Vec<u8> {
+    let mut buf = Vec::new();
+
+    write_prefix(Version::CURRENT, &mut buf);
+    buf.extend(headpack_encode(root));
+
+    buf
+}
+
+// encode without map keys, both sides must agree on `schema` (see `Schema::strip_keys`)
+pub fn headpack_encode_with_schema(
+    root: Object,
+    schema: &Schema,
+) -> Result<Vec<u8>, SchemaMismatch> {
+    Ok(headpack_try_encode(schema.strip_keys(root)?)?)
+}
+
+// `expansion` is how much more the runs written so far could expand, see MAX_RUN_EXPANSION
+fn flatten_map_or_list(map_or_list: Object, into: &mut Vec<Object>, expansion: &mut usize) {
     match map_or_list.value {
         Value::Map(items) => {
             for (key, value_object) in items.into_iter() {
                 into.push(Object::key_string(key));
 
-                match value_object.value {
-                    Value::List(_) => {
-                        into.push(Object {
-                            value: Value::List(Vec::new()),
-                            length: value_object.length,
-                        });
-
-                        flatten_map_or_list(value_object, into);
-                    }
-                    Value::Map(_) => {
-                        into.push(Object {
-                            value: Value::Map(Vec::new()),
-                            length: value_object.length,
-                        });
-
-                        flatten_map_or_list(value_object, into);
-                    }
-                    _ => into.push(value_object),
-                }
+                flatten_object(pack_int_seq(value_object), into, expansion);
             }
         }
         Value::List(elements) => {
+            let mut elements = elements.into_iter().map(pack_int_seq).peekable();
+
+            while let Some(element) = elements.next() {
+                // count how many times this element repeats back to back
+                let mut count = 1;
+                while elements
+                    .peek()
+                    .is_some_and(|next| identical(next, &element))
+                {
+                    elements.next();
+                    count += 1;
+                }
 
-            for element in elements.into_iter() {
-                match element.value {
-                    Value::List(_) => {
-                        into.push(Object {
-                            value: Value::List(Vec::new()),
-                            length: element.length,
-                        });
+                let run = Object {
+                    value: Value::Run(count),
+                    length: 0,
+                };
 
-                        flatten_map_or_list(element, into);
-                    }
-                    Value::Map(_) => {
-                        into.push(Object {
-                            value: Value::Map(Vec::new()),
-                            length: element.length,
-                        });
+                let mut copies = count;
+
+                if count > 1 && (count - 1) * encoded_bits(&element) > header_bits(&run) {
+                    // runs inside the element count first, the same way the decoder counts them
+                    let start = into.len();
+                    into.push(run);
+                    flatten_object(element.clone(), into, expansion);
+
+                    let size = (count - 1).saturating_mul(element.expanded_size());
 
-                        flatten_map_or_list(element, into);
+                    if size <= *expansion {
+                        *expansion -= size;
+                        continue;
                     }
-                    _ => into.push(element),
+
+                    // the decoder would refuse this run, so the element is written out every time
+                    into.remove(start);
+                    copies -= 1;
+                }
+
+                for _ in 0..copies {
+                    flatten_object(element.clone(), into, expansion);
                 }
             }
         }
@@ -84,9 +198,138 @@ fn flatten_map_or_list(map_or_list: Object, into: &mut Vec<Object>) {
     }
 }
 
+fn flatten_object(object: Object, into: &mut Vec<Object>, expansion: &mut usize) {
+    match object.value {
+        Value::List(_) => {
+            into.push(Object {
+                value: Value::List(Vec::new()),
+                length: object.length,
+            });
+
+            flatten_map_or_list(object, into, expansion);
+        }
+        Value::Map(_) => {
+            into.push(Object {
+                value: Value::Map(Vec::new()),
+                length: object.length,
+            });
+
+            flatten_map_or_list(object, into, expansion);
+        }
+        _ => into.push(object),
+    }
+}
+
+// like ==, but floats only match when their bits do, so 0.0 and -0.0 aren't the same element
+fn identical(a: &Object, b: &Object) -> bool {
+    if a.length != b.length {
+        return false;
+    }
+
+    match (&a.value, &b.value) {
+        (Value::Float32(x), Value::Float32(y)) => x.to_bits() == y.to_bits(),
+        (Value::Float64(x), Value::Float64(y)) => x.to_bits() == y.to_bits(),
+        (Value::Map(x), Value::Map(y)) => {
+            x.len() == y.len()
+                && x.iter()
+                    .zip(y)
+                    .all(|((xk, xv), (yk, yv))| xk == yk && identical(xv, yv))
+        }
+        (Value::List(x), Value::List(y)) => {
+            x.len() == y.len() && x.iter().zip(y).all(|(x, y)| identical(x, y))
+        }
+        (x, y) => x == y,
+    }
+}
+
+// swap a list of integers for an IntSeq when that makes the message smaller
+fn pack_int_seq(list: Object) -> Object {
+    let elements = match &list.value {
+        Value::List(elements) if elements.len() >= 2 => elements,
+        _ => return list,
+    };
+
+    let packed = match int_seq_to_bytes(elements) {
+        Some(packed) => packed,
+        None => return list,
+    };
+
+    let signed = matches!(elements[0].value, Value::SInt(_));
+
+    let plain_bits = header_bits(&list)
+        + elements
+            .iter()
+            .map(|e| header_bits(e) + e.length * 8)
+            .sum::<usize>();
+
+    let seq = Object {
+        length: packed.len(),
+        value: Value::IntSeq {
+            signed,
+            elements: Vec::new(),
+        },
+    };
+
+    if header_bits(&seq) + packed.len() * 8 >= plain_bits {
+        return list;
+    }
+
+    match list.value {
+        Value::List(elements) => Object {
+            length: packed.len(),
+            value: Value::IntSeq { signed, elements },
+        },
+        _ => unreachable!(),
+    }
+}
+
+// every integer is stored as the zig-zag varint of its difference to the previous one (starting at 0)
+// returns None if the elements aren't all SInt or all UInt, or a difference overflows
+fn int_seq_to_bytes(elements: &[Object]) -> Option<Vec<u8>> {
+    let signed = matches!(elements.first()?.value, Value::SInt(_));
+
+    let mut buf = Vec::new();
+    let mut previous: i128 = 0;
+
+    for element in elements {
+        let n = match (&element.value, signed) {
+            (Value::SInt(i), true) => *i,
+            (Value::UInt(u), false) => i128::try_from(*u).ok()?,
+            _ => return None,
+        };
+
+        write_varint(zigzag(n.checked_sub(previous)?), &mut buf);
+        previous = n;
+    }
+
+    Some(buf)
+}
+
+// approximate size of an object's class and length in the header
+fn header_bits(object: &Object) -> usize {
+    2 + split_into_3_bit_chunks(length_code(object)).len() * 4
+}
+
+// approximate size of an object and everything inside it, header and data
+fn encoded_bits(object: &Object) -> usize {
+    let inner = match &object.value {
+        Value::Map(items) => items
+            .iter()
+            // keys have no class, only a length
+            .map(|(key, value)| {
+                split_into_3_bit_chunks(key.len()).len() * 4 + key.len() * 8 + encoded_bits(value)
+            })
+            .sum(),
+        Value::List(elements) => elements.iter().map(encoded_bits).sum(),
+        _ => object.length * 8,
+    };
+
+    header_bits(object) + inner
+}
+
 fn write_classes_section(objects: &[Object], data: &mut Vec<u8>, is_root_map: bool) {
     let objects: Vec<Object> = objects
-        .into_iter()
+        .iter()
         .filter(|o| {
             // filter out relevant objects only
             if let Value::String {
@@ -99,7 +342,7 @@ fn write_classes_section(objects: &[Object], data: &mut Vec<u8>, is_root_map: bo
                 true
             }
         })
-        .map(|o_ref| o_ref.clone())
+        .cloned()
         .collect();
 
     let mut len = objects.len();
@@ -214,6 +457,15 @@ pub fn uint_from_bytes(bytes: impl Iterator<Item = u8>) -> u128 {
 }
 
 pub fn sint_to_bytes(n: i128) -> Vec<u8> {
+    uint_to_bytes(zigzag(n))
+}
+
+pub fn sint_from_bytes(bytes: impl Iterator<Item = u8>) -> i128 {
+    unzigzag(uint_from_bytes(bytes))
+}
+
+// move the sign of a signed integer into its lowest bit, so that small negative numbers stay small
+pub fn zigzag(n: i128) -> u128 {
     let mut uint_n = (n.unsigned_abs()) << 1;
 
     if n < 0 {
@@ -224,21 +476,58 @@ pub fn sint_to_bytes(n: i128) -> Vec<u8> {
         uint_n &= !1;
     }
 
-    uint_to_bytes(uint_n)
+    uint_n
 }
 
-pub fn sint_from_bytes(bytes: impl Iterator<Item = u8>) -> i128 {
-    let u = uint_from_bytes(bytes) as u128;
-
+pub fn unzigzag(u: u128) -> i128 {
     // convert least significant bit to sign
     if u & 1 == 1 {
         let s = -((u >> 1) as i128);
-        return if s == 0 { i128::MIN } else { s };
+        if s == 0 {
+            i128::MIN
+        } else {
+            s
+        }
     } else {
-        return (u >> 1) as i128;
+        (u >> 1) as i128
     }
 }
 
+// 7 bits per byte, least significant group first, top bit set on every byte but the last
+pub fn write_varint(mut n: u128, buf: &mut Vec<u8>) {
+    loop {
+        let group = (n & 0x7f) as u8;
+        n >>= 7;
+
+        if n == 0 {
+            buf.push(group);
+            return;
+        }
+
+        buf.push(group | 0x80);
+    }
+}
+
+pub fn read_varint(bytes: &mut impl Iterator<Item = u8>) -> Option<u128> {
+    let mut n: u128 = 0;
+    let mut shift = 0;
+
+    for byte in bytes {
+        if shift >= 128 {
+            return None;
+        }
+
+        n |= ((byte & 0x7f) as u128) << shift;
+        shift += 7;
+
+        if byte & 0x80 == 0 {
+            return Some(n);
+        }
+    }
+
+    None
+}
+
 fn write_lengths_section(objects: &[Object], data: &mut Vec<u8>) {
     let mut chunks = Vec::with_capacity(objects.len());
 
@@ -264,33 +553,7 @@ fn write_lengths_section(objects: &[Object], data: &mut Vec<u8>) {
 
 fn write_length_chunks(objects: &[&Object], chunks: &mut Vec<u8>) {
     for object in objects {
-        let length = match &object.value {
-            // uint has a variable length but offset by 16
-            Value::UInt(_) => object.length + 16,
-            // special fixed-length objects
-            Value::Float32(_) => 33,
-            Value::Float64(_) => 34,
-            Value::Null => 35,
-            Value::Bool(b) => {
-                if *b {
-                    37
-                } else {
-                    36
-                }
-            }
-            Value::Timestamp32(_) => 38,
-            Value::UserDefined { id, data: _ } => *id as usize,
-            // variable-length objects
-            Value::Map(_) => {
-                object.length << 1 // set "is list" bit to 0
-            }
-            Value::List(_) => {
-                object.length << 1 | 1 // set "is list" bit to 1
-            }
-            _ => object.length,
-        };
-
-        for triplet in split_into_3_bit_chunks(length) {
+        for triplet in split_into_3_bit_chunks(length_code(object)) {
             let chunk = (triplet << 1) | 1;
             chunks.push(chunk);
         }
@@ -301,6 +564,46 @@ fn write_length_chunks(objects: &[&Object], chunks: &mut Vec<u8>) {
     }
 }
 
+// the number stored in the length section for an object, see `Object::from_class_and_length`
+fn length_code(object: &Object) -> usize {
+    match &object.value {
+        // uint has a variable length but offset by 16
+        // a UInt has at least one byte, length 0 would be read as an SInt 16 bytes long
+        Value::UInt(_) => object.length.max(1) + 16,
+        // special fixed-length objects
+        Value::Float32(_) => 33,
+        Value::Float64(_) => 34,
+        Value::Null => 35,
+        Value::Bool(b) => {
+            if *b {
+                37
+            } else {
+                36
+            }
+        }
+        Value::Timestamp32(_) => 38,
+        Value::UserDefined { id, data: _ } => *id as usize,
+        Value::IntSeq { signed, .. } => {
+            let kind = if *signed {
+                EXTENDED_KIND_SINT_SEQ
+            } else {
+                EXTENDED_KIND_UINT_SEQ
+            };
+
+            EXTENDED_LENGTH_BASE + (object.length << 2 | kind)
+        }
+        Value::Run(count) => EXTENDED_LENGTH_BASE + (count << 2 | EXTENDED_KIND_RUN),
+        // variable-length objects
+        Value::Map(_) => {
+            object.length << 1 // set "is list" bit to 0
+        }
+        Value::List(_) => {
+            object.length << 1 | 1 // set "is list" bit to 1
+        }
+        _ => object.length,
+    }
+}
+
 fn write_data(objects: impl Iterator<Item = Object>, buf: &mut Vec<u8>) -> io::Result<()> {
     for object in objects {
         match object.value {
@@ -308,28 +611,35 @@ fn write_data(objects: impl Iterator<Item = Object>, buf: &mut Vec<u8>) -> io::R
                 string,
                 encode_class: _,
             } => {
-                buf.write(string.as_bytes())?;
+                buf.write_all(string.as_bytes())?;
             }
             Value::Bytes(b) => {
-                buf.write(&b)?;
+                buf.write_all(&b)?;
             }
             Value::SInt(i) => {
-                buf.write(&sint_to_bytes(i))?;
+                buf.write_all(&sint_to_bytes(i))?;
             }
             Value::UInt(i) => {
-                buf.write(&uint_to_bytes(i))?;
+                let bytes = uint_to_bytes(i);
+                buf.write_all(if bytes.is_empty() { &[0] } else { &bytes })?;
             }
             Value::Float32(f) => {
-                buf.write(&f.to_be_bytes())?;
+                buf.write_all(&f.to_be_bytes())?;
             }
             Value::Float64(f) => {
-                buf.write(&f.to_be_bytes())?;
+                buf.write_all(&f.to_be_bytes())?;
             }
             Value::Timestamp32(t) => {
-                buf.write(&t.to_be_bytes())?;
+                buf.write_all(&t.to_be_bytes())?;
             }
             Value::UserDefined { id: _, data } => {
-                buf.write(&data)?;
+                buf.write_all(&data)?;
+            }
+            Value::IntSeq {
+                signed: _,
+                elements,
+            } => {
+                buf.write_all(&int_seq_to_bytes(&elements).expect("invalid IntSeq"))?;
             }
             // others need no data
             _ => {}
@@ -343,3 +653,115 @@ fn classes_join(a: u8, b: u8, c: u8, d: u8) -> u8 {
     // join 4 2-bit values into a byte
     ((a & 0b11) << 6) | ((b & 0b11) << 4) | ((c & 0b11) << 2) | (d & 0b11)
 }
+
+// a message made of objects exactly as they're given, for writing ones the encoder wouldn't
+#[cfg(test)]
+pub(crate) fn encode_flat(objects: Vec<Object>, is_root_map: bool) -> Vec<u8> {
+    let mut buf = Vec::new();
+
+    write_classes_section(&objects, &mut buf, is_root_map);
+    write_lengths_section(&objects, &mut buf);
+    write_data(objects.into_iter(), &mut buf).unwrap();
+
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+    use crate::decode::headpack_try_decode;
+
+    fn round_trip(object: Object) -> Object {
+        headpack_try_decode(VecDeque::from(headpack_encode(object))).unwrap()
+    }
+
+    fn float_bits(list: &Object) -> Vec<u64> {
+        match &list.value {
+            Value::List(elements) => elements
+                .iter()
+                .map(|e| match e.value {
+                    Value::Float64(f) => f.to_bits(),
+                    _ => panic!("not a Float64: {:?}", e),
+                })
+                .collect(),
+            _ => panic!("not a List: {:?}", list),
+        }
+    }
+
+    #[test]
+    fn signed_zeros_keep_their_sign_in_runs() {
+        for zeros in [[0.0, -0.0, -0.0, -0.0], [-0.0, 0.0, -0.0, 0.0]] {
+            let list = Object::list(zeros.iter().map(|z| Object::float64(*z)).collect());
+            let decoded = round_trip(Object::map(vec![("a".to_string(), list.clone())]));
+
+            match decoded.value {
+                Value::Map(items) => assert_eq!(float_bits(&items[0].1), float_bits(&list)),
+                _ => panic!("not a Map: {:?}", decoded),
+            }
+        }
+    }
+
+    fn user_defined(id: u8, length: usize) -> Object {
+        Object {
+            length,
+            value: Value::UserDefined {
+                id,
+                data: vec![0; length],
+            },
+        }
+    }
+
+    #[test]
+    fn integer_lists_round_trip() {
+        let sorted: Vec<_> = (0..100)
+            .map(|i| Object::uint(1_700_000_000 + i * 60))
+            .collect();
+        let lists = [
+            sorted.clone(),
+            (-50..50).map(|i| Object::sint(i * i * i)).collect(),
+            vec![Object::sint(i128::MIN), Object::sint(i128::MAX)],
+            vec![Object::uint(u128::MAX), Object::uint(0)],
+            vec![Object::uint(0)],
+            vec![Object::uint(0), Object::uint(0), Object::uint(0)],
+            vec![Object::uint(1), Object::sint(-1), Object::uint(2)],
+        ];
+
+        for list in lists {
+            let message = Object::map(vec![("a".to_string(), Object::list(list))]);
+            assert_eq!(round_trip(message.clone()), message);
+        }
+
+        // a sorted list is packed into deltas, which is smaller than writing every element
+        let packed = headpack_encode(Object::list(vec![Object::list(sorted.clone())]));
+        assert!(packed.len() < sorted.len() * 4, "{} bytes", packed.len());
+    }
+
+    #[test]
+    fn invalid_objects_are_encode_errors() {
+        assert_eq!(
+            headpack_try_encode(Object::sint(1)),
+            Err(EncodeError::InvalidRoot("SInt"))
+        );
+
+        let valid = Object::list(vec![user_defined(39, 39), user_defined(63, 63)]);
+        assert_eq!(round_trip(valid.clone()), valid);
+
+        for (id, length) in [(38, 38), (64, 64), (0, 0), (40, 39), (39, 40)] {
+            let message = Object::map(vec![(
+                "a/b".to_string(),
+                Object::list(vec![Object::null(), user_defined(id, length)]),
+            )]);
+
+            assert_eq!(
+                headpack_try_encode(message),
+                Err(EncodeError::InvalidUserDefined {
+                    path: "/a~1b/1".to_string(),
+                    id,
+                    length
+                })
+            );
+        }
+    }
+}
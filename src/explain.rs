@@ -0,0 +1,768 @@
+use std::collections::VecDeque;
+use std::fmt::Write;
+
+use crate::checksum::{crc32c, CHECKSUM_LENGTH};
+use crate::decode::{data_length, read_data, DecodeError, MAX_DEPTH};
+use crate::object::{Object, Value, ValueClass};
+use crate::pointer::escape_pointer_token;
+use crate::version::{detect, PREFIX_LENGTH};
+
+/*
+    An annotated dump of an encoded message, like the diagrams in the README but for real bytes.
+
+    Every byte of the CLASS section is split into its 2-bit fields, every 4-bit chunk of the
+    LENGTH section is shown with the length it adds up to and what `from_class_and_length` makes
+    of it, and the DATA section is cut into the bytes of each object, labelled with its path.
+    Offsets are in hex from the start of the input. The layout is for people, not for parsing.
+*/
+
+// how many bytes of an object's data are shown before the rest is left out
+const DATA_PREVIEW: usize = 8;
+
+#[derive(Clone, Copy)]
+enum Slot {
+    // a map key, named by its own contents
+    Key,
+    // the value of the key at this entry
+    Value(usize),
+    // a list element, or the first of several if a run repeats it
+    Element(usize),
+}
+
+enum ClassChunk {
+    // the first byte of an empty root map or list
+    Empty { root_map: bool },
+    // the first byte: flags, then 1 or 2 classes, then how many are in the next byte
+    First { count: u8, next: u8 },
+    // any later byte: up to 3 classes, then how many are in the next byte if all 3 are used
+    Next { count: u8, next: u8 },
+}
+
+struct Nibble {
+    offset: usize,
+    high: bool,
+    bits: u8,
+    continues: bool,
+}
+
+struct Entry {
+    object: Object,
+    // index into the classes, map keys don't have one
+    class: Option<usize>,
+    // the length as it was written, before `from_class_and_length` looked at it
+    raw_length: usize,
+    nibbles: Vec<Nibble>,
+    parent: Option<usize>,
+    slot: Slot,
+    data_offset: usize,
+}
+
+struct Parser<'a> {
+    data: &'a [u8],
+    position: usize,
+    classes: Vec<ValueClass>,
+    next_class: usize,
+    class_chunks: Vec<(usize, u8, ClassChunk)>,
+    // the low half of the last LENGTH byte, if it hasn't been used yet
+    pending: Option<Nibble>,
+    entries: Vec<Entry>,
+    // how many maps and lists the next object is in, the root's included
+    depth: usize,
+    // whether the next object is the one a run repeats
+    repeating: bool,
+}
+
+// describe every section of a bare or framed message, followed by a checksum if there is one
+pub fn explain(message: &[u8]) -> Result<String, DecodeError> {
+    let mut out = String::new();
+    let mut parser = Parser {
+        data: message,
+        position: 0,
+        classes: Vec::new(),
+        next_class: 0,
+        class_chunks: Vec::new(),
+        pending: None,
+        entries: Vec::new(),
+        depth: 1,
+        repeating: false,
+    };
+
+    if let Some(version) = detect(message) {
+        writeln!(out, "PREFIX: framed message, format version {}", version.0).unwrap();
+        writeln!(
+            out,
+            "  {:04x}  {}",
+            0,
+            hex_preview(&message[..PREFIX_LENGTH])
+        )
+        .unwrap();
+        out.push('\n');
+        parser.position = PREFIX_LENGTH;
+    }
+
+    let start = parser.position;
+    let root_map = parser.read_classes()?;
+    let lengths_start = parser.position;
+
+    if !matches!(parser.class_chunks[0].2, ClassChunk::Empty { .. }) {
+        parser.read_lengths(root_map)?;
+    }
+
+    let data_start = parser.position;
+    parser.read_data()?;
+    let end = parser.position;
+
+    let paths = parser.paths();
+
+    let mut class_paths = vec![String::new(); parser.classes.len()];
+    for (entry, path) in parser.entries.iter().zip(&paths) {
+        if let Some(class) = entry.class {
+            class_paths[class] = path.clone();
+        }
+    }
+
+    writeln!(
+        out,
+        "CLASS section: {}, {}",
+        plural(lengths_start - start, "byte"),
+        plural(parser.classes.len(), "class")
+    )
+    .unwrap();
+
+    let mut class = 0;
+    for (offset, byte, chunk) in &parser.class_chunks {
+        let fields = [
+            byte >> 6,
+            (byte >> 4) & 0b11,
+            (byte >> 2) & 0b11,
+            byte & 0b11,
+        ];
+
+        let (shown, text) = match *chunk {
+            ClassChunk::Empty { root_map } => (
+                4,
+                format!("an empty root {}, nothing follows", map_or_list(root_map)),
+            ),
+            ClassChunk::First { count, next } => {
+                let mut text = format!(
+                    "flags {:02b}: root is a {}, {} here",
+                    fields[0],
+                    map_or_list(root_map),
+                    plural(count as usize, "class")
+                );
+
+                for field in &fields[1..=count as usize] {
+                    text += &class_text(*field, &class_paths[class]);
+                    class += 1;
+                }
+
+                if count == 2 {
+                    text += &next_text(next);
+                }
+
+                (1 + count as usize + usize::from(count == 2), text)
+            }
+            ClassChunk::Next { count, next } => {
+                let mut text = String::new();
+
+                for field in &fields[..count as usize] {
+                    text += &class_text(*field, &class_paths[class]);
+                    class += 1;
+                }
+
+                if count == 3 {
+                    text += &next_text(next);
+                }
+
+                (count as usize + usize::from(count == 3), text)
+            }
+        };
+
+        let bits: Vec<String> = fields
+            .iter()
+            .enumerate()
+            .map(|(i, field)| match i < shown {
+                true => format!("{:02b}", field),
+                false => "..".to_string(),
+            })
+            .collect();
+
+        writeln!(
+            out,
+            "  {:04x}  {}  {}",
+            offset,
+            bits.join(" "),
+            text.trim_start_matches("; ")
+        )
+        .unwrap();
+    }
+
+    writeln!(
+        out,
+        "\nLENGTH section: {}, {}",
+        plural(data_start - lengths_start, "byte"),
+        plural(parser.entries.len(), "length")
+    )
+    .unwrap();
+
+    for (entry, path) in parser.entries.iter().zip(&paths) {
+        for (i, nibble) in entry.nibbles.iter().enumerate() {
+            let text = match i + 1 == entry.nibbles.len() {
+                true => format!(
+                    "= {:<5} {}: {}",
+                    entry.raw_length,
+                    label(entry, path),
+                    parser.length_text(entry)
+                ),
+                false => "continues".to_string(),
+            };
+
+            writeln!(out, "{}  {}", nibble_text(nibble), text).unwrap();
+        }
+    }
+
+    if let Some(nibble) = &parser.pending {
+        writeln!(out, "{}  unused", nibble_text(nibble)).unwrap();
+    }
+
+    writeln!(out, "\nDATA section: {}", plural(end - data_start, "byte")).unwrap();
+
+    for (entry, path) in parser.entries.iter().zip(&paths) {
+        if matches!(
+            entry.object.value,
+            Value::Map(_) | Value::List(_) | Value::Run(_)
+        ) {
+            continue;
+        }
+
+        let length = data_length(&entry.object);
+        let bytes = &message[entry.data_offset..entry.data_offset + length];
+
+        writeln!(
+            out,
+            "  {:04x}  {:<26}  {}: {}",
+            entry.data_offset,
+            hex_preview(bytes),
+            label(entry, path),
+            value_text(entry)
+        )
+        .unwrap();
+    }
+
+    let rest = &message[end..];
+
+    if !rest.is_empty() {
+        let text = match rest.len() == CHECKSUM_LENGTH {
+            true => {
+                let expected = u32::from_be_bytes(rest.try_into().unwrap());
+                let actual = crc32c(&message[start..end]);
+
+                match expected == actual {
+                    true => "a CRC-32C checksum of the message, which matches".to_string(),
+                    false => format!(
+                        "not a CRC-32C checksum of the message, that would be {:08x}",
+                        actual
+                    ),
+                }
+            }
+            false => "after the end of the message".to_string(),
+        };
+
+        writeln!(out, "\nTRAILER: {}, {}", plural(rest.len(), "byte"), text).unwrap();
+        writeln!(out, "  {:04x}  {}", end, hex_preview(rest)).unwrap();
+    }
+
+    Ok(out)
+}
+
+impl Parser<'_> {
+    fn next_byte(&mut self) -> Result<u8, DecodeError> {
+        let byte = *self.data.get(self.position).ok_or(DecodeError::Truncated)?;
+        self.position += 1;
+
+        Ok(byte)
+    }
+
+    // the same steps as `decode_classes_section`, remembering where each chunk was
+    fn read_classes(&mut self) -> Result<bool, DecodeError> {
+        let offset = self.position;
+        let first = self.next_byte()?;
+
+        if first & 0b1011_1111 == 0b0000_1100 {
+            let root_map = first & 0b0100_0000 != 0;
+            self.class_chunks
+                .push((offset, first, ClassChunk::Empty { root_map }));
+            return Ok(root_map);
+        }
+
+        let (flags, a, b, next) = (
+            first >> 6,
+            (first >> 4) & 0b11,
+            (first >> 2) & 0b11,
+            first & 0b11,
+        );
+        let root_map = flags & 0b01 != 0;
+
+        if flags & 0b10 == 0 {
+            self.classes.push(a.into());
+            self.class_chunks
+                .push((offset, first, ClassChunk::First { count: 1, next: 0 }));
+            return Ok(root_map);
+        }
+
+        self.classes.push(a.into());
+        self.classes.push(b.into());
+        self.class_chunks
+            .push((offset, first, ClassChunk::First { count: 2, next }));
+
+        let mut count = next;
+
+        while count > 0 {
+            let offset = self.position;
+            let byte = self.next_byte()?;
+            let fields = [
+                byte >> 6,
+                (byte >> 4) & 0b11,
+                (byte >> 2) & 0b11,
+                byte & 0b11,
+            ];
+
+            for field in &fields[..count as usize] {
+                self.classes.push((*field).into());
+            }
+
+            let next = if count == 3 { fields[3] } else { 0 };
+            self.class_chunks
+                .push((offset, byte, ClassChunk::Next { count, next }));
+
+            count = next;
+        }
+
+        Ok(root_map)
+    }
+
+    // walk the LENGTH section the way `decode_header` does
+    fn read_lengths(&mut self, root_map: bool) -> Result<(), DecodeError> {
+        let mut index = 0;
+
+        while self.next_class < self.classes.len() {
+            if root_map {
+                let key = self.push_key(None)?;
+                self.push_object(None, Slot::Value(key))?;
+            } else {
+                index += self.push_object(None, Slot::Element(index))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn next_length(&mut self) -> Result<(usize, Vec<Nibble>), DecodeError> {
+        let mut length = 0;
+        let mut nibbles = Vec::new();
+
+        loop {
+            let nibble = match self.pending.take() {
+                Some(nibble) => nibble,
+                None => {
+                    let offset = self.position;
+                    let byte = self.next_byte()?;
+
+                    self.pending = Some(Nibble {
+                        offset,
+                        high: false,
+                        bits: (byte >> 1) & 0b111,
+                        continues: byte & 1 != 0,
+                    });
+
+                    Nibble {
+                        offset,
+                        high: true,
+                        bits: byte >> 5,
+                        continues: (byte >> 4) & 1 != 0,
+                    }
+                }
+            };
+
+            length = (length << 3) | nibble.bits as usize;
+            let continues = nibble.continues;
+            nibbles.push(nibble);
+
+            if !continues {
+                return Ok((length, nibbles));
+            }
+        }
+    }
+
+    fn push_key(&mut self, parent: Option<usize>) -> Result<usize, DecodeError> {
+        let (length, nibbles) = self.next_length()?;
+
+        self.entries.push(Entry {
+            object: Object::sized_string(length),
+            class: None,
+            raw_length: length,
+            nibbles,
+            parent,
+            slot: Slot::Key,
+            data_offset: 0,
+        });
+
+        Ok(self.entries.len() - 1)
+    }
+
+    // returns how many list elements the object stands for, like `push_next_obj`
+    fn push_object(&mut self, parent: Option<usize>, slot: Slot) -> Result<usize, DecodeError> {
+        let (raw_length, nibbles) = self.next_length()?;
+
+        if self.next_class == self.classes.len() {
+            return Err(DecodeError::Malformed(
+                "the LENGTH section has more objects than the CLASS section".to_string(),
+            ));
+        }
+
+        let class = self.next_class;
+        self.next_class += 1;
+
+        let mut length = raw_length;
+        let object = Object::from_class_and_length(self.classes[class], &mut length)?;
+
+        // the same checks `push_next_obj` makes
+        let repeated = std::mem::take(&mut self.repeating);
+
+        match object.value {
+            Value::Run(_) if repeated => {
+                return Err(DecodeError::Malformed(
+                    "a run repeats another run".to_string(),
+                ));
+            }
+            Value::Run(_) if matches!(slot, Slot::Value(_)) => {
+                return Err(DecodeError::Malformed(
+                    "a run in a map, runs can only be in lists".to_string(),
+                ));
+            }
+            Value::Run(count) if count < 2 => {
+                return Err(DecodeError::Malformed(format!(
+                    "a run of {}, runs repeat their object at least twice",
+                    count
+                )));
+            }
+            Value::Map(_) | Value::List(_) if self.depth == MAX_DEPTH => {
+                return Err(DecodeError::Malformed(format!(
+                    "maps and lists nest more than {} deep",
+                    MAX_DEPTH
+                )));
+            }
+            _ => {}
+        }
+
+        self.entries.push(Entry {
+            object,
+            class: Some(class),
+            raw_length,
+            nibbles,
+            parent,
+            slot,
+            data_offset: 0,
+        });
+
+        let index = self.entries.len() - 1;
+
+        match self.entries[index].object.value {
+            Value::Map(_) => {
+                self.depth += 1;
+
+                for _ in 0..length {
+                    let key = self.push_key(Some(index))?;
+                    self.push_object(Some(index), Slot::Value(key))?;
+                }
+
+                self.depth -= 1;
+            }
+            Value::List(_) => {
+                let mut element = 0;
+                self.depth += 1;
+
+                while element < length {
+                    element += self
+                        .push_object(Some(index), Slot::Element(element))?
+                        .max(1);
+                }
+
+                self.depth -= 1;
+            }
+            Value::Run(count) => {
+                self.repeating = true;
+                self.push_object(parent, slot)?;
+                return Ok(count);
+            }
+            _ => {}
+        }
+
+        Ok(1)
+    }
+
+    fn read_data(&mut self) -> Result<(), DecodeError> {
+        for entry in &mut self.entries {
+            let length = data_length(&entry.object);
+            let bytes = self
+                .data
+                .get(self.position..self.position + length)
+                .ok_or(DecodeError::Truncated)?;
+
+            entry.data_offset = self.position;
+            self.position += length;
+
+            match entry.object.value {
+                Value::String { ref mut string, .. } => {
+                    *string = String::from_utf8(bytes.to_vec()).map_err(|_| {
+                        DecodeError::Malformed(format!(
+                            "invalid UTF-8 in the string at {:04x}",
+                            entry.data_offset
+                        ))
+                    })?;
+                }
+                _ => read_data(
+                    std::slice::from_mut(&mut entry.object),
+                    &mut VecDeque::from(bytes.to_vec()),
+                )?,
+            }
+        }
+
+        Ok(())
+    }
+
+    // every entry's path, parents always come before their children
+    fn paths(&self) -> Vec<String> {
+        let mut paths: Vec<String> = Vec::with_capacity(self.entries.len());
+
+        for entry in &self.entries {
+            let mut path = entry
+                .parent
+                .map(|parent| paths[parent].clone())
+                .unwrap_or_default();
+
+            let token = match entry.slot {
+                Slot::Key => string_of(&entry.object).to_string(),
+                Slot::Value(key) => string_of(&self.entries[key].object).to_string(),
+                Slot::Element(index) => index.to_string(),
+            };
+
+            path.push('/');
+            path += &escape_pointer_token(&token);
+            paths.push(path);
+        }
+
+        paths
+    }
+
+    fn length_text(&self, entry: &Entry) -> String {
+        let class = match entry.class {
+            Some(class) => self.classes[class],
+            None => return plural(entry.raw_length, "byte"),
+        };
+
+        let length = entry.object.length;
+
+        let meaning = match &entry.object.value {
+            Value::String { .. } | Value::Bytes(_) => plural(length, "byte"),
+            Value::Map(_) => format!("even, so a Map of {}", plural(length, "item")),
+            Value::List(_) => format!("odd, so a List of {}", plural(length, "element")),
+            Value::SInt(_) => format!("SInt, {}", plural(length, "byte")),
+            Value::UInt(_) => format!("UInt, {}", plural(length, "byte")),
+            Value::Float32(_) => "Float32, 4 bytes".to_string(),
+            Value::Float64(_) => "Float64, 8 bytes".to_string(),
+            Value::Null => "Null".to_string(),
+            Value::Bool(false) => "Bool false".to_string(),
+            Value::Bool(true) => "Bool true".to_string(),
+            Value::Timestamp32(_) => "Timestamp32, 4 bytes".to_string(),
+            Value::UserDefined { id, .. } => {
+                format!("UserDefined with id {}, {}", id, plural(length, "byte"))
+            }
+            Value::IntSeq { signed, .. } => format!(
+                "extended, a delta sequence of {}, {}",
+                if *signed { "SInts" } else { "UInts" },
+                plural(length, "byte")
+            ),
+            Value::Run(count) => format!(
+                "extended, a Run: the next object stands for {}",
+                plural(*count, "element")
+            ),
+        };
+
+        format!("{:?}, {}", class, meaning)
+    }
+}
+
+fn label(entry: &Entry, path: &str) -> String {
+    match entry.slot {
+        Slot::Key => format!("key of {}", path),
+        _ => path.to_string(),
+    }
+}
+
+fn value_text(entry: &Entry) -> String {
+    let value = match &entry.object.value {
+        Value::String { string, .. } => format!("{:?}", string),
+        Value::Bytes(bytes) => plural(bytes.len(), "byte"),
+        Value::Bool(b) => b.to_string(),
+        Value::SInt(i) => i.to_string(),
+        Value::UInt(u) => u.to_string(),
+        Value::Float32(f) => f.to_string(),
+        Value::Float64(f) => f.to_string(),
+        Value::Null => String::new(),
+        Value::Timestamp32(t) => t.to_string(),
+        Value::UserDefined { id, data } => format!("id {}, {}", id, plural(data.len(), "byte")),
+        Value::IntSeq { elements, .. } => {
+            let elements: Vec<String> = elements
+                .iter()
+                .map(|element| match element.value {
+                    Value::SInt(i) => i.to_string(),
+                    Value::UInt(u) => u.to_string(),
+                    _ => unreachable!(),
+                })
+                .collect();
+
+            format!("[{}]", elements.join(", "))
+        }
+        Value::Map(_) | Value::List(_) | Value::Run(_) => unreachable!(),
+    };
+
+    match entry.slot {
+        Slot::Key => value,
+        _ => format!("{} {}", entry.object.value.type_name(), value)
+            .trim_end()
+            .to_string(),
+    }
+}
+
+fn string_of(object: &Object) -> &str {
+    match &object.value {
+        Value::String { string, .. } => string,
+        _ => unreachable!("map keys are always strings"),
+    }
+}
+
+fn class_text(field: u8, path: &str) -> String {
+    format!("; {:?} {}", ValueClass::from(field), path)
+}
+
+fn next_text(next: u8) -> String {
+    match next {
+        0 => "; no more bytes".to_string(),
+        n => format!("; {} in the next byte", plural(n as usize, "class")),
+    }
+}
+
+fn nibble_text(nibble: &Nibble) -> String {
+    format!(
+        "  {:04x} {}  {:03b} {}",
+        nibble.offset,
+        if nibble.high { "hi" } else { "lo" },
+        nibble.bits,
+        nibble.continues as u8
+    )
+}
+
+fn map_or_list(root_map: bool) -> &'static str {
+    match root_map {
+        true => "Map",
+        false => "List",
+    }
+}
+
+fn hex_preview(bytes: &[u8]) -> String {
+    let shown: Vec<String> = bytes
+        .iter()
+        .take(DATA_PREVIEW)
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+
+    match bytes.len() > DATA_PREVIEW {
+        true => shown.join(" ") + " ..",
+        false => shown.join(" "),
+    }
+}
+
+fn plural(n: usize, noun: &str) -> String {
+    match (n, noun.ends_with('s')) {
+        (1, _) => format!("{} {}", n, noun),
+        (_, true) => format!("{} {}es", n, noun),
+        (_, false) => format!("{} {}s", n, noun),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode::{headpack_encode, headpack_encode_framed, headpack_encode_with_checksum};
+
+    fn message() -> Object {
+        Object::map(vec![
+            ("id".to_string(), Object::uint(300)),
+            ("a/b".to_string(), Object::string("x".to_string())),
+            ("ids".to_string(), Object::list(vec![Object::uint(1); 4])),
+            ("none".to_string(), Object::null()),
+        ])
+    }
+
+    #[test]
+    fn every_object_is_explained() {
+        let text = explain(&headpack_encode(message())).unwrap();
+
+        assert!(text.starts_with("CLASS section: "), "{}", text);
+        assert!(text.contains("root is a Map"), "{}", text);
+        assert!(text.contains("/id: UInt 300"), "{}", text);
+        assert!(text.contains("key of /a~1b: \"a/b\""), "{}", text);
+        assert!(text.contains("/a~1b: String \"x\""), "{}", text);
+        assert!(text.contains("/none: Null"), "{}", text);
+        assert!(!text.contains("TRAILER"), "{}", text);
+    }
+
+    #[test]
+    fn prefixes_and_trailers_are_explained() {
+        let framed = explain(&headpack_encode_framed(message())).unwrap();
+        assert!(framed.starts_with("PREFIX: framed message, format version 1"));
+
+        let checksummed = explain(&headpack_encode_with_checksum(message())).unwrap();
+        assert!(checksummed
+            .contains("TRAILER: 4 bytes, a CRC-32C checksum of the message, which matches"));
+
+        let mut corrupted = headpack_encode_with_checksum(message());
+        *corrupted.last_mut().unwrap() ^= 1;
+        assert!(explain(&corrupted)
+            .unwrap()
+            .contains("not a CRC-32C checksum of the message"));
+
+        let mut trailing = headpack_encode(message());
+        trailing.push(0);
+        assert!(explain(&trailing)
+            .unwrap()
+            .contains("TRAILER: 1 byte, after the end of the message"));
+
+        let empty = explain(&headpack_encode(Object::list(Vec::new()))).unwrap();
+        assert!(
+            empty.contains("an empty root List, nothing follows"),
+            "{}",
+            empty
+        );
+    }
+
+    #[test]
+    fn malformed_messages_are_errors() {
+        let message = headpack_encode(message());
+
+        for length in 0..message.len() {
+            assert!(explain(&message[..length]).is_err(), "cut at {}", length);
+        }
+
+        let mut invalid_utf8 = headpack_encode(Object::map(vec![(
+            "a".to_string(),
+            Object::string("é".to_string()),
+        )]));
+        *invalid_utf8.last_mut().unwrap() = 0xff;
+        assert!(matches!(
+            explain(&invalid_utf8),
+            Err(DecodeError::Malformed(_))
+        ));
+    }
+}
@@ -0,0 +1,434 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fs;
+use std::io::{self, Cursor, ErrorKind};
+use std::path::Path;
+
+use serde_json::json;
+
+use crate::container::{ContainerReader, CONTAINER_MAGIC};
+use crate::convert::FromJsonOptions;
+use crate::decode::{headpack_decode_framed, headpack_try_decode};
+use crate::encode::{sint_to_bytes, uint_to_bytes};
+use crate::lines::{LinesReader, RECORD_SEPARATOR};
+use crate::object::{Object, Value};
+use crate::schema::{Schema, SchemaType};
+use crate::version::detect;
+
+/*
+    Infers a schema from sample objects. Every position in the samples (the root, each map field and
+    the elements of each list) keeps track of what was seen there: how often each type turned up, how
+    many bytes its integers needed, how many distinct strings it held, and for map fields, how many of
+    the maps they were missing from.
+*/
+
+// past this many distinct strings, only the fact that there are more is kept
+pub const DISTINCT_STRINGS_LIMIT: usize = 32;
+
+#[derive(Clone, Debug, Default)]
+pub struct SchemaInference {
+    root: Observed,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Observed {
+    count: usize,
+    // by `Value::type_name`
+    types: BTreeMap<&'static str, usize>,
+    max_int_bytes: usize,
+    strings: BTreeSet<String>,
+    too_many_strings: bool,
+    // number of maps seen here, to tell which fields are optional
+    maps: usize,
+    // in the order they were first seen
+    fields: Vec<(String, Observed)>,
+    items: Option<Box<Observed>>,
+}
+
+impl SchemaInference {
+    pub fn new() -> Self {
+        SchemaInference::default()
+    }
+
+    pub fn add(&mut self, object: &Object) {
+        self.root.add(object);
+    }
+
+    // add every document in `data`, see `read_objects`, and return how many there were
+    pub fn add_bytes(&mut self, data: &[u8]) -> io::Result<usize> {
+        let objects = read_objects(data)?;
+
+        for object in &objects {
+            self.add(object);
+        }
+
+        Ok(objects.len())
+    }
+
+    pub fn add_file(&mut self, path: impl AsRef<Path>) -> io::Result<usize> {
+        self.add_bytes(&fs::read(path)?)
+    }
+
+    // number of samples added so far
+    pub fn len(&self) -> usize {
+        self.root.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.count == 0
+    }
+
+    pub fn schema(&self) -> Schema {
+        self.root.schema()
+    }
+
+    // the inferred schema, with a "description" summarizing what was seen at every position
+    // and the raw numbers under "observed", both of which `Schema::from_json` ignores
+    pub fn to_json(&self) -> serde_json::Value {
+        self.root.to_json()
+    }
+}
+
+impl Observed {
+    fn add(&mut self, object: &Object) {
+        self.count += 1;
+        *self.types.entry(object.value.type_name()).or_default() += 1;
+
+        match &object.value {
+            Value::SInt(i) => self.max_int_bytes = self.max_int_bytes.max(sint_to_bytes(*i).len()),
+            Value::UInt(u) => self.max_int_bytes = self.max_int_bytes.max(uint_to_bytes(*u).len()),
+            Value::String { string, .. }
+                if !self.too_many_strings && !self.strings.contains(string) =>
+            {
+                if self.strings.len() < DISTINCT_STRINGS_LIMIT {
+                    self.strings.insert(string.clone());
+                } else {
+                    self.too_many_strings = true;
+                }
+            }
+            Value::Map(items) => {
+                self.maps += 1;
+
+                for (key, value) in items {
+                    let i = match self.fields.iter().position(|(k, _)| k == key) {
+                        Some(i) => i,
+                        None => {
+                            self.fields.push((key.clone(), Observed::default()));
+                            self.fields.len() - 1
+                        }
+                    };
+
+                    self.fields[i].1.add(value);
+                }
+            }
+            Value::List(elements) => {
+                let items = self.items.get_or_insert_with(Default::default);
+
+                for element in elements {
+                    items.add(element);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn seen(&self, type_name: &str) -> bool {
+        self.types.contains_key(type_name)
+    }
+
+    fn schema_types(&self) -> Vec<SchemaType> {
+        let ints = self.seen("SInt") || self.seen("UInt");
+        let floats = self.seen("Float32") || self.seen("Float64");
+
+        let mut types = Vec::new();
+
+        for &name in self.types.keys() {
+            let schema_type = match name {
+                "Null" => SchemaType::Null,
+                "Bool" => SchemaType::Bool,
+                "String" => SchemaType::String,
+                "Bytes" => SchemaType::Bytes,
+                "Map" => SchemaType::Map,
+                "List" => SchemaType::List,
+                "Timestamp32" => SchemaType::Timestamp32,
+                "UserDefined" => SchemaType::UserDefined,
+                // a mix of integers and floats is just a number
+                _ if ints && floats => SchemaType::Number,
+                "SInt" if self.seen("UInt") => SchemaType::Integer,
+                "UInt" if self.seen("SInt") => SchemaType::Integer,
+                "SInt" => SchemaType::SInt,
+                "UInt" => SchemaType::UInt,
+                // a Float64 field can hold Float32s too
+                "Float32" if self.seen("Float64") => SchemaType::Float64,
+                "Float32" => SchemaType::Float32,
+                "Float64" => SchemaType::Float64,
+                _ => continue,
+            };
+
+            if !types.contains(&schema_type) {
+                types.push(schema_type);
+            }
+        }
+
+        types
+    }
+
+    fn schema(&self) -> Schema {
+        Schema {
+            types: self.schema_types(),
+            properties: self
+                .fields
+                .iter()
+                .map(|(k, field)| (k.clone(), field.schema()))
+                .collect(),
+            required: self
+                .fields
+                .iter()
+                .filter(|(_, field)| field.count == self.maps)
+                .map(|(k, _)| k.clone())
+                .collect(),
+            items: self
+                .items
+                .as_ref()
+                .filter(|items| items.count > 0)
+                .map(|items| Box::new(items.schema())),
+            ..Default::default()
+        }
+    }
+
+    // e.g. "SInt up to 2 bytes, sometimes Null"
+    fn describe(&self) -> String {
+        let mut types: Vec<(&str, usize)> = self.types.iter().map(|(k, v)| (*k, *v)).collect();
+        types.sort_by_key(|(_, count)| Reverse(*count));
+
+        let describe_type = |name: &str| match name {
+            "SInt" | "UInt" => format!(
+                "{} up to {} byte{}",
+                name,
+                self.max_int_bytes,
+                if self.max_int_bytes == 1 { "" } else { "s" }
+            ),
+            "String" if self.too_many_strings => format!(
+                "String with more than {} distinct values",
+                DISTINCT_STRINGS_LIMIT
+            ),
+            "String" => format!(
+                "String with {} distinct value{}",
+                self.strings.len(),
+                if self.strings.len() == 1 { "" } else { "s" }
+            ),
+            _ => name.to_string(),
+        };
+
+        let mut parts = Vec::new();
+
+        for (i, (name, count)) in types.iter().enumerate() {
+            parts.push(match i {
+                0 if *count == self.count => describe_type(name),
+                0 => format!("mostly {}", describe_type(name)),
+                _ => format!("sometimes {}", describe_type(name)),
+            });
+        }
+
+        parts.join(", ")
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        let mut json = self.schema().to_json();
+        let map = json.as_object_mut().unwrap();
+
+        let mut observed = serde_json::Map::new();
+        observed.insert("count".to_string(), json!(self.count));
+        observed.insert("types".to_string(), json!(self.types));
+
+        if self.max_int_bytes > 0 {
+            observed.insert("max_int_bytes".to_string(), json!(self.max_int_bytes));
+        }
+
+        if self.seen("String") {
+            observed.insert(
+                "distinct_strings".to_string(),
+                match self.too_many_strings {
+                    true => json!(format!("more than {}", DISTINCT_STRINGS_LIMIT)),
+                    false => json!(self.strings.len()),
+                },
+            );
+
+            if !self.too_many_strings {
+                observed.insert("strings".to_string(), json!(self.strings));
+            }
+        }
+
+        if self.maps > 0 {
+            observed.insert("maps".to_string(), json!(self.maps));
+        }
+
+        map.insert("description".to_string(), json!(self.describe()));
+        map.insert("observed".to_string(), serde_json::Value::Object(observed));
+
+        // replace the plain property and item schemas with annotated ones
+        if !self.fields.is_empty() {
+            let mut properties = serde_json::Map::new();
+
+            for (k, field) in &self.fields {
+                let mut property = field.to_json();
+
+                if field.count < self.maps {
+                    let description = format!(
+                        "{}, missing from {} of {} maps",
+                        field.describe(),
+                        self.maps - field.count,
+                        self.maps
+                    );
+                    property["description"] = json!(description);
+                }
+
+                properties.insert(k.clone(), property);
+            }
+
+            map.insert(
+                "properties".to_string(),
+                serde_json::Value::Object(properties),
+            );
+        }
+
+        if let Some(items) = self.items.as_ref().filter(|items| items.count > 0) {
+            map.insert("items".to_string(), items.to_json());
+        }
+
+        json
+    }
+}
+
+/*
+    Every document in `data`, whatever form it's in: a HeadPack container, HeadPack Lines,
+    a framed message, JSON (one document or many, e.g. NDJSON) or a single bare message.
+*/
+pub fn read_objects(data: &[u8]) -> io::Result<Vec<Object>> {
+    read_objects_with_options(data, &FromJsonOptions::default())
+}
+
+// the same, with JSON documents read according to `options`
+pub fn read_objects_with_options(
+    data: &[u8],
+    options: &FromJsonOptions,
+) -> io::Result<Vec<Object>> {
+    if data.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    if data.starts_with(&CONTAINER_MAGIC) {
+        let reader = ContainerReader::new(data)?;
+        return (0..reader.len()).filter_map(|i| reader.get(i)).collect();
+    }
+
+    if data.first() == Some(&RECORD_SEPARATOR) {
+        return LinesReader::new(Cursor::new(data))?.collect();
+    }
+
+    if detect(data).is_some() {
+        return headpack_decode_framed(VecDeque::from(data.to_vec()))
+            .map(|object| vec![object])
+            .map_err(|e| io::Error::new(ErrorKind::InvalidData, e));
+    }
+
+    let json: Result<Vec<serde_json::Value>, _> = serde_json::Deserializer::from_slice(data)
+        .into_iter()
+        .collect();
+
+    match json {
+        Ok(documents) if !documents.is_empty() => Ok(documents
+            .into_iter()
+            .map(|json| Object::from_json_with_options(json, options))
+            .collect()),
+        _ => headpack_try_decode(VecDeque::from(data.to_vec()))
+            .map(|object| vec![object])
+            .map_err(|e| io::Error::new(ErrorKind::InvalidData, e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode::{headpack_encode, headpack_encode_framed};
+
+    fn infer(samples: &[serde_json::Value]) -> SchemaInference {
+        let mut inference = SchemaInference::new();
+
+        for sample in samples {
+            inference.add(&Object::from_json(sample.clone()));
+        }
+
+        inference
+    }
+
+    #[test]
+    fn schemas_describe_the_samples() {
+        let inference = infer(&[
+            json!({"id": 1, "name": "a", "tags": [true]}),
+            json!({"id": -300, "name": "b", "score": 0.5}),
+            json!({"id": 2, "name": "a", "score": 1, "tags": []}),
+        ]);
+
+        assert_eq!(inference.len(), 3);
+        assert_eq!(
+            inference.schema().to_json(),
+            json!({
+                "type": "object",
+                "properties": {
+                    "id": {"type": "sint"},
+                    "name": {"type": "string"},
+                    "tags": {"type": "array", "items": {"type": "boolean"}},
+                    "score": {"type": "number"}
+                },
+                "required": ["id", "name"]
+            })
+        );
+
+        let json = inference.to_json();
+        assert_eq!(
+            json["properties"]["id"]["description"],
+            "SInt up to 2 bytes"
+        );
+        assert_eq!(
+            json["properties"]["name"]["description"],
+            "String with 2 distinct values"
+        );
+        assert_eq!(
+            json["properties"]["score"]["description"],
+            "mostly Float32, sometimes SInt up to 1 byte, missing from 1 of 3 maps"
+        );
+        assert_eq!(
+            Schema::from_json(&json).unwrap(),
+            inference.schema(),
+            "annotations are ignored when reading the schema back"
+        );
+    }
+
+    #[test]
+    fn documents_are_read_in_any_form() {
+        let object = Object::from_json(json!({"a": [1, 2]}));
+
+        for data in [
+            b"{\"a\": [1, 2]}".to_vec(),
+            headpack_encode_framed(object.clone()),
+            headpack_encode(object.clone()),
+        ] {
+            assert_eq!(read_objects(&data).unwrap(), vec![object.clone()]);
+        }
+
+        assert_eq!(read_objects(b"{}\n[]\n").unwrap().len(), 2);
+        assert_eq!(read_objects(b"").unwrap(), vec![]);
+    }
+
+    #[test]
+    fn malformed_documents_are_errors() {
+        let mut truncated = headpack_encode_framed(Object::from_json(json!({"a": [1, 2]})));
+        truncated.pop();
+
+        for data in [truncated, b"[".to_vec(), vec![0xff; 3]] {
+            let error = read_objects(&data).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidData, "{:?}", data);
+        }
+    }
+}
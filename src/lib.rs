@@ -0,0 +1,22 @@
+pub mod bench;
+pub mod cbor;
+pub mod checksum;
+pub mod codegen;
+pub mod compat;
+pub mod container;
+pub mod convert;
+pub mod decode;
+pub mod diff;
+pub mod encode;
+pub mod explain;
+pub mod infer;
+pub mod lines;
+pub mod msgpack;
+pub mod object;
+pub mod patch;
+pub mod pointer;
+pub mod query;
+pub mod schema;
+pub mod stream;
+pub mod verify;
+pub mod version;
@@ -0,0 +1,511 @@
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Seek, SeekFrom, Write};
+
+use crate::checksum::{crc32c, CHECKSUM_LENGTH};
+use crate::decode::headpack_try_decode;
+use crate::encode::{headpack_try_encode, write_varint};
+use crate::object::{Object, Value};
+
+/*
+    HeadPack Lines is the HeadPack equivalent of newline-delimited JSON:
+
+    file   = record* [index]
+    record = RECORD_SEPARATOR length(varint) message crc32c(message, 4 bytes big endian)
+    index  = offset(8 bytes big endian)* count(8 bytes big endian) INDEX_MAGIC
+
+    Every record starts with the ASCII record separator and carries its own checksum,
+    so a reader can skip a corrupt record by looking for the next separator that starts a valid one.
+    The index is optional and lists the byte offset of every record, so the Nth one can be found without scanning.
+*/
+
+pub const RECORD_SEPARATOR: u8 = 0x1e;
+
+pub const INDEX_MAGIC: [u8; 4] = [0x89, b'H', b'P', b'X'];
+
+// count plus magic
+const INDEX_FOOTER_LENGTH: u64 = 8 + INDEX_MAGIC.len() as u64;
+
+pub struct LinesWriter<W: Write> {
+    inner: W,
+    offsets: Vec<u64>,
+    position: u64,
+}
+
+impl<W: Write> LinesWriter<W> {
+    pub fn new(inner: W) -> Self {
+        LinesWriter {
+            inner,
+            offsets: Vec::new(),
+            position: 0,
+        }
+    }
+
+    pub fn write(&mut self, object: Object) -> io::Result<()> {
+        let message = headpack_try_encode(object)?;
+
+        let mut record = vec![RECORD_SEPARATOR];
+        write_varint(message.len() as u128, &mut record);
+        record.extend_from_slice(&message);
+        record.extend(crc32c(&message).to_be_bytes());
+
+        self.inner.write_all(&record)?;
+
+        self.offsets.push(self.position);
+        self.position += record.len() as u64;
+
+        Ok(())
+    }
+
+    // number of records written so far
+    pub fn len(&self) -> usize {
+        self.offsets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.offsets.is_empty()
+    }
+
+    // write the index and return the underlying writer
+    pub fn finish(mut self) -> io::Result<W> {
+        for offset in &self.offsets {
+            self.inner.write_all(&offset.to_be_bytes())?;
+        }
+
+        self.inner
+            .write_all(&(self.offsets.len() as u64).to_be_bytes())?;
+        self.inner.write_all(&INDEX_MAGIC)?;
+        self.inner.flush()?;
+
+        Ok(self.inner)
+    }
+
+    // return the underlying writer without writing an index
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+pub struct LinesReader<R: Read + Seek> {
+    inner: BufReader<R>,
+    // where the next record starts
+    position: u64,
+    // where the records end, which is where the index starts if there is one
+    end: u64,
+    index: Option<IndexInfo>,
+}
+
+#[derive(Clone, Copy)]
+struct IndexInfo {
+    start: u64,
+    count: u64,
+}
+
+impl<R: Read + Seek> LinesReader<R> {
+    pub fn new(inner: R) -> io::Result<Self> {
+        let mut inner = BufReader::new(inner);
+        let file_length = inner.seek(SeekFrom::End(0))?;
+
+        let index = read_index_footer(&mut inner, file_length)?;
+        let end = index.map(|i| i.start).unwrap_or(file_length);
+
+        inner.seek(SeekFrom::Start(0))?;
+
+        Ok(LinesReader {
+            inner,
+            position: 0,
+            end,
+            index,
+        })
+    }
+
+    pub fn has_index(&self) -> bool {
+        self.index.is_some()
+    }
+
+    // number of records according to the index, if there is one
+    pub fn len(&self) -> Option<usize> {
+        self.index.map(|i| i.count as usize)
+    }
+
+    pub fn is_empty(&self) -> Option<bool> {
+        self.len().map(|l| l == 0)
+    }
+
+    // position the reader so that the next record read is the Nth one, using the index
+    pub fn seek_record(&mut self, n: usize) -> io::Result<()> {
+        let index = self
+            .index
+            .ok_or_else(|| io::Error::new(ErrorKind::Unsupported, "file has no record index"))?;
+
+        if n as u64 >= index.count {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("record {} is out of range ({} records)", n, index.count),
+            ));
+        }
+
+        self.inner
+            .seek(SeekFrom::Start(index.start + n as u64 * 8))?;
+
+        let mut offset = [0; 8];
+        self.inner.read_exact(&mut offset)?;
+
+        self.position = u64::from_be_bytes(offset);
+        Ok(())
+    }
+
+    // read the message of the record at `offset`, or None if there isn't a valid record there
+    // on success, also returns the offset of the record after it
+    fn read_record_at(&mut self, offset: u64) -> io::Result<Option<(Vec<u8>, u64)>> {
+        match self.try_read_record_at(offset) {
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(None),
+            result => result,
+        }
+    }
+
+    fn try_read_record_at(&mut self, offset: u64) -> io::Result<Option<(Vec<u8>, u64)>> {
+        // seeking throws away the read buffer, so avoid it when reading records in order
+        if self.inner.stream_position()? != offset {
+            self.inner.seek(SeekFrom::Start(offset))?;
+        }
+
+        let mut byte = [0; 1];
+        self.inner.read_exact(&mut byte)?;
+
+        if byte[0] != RECORD_SEPARATOR {
+            return Ok(None);
+        }
+
+        let mut length: u64 = 0;
+        let mut shift = 0;
+
+        loop {
+            self.inner.read_exact(&mut byte)?;
+
+            if shift >= u64::BITS {
+                return Ok(None);
+            }
+
+            length |= ((byte[0] & 0x7f) as u64) << shift;
+            shift += 7;
+
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+        }
+
+        let header_length = self.inner.stream_position()? - offset;
+
+        let next = match (offset + header_length)
+            .checked_add(length)
+            .and_then(|n| n.checked_add(CHECKSUM_LENGTH as u64))
+        {
+            Some(next) if next <= self.end => next,
+            _ => return Ok(None),
+        };
+
+        let mut message = vec![0; length as usize];
+        self.inner.read_exact(&mut message)?;
+
+        let mut checksum = [0; CHECKSUM_LENGTH];
+        self.inner.read_exact(&mut checksum)?;
+
+        if u32::from_be_bytes(checksum) != crc32c(&message) {
+            return Ok(None);
+        }
+
+        Ok(Some((message, next)))
+    }
+
+    // look for the first valid record after `offset`
+    fn resync(&mut self, offset: u64) -> io::Result<u64> {
+        let mut candidate = offset + 1;
+
+        while candidate < self.end {
+            self.inner.seek(SeekFrom::Start(candidate))?;
+
+            // skip ahead to the next record separator
+            let buf = self.inner.fill_buf()?;
+            let available = buf.len().min((self.end - candidate) as usize);
+
+            if available == 0 {
+                break;
+            }
+
+            match buf[..available].iter().position(|b| *b == RECORD_SEPARATOR) {
+                Some(i) => candidate += i as u64,
+                None => {
+                    candidate += available as u64;
+                    continue;
+                }
+            }
+
+            if self.read_record_at(candidate)?.is_some() {
+                return Ok(candidate);
+            }
+
+            candidate += 1;
+        }
+
+        Ok(self.end)
+    }
+}
+
+impl<R: Read + Seek> Iterator for LinesReader<R> {
+    type Item = io::Result<Object>;
+
+    // a corrupt record comes out as an InvalidData error, and reading carries on from the next valid record
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.position >= self.end {
+            return None;
+        }
+
+        let offset = self.position;
+
+        let record = match self.read_record_at(offset) {
+            Ok(record) => record,
+            Err(e) => return Some(Err(e)),
+        };
+
+        match record {
+            Some((message, next)) => {
+                self.position = next;
+                Some(
+                    headpack_try_decode(VecDeque::from(message))
+                        .map_err(|e| io::Error::new(ErrorKind::InvalidData, e)),
+                )
+            }
+            None => {
+                self.position = match self.resync(offset) {
+                    Ok(next) => next,
+                    Err(e) => return Some(Err(e)),
+                };
+
+                Some(Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("corrupt record at offset {}", offset),
+                )))
+            }
+        }
+    }
+}
+
+fn read_index_footer<R: Read + Seek>(
+    inner: &mut R,
+    file_length: u64,
+) -> io::Result<Option<IndexInfo>> {
+    if file_length < INDEX_FOOTER_LENGTH {
+        return Ok(None);
+    }
+
+    inner.seek(SeekFrom::Start(file_length - INDEX_FOOTER_LENGTH))?;
+
+    let mut footer = [0; INDEX_FOOTER_LENGTH as usize];
+    inner.read_exact(&mut footer)?;
+
+    if footer[8..] != INDEX_MAGIC {
+        return Ok(None);
+    }
+
+    let count = u64::from_be_bytes(footer[..8].try_into().unwrap());
+
+    // make sure the index actually fits in the file, in case the magic appeared by accident
+    let index_length = match count.checked_mul(8) {
+        Some(l) if l <= file_length - INDEX_FOOTER_LENGTH => l,
+        _ => return Ok(None),
+    };
+
+    Ok(Some(IndexInfo {
+        start: file_length - INDEX_FOOTER_LENGTH - index_length,
+        count,
+    }))
+}
+
+// convert newline-delimited JSON into HeadPack Lines, returning the number of records
+// every line has to be a JSON object or array, blank lines are skipped
+pub fn ndjson_to_lines<R: BufRead, W: Write>(
+    input: R,
+    output: W,
+    with_index: bool,
+) -> io::Result<usize> {
+    let mut writer = LinesWriter::new(output);
+
+    for (i, line) in input.lines().enumerate() {
+        let line = line?;
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let json: serde_json::Value = serde_json::from_str(&line).map_err(|e| {
+            io::Error::new(ErrorKind::InvalidData, format!("line {}: {}", i + 1, e))
+        })?;
+
+        let object = Object::from_json(json);
+
+        if !matches!(object.value, Value::Map(_) | Value::List(_)) {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("line {}: must be an object or array", i + 1),
+            ));
+        }
+
+        writer.write(object)?;
+    }
+
+    let count = writer.len();
+
+    if with_index {
+        writer.finish()?;
+    } else {
+        writer.into_inner().flush()?;
+    }
+
+    Ok(count)
+}
+
+// convert HeadPack Lines into newline-delimited JSON, returning the number of records
+// corrupt records are skipped when `skip_corrupt` is set, otherwise they are an error
+pub fn lines_to_ndjson<R: Read + Seek, W: Write>(
+    input: R,
+    mut output: W,
+    skip_corrupt: bool,
+) -> io::Result<usize> {
+    let mut count = 0;
+
+    for object in LinesReader::new(input)? {
+        let object = match object {
+            Ok(object) => object,
+            Err(e) if skip_corrupt && e.kind() == ErrorKind::InvalidData => continue,
+            Err(e) => return Err(e),
+        };
+
+        serde_json::to_writer(&mut output, &object.into_json())?;
+        output.write_all(b"\n")?;
+        count += 1;
+    }
+
+    output.flush()?;
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn records() -> Vec<Object> {
+        (0..3)
+            .map(|i| Object::map(vec![("n".to_string(), Object::sint(i))]))
+            .collect()
+    }
+
+    // the file, and the offset of every record in it
+    fn write(with_index: bool) -> (Vec<u8>, Vec<u64>) {
+        let mut writer = LinesWriter::new(Vec::new());
+
+        for record in records() {
+            writer.write(record).unwrap();
+        }
+
+        let offsets = writer.offsets.clone();
+
+        match with_index {
+            true => (writer.finish().unwrap(), offsets),
+            false => (writer.into_inner(), offsets),
+        }
+    }
+
+    fn read(file: Vec<u8>) -> Vec<io::Result<Object>> {
+        LinesReader::new(Cursor::new(file)).unwrap().collect()
+    }
+
+    #[test]
+    fn lines_round_trip() {
+        for with_index in [true, false] {
+            let (file, _) = write(with_index);
+            let reader = LinesReader::new(Cursor::new(file.clone())).unwrap();
+
+            assert_eq!(reader.has_index(), with_index);
+            assert_eq!(reader.len(), with_index.then_some(3));
+
+            let read: Vec<_> = read(file).into_iter().map(Result::unwrap).collect();
+            assert_eq!(read, records());
+        }
+
+        let mut reader = LinesReader::new(Cursor::new(write(true).0)).unwrap();
+        reader.seek_record(2).unwrap();
+        assert_eq!(reader.next().unwrap().unwrap(), records()[2]);
+    }
+
+    #[test]
+    fn corrupt_records_are_skipped_with_an_error() {
+        let (mut corrupt, offsets) = write(true);
+
+        // the last byte of the second record's message
+        corrupt[offsets[2] as usize - CHECKSUM_LENGTH - 1] ^= 0xff;
+
+        let records_read = read(corrupt);
+        assert_eq!(records_read.len(), 3);
+        assert_eq!(records_read[0].as_ref().unwrap(), &records()[0]);
+        assert_eq!(
+            records_read[1].as_ref().unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+        assert_eq!(records_read[2].as_ref().unwrap(), &records()[2]);
+
+        // a file cut off in the middle of a record
+        let (mut truncated, _) = write(false);
+        truncated.truncate(truncated.len() - 2);
+
+        let records_read = read(truncated);
+        assert_eq!(records_read.len(), 3);
+        assert_eq!(
+            records_read[2].as_ref().unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn seeking_needs_an_index_and_a_record() {
+        let mut reader = LinesReader::new(Cursor::new(write(false).0)).unwrap();
+        assert_eq!(
+            reader.seek_record(0).unwrap_err().kind(),
+            ErrorKind::Unsupported
+        );
+
+        let mut reader = LinesReader::new(Cursor::new(write(true).0)).unwrap();
+        assert_eq!(
+            reader.seek_record(3).unwrap_err().kind(),
+            ErrorKind::InvalidInput
+        );
+    }
+
+    #[test]
+    fn ndjson_round_trips() {
+        let ndjson = "{\"a\":1}\n\n[true,null]\n{\"b\":\"x\"}\n";
+
+        let mut lines = Vec::new();
+        assert_eq!(
+            ndjson_to_lines(ndjson.as_bytes(), &mut lines, true).unwrap(),
+            3
+        );
+
+        let mut output = Vec::new();
+        assert_eq!(
+            lines_to_ndjson(Cursor::new(lines), &mut output, false).unwrap(),
+            3
+        );
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            ndjson.replace("\n\n", "\n")
+        );
+
+        for invalid in ["{\"a\":1}\n{", "{\"a\":1}\n1\n"] {
+            let e = ndjson_to_lines(invalid.as_bytes(), Vec::new(), false).unwrap_err();
+            assert_eq!(e.kind(), ErrorKind::InvalidData);
+            assert!(e.to_string().starts_with("line 2: "), "{}", e);
+        }
+    }
+}
@@ -1,181 +1,818 @@
-use std::{collections::VecDeque, f32::consts::PI};
-
-use decode::headpack_decode;
-use encode::headpack_encode;
-use object::Object;
-use serde_json::{json, Value};
-
-mod convert;
-mod decode;
-mod encode;
-mod object;
-
-fn main() {
-    // for benchmarking
-    let easy_as_pi = json!({"easy": true, "as": {"pi": PI }});
-
-    let messagepack_demo = json!({"compact": true, "schema": 0});
-
-    let twitter_json = json!({
-      "id": 1186275104,
-      "id_str": "1186275104",
-      "name": "AYUMI",
-      "screen_name": "ayuu0123",
-      "location": "",
-      "description": "元野球部マネージャー❤︎…最高の夏をありがとう…❤︎",
-      "url": null,
-      "entities": {
-        "description": {
-          "urls": []
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{self, Cursor, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+
+use mvencode::bench::{bench_corpus, BenchOptions};
+use mvencode::cbor::read_cbor_values;
+use mvencode::codegen::generate_rust;
+use mvencode::compat::{check_compatibility, check_keyless_compatibility, Direction};
+use mvencode::container::{ContainerReader, ContainerWriter};
+use mvencode::convert::{read_yaml_documents, FloatNarrowing, FromJsonOptions};
+use mvencode::decode::{
+    headpack_decode_framed, headpack_decode_with_checksum, headpack_try_decode,
+};
+use mvencode::encode::{
+    check_message, headpack_encode, headpack_encode_framed, headpack_encode_with_checksum,
+};
+use mvencode::infer::{read_objects_with_options, SchemaInference};
+use mvencode::lines::{LinesReader, LinesWriter};
+use mvencode::msgpack::read_msgpack_values;
+use mvencode::object::Object;
+use mvencode::schema::Schema;
+use mvencode::stream::{StreamReader, StreamWriter};
+use mvencode::verify::verify_corpus;
+
+/*
+    Exits with 0 on success, 1 when the command worked but the answer is "no"
+    (e.g. data that doesn't match a schema) and 2 when it couldn't do its job at all.
+*/
+
+#[derive(Parser)]
+#[command(
+    name = "headpack",
+    about = "Inspect, convert and describe HeadPack messages"
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Encode JSON or NDJSON as HeadPack
+    Encode {
+        /// A JSON or NDJSON file, or - for standard input
+        #[arg(default_value = "-")]
+        input: PathBuf,
+
+        /// How to write the messages
+        #[arg(long, value_enum, default_value_t = Format::Headpack)]
+        to: Format,
+
+        /// Which floats to store as Float32
+        #[arg(long, value_enum, default_value_t = Floats::Lossless)]
+        floats: Floats,
+
+        #[command(flatten)]
+        json: JsonOptions,
+
+        /// Where to write the messages, standard output by default
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Decode HeadPack messages as JSON
+    Decode {
+        /// A HeadPack file, or - for standard input
+        #[arg(default_value = "-")]
+        input: PathBuf,
+
+        /// How the messages were written, detected from the data if left out
+        #[arg(long, value_enum)]
+        from: Option<Format>,
+
+        /// Write every message on one line instead of pretty-printing it
+        #[arg(long)]
+        compact: bool,
+
+        /// Where to write the JSON, standard output by default
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Show what every byte of a message means
+    Explain {
+        /// A bare, framed or checksummed message, or - for standard input
+        #[arg(default_value = "-")]
+        input: PathBuf,
+
+        /// Where to write the explanation, standard output by default
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Convert messages from one format to another
+    Convert {
+        /// The file to convert, or - for standard input
+        #[arg(default_value = "-")]
+        input: PathBuf,
+
+        /// How the input was written, detected from the data if left out
+        #[arg(long, value_enum)]
+        from: Option<Format>,
+
+        /// How to write the output
+        #[arg(long, value_enum)]
+        to: Format,
+
+        /// Which floats to store as Float32, kept as they were read if left out
+        #[arg(long, value_enum)]
+        floats: Option<Floats>,
+
+        #[command(flatten)]
+        json: JsonOptions,
+
+        /// Write JSON messages on one line instead of pretty-printing them
+        #[arg(long)]
+        compact: bool,
+
+        /// Where to write the output, standard output by default
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Compare sizes and speeds of HeadPack and other formats over a corpus
+    Bench {
+        /// A JSON or NDJSON file, or a directory to search for them
+        corpus: PathBuf,
+
+        /// Also compare sizes after gzip compression
+        #[arg(long)]
+        gzip: bool,
+
+        /// Also compare sizes after zstd compression
+        #[arg(long)]
+        zstd: bool,
+
+        /// How many times to encode and decode every file when timing
+        #[arg(long, default_value_t = 10)]
+        iterations: usize,
+
+        /// Print CSV instead of a table
+        #[arg(long)]
+        csv: bool,
+
+        /// Where to write the results, standard output by default
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Check that every document in a corpus comes back unchanged from HeadPack
+    Verify {
+        /// A JSON or NDJSON file, or a directory to search for them
+        corpus: PathBuf,
+
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Infer a schema from sample documents
+    Infer {
+        /// JSON, NDJSON or HeadPack files, or - for standard input
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+
+        /// Leave out the descriptions of what was seen at every field
+        #[arg(long)]
+        plain: bool,
+
+        /// Where to write the schema, standard output by default
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Generate Rust types for a schema
+    Codegen {
+        /// A JSON schema, e.g. one written by `headpack infer`
+        schema: PathBuf,
+
+        /// Name of the type generated for the root of the schema
+        #[arg(short, long, default_value = "Message")]
+        name: String,
+
+        /// Where to write the code, standard output by default
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Check whether data written with one schema can be read with another
+    Compat {
+        /// The schema readers use now
+        old: PathBuf,
+
+        /// The schema it's changing to
+        new: PathBuf,
+
+        /// Treat OLD and NEW as sample documents and compare the schemas inferred from them
+        #[arg(long)]
+        samples: bool,
+
+        /// Check messages written without keys, where adding or removing any field breaks them
+        #[arg(long)]
+        keyless: bool,
+
+        /// Which compatibility to require
+        #[arg(long, value_enum, default_value_t = Compatibility::Both)]
+        require: Compatibility,
+
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+// how JSON values are turned into HeadPack types, on top of --floats
+#[derive(Args, Default)]
+struct JsonOptions {
+    /// Store non-negative integers as UInt instead of SInt
+    #[arg(long)]
+    uint: bool,
+
+    /// Store floats without a fractional part as integers
+    #[arg(long)]
+    integral_floats: bool,
+
+    /// Store strings that are valid base64 as Bytes
+    #[arg(long)]
+    detect_bytes: bool,
+
+    /// Store RFC 3339 date-times as Timestamp32 when they fit
+    #[arg(long)]
+    detect_timestamps: bool,
+
+    /// Leave out map entries that are null
+    #[arg(long)]
+    drop_nulls: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Format {
+    /// JSON documents, one after the other
+    Json,
+    /// JSON documents, one per line
+    Ndjson,
+    /// JSON documents with tags for the types plain JSON loses, so they convert back exactly
+    ExtendedJson,
+    /// A single bare message
+    Headpack,
+    /// A single message with a version prefix
+    Framed,
+    /// A single message followed by a CRC-32C
+    Checksum,
+    /// Length-prefixed messages
+    Stream,
+    /// Record-separated messages with an index
+    Lines,
+    /// A container file
+    Container,
+    /// MessagePack values, one after the other
+    Msgpack,
+    /// CBOR items, one after the other
+    Cbor,
+    /// YAML documents, separated by ---
+    Yaml,
+    /// A single TOML document
+    Toml,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Floats {
+    /// Only the ones that fit without losing precision
+    Lossless,
+    /// None of them
+    Never,
+    /// All of them, rounding the ones that don't fit
+    Always,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Compatibility {
+    /// New readers can read old data
+    Backward,
+    /// Old readers can read new data
+    Forward,
+    /// Both
+    Both,
+}
+
+fn main() -> ExitCode {
+    let result = match Cli::parse().command {
+        Command::Encode {
+            input,
+            to,
+            floats,
+            json,
+            output,
+        } => convert(
+            &input,
+            Some(Format::Json),
+            to,
+            Some(floats),
+            &json,
+            false,
+            output.as_deref(),
+        ),
+        Command::Decode {
+            input,
+            from,
+            compact,
+            output,
+        } => convert(
+            &input,
+            from,
+            Format::Json,
+            None,
+            &JsonOptions::default(),
+            compact,
+            output.as_deref(),
+        ),
+        Command::Explain { input, output } => explain(&input, output.as_deref()),
+        Command::Convert {
+            input,
+            from,
+            to,
+            floats,
+            json,
+            compact,
+            output,
+        } => convert(&input, from, to, floats, &json, compact, output.as_deref()),
+        Command::Bench {
+            corpus,
+            gzip,
+            zstd,
+            iterations,
+            csv,
+            output,
+        } => bench(
+            &corpus,
+            &BenchOptions {
+                gzip,
+                zstd,
+                iterations,
+            },
+            csv,
+            output.as_deref(),
+        ),
+        Command::Verify { corpus, json } => verify(&corpus, json),
+        Command::Infer {
+            files,
+            plain,
+            output,
+        } => infer(&files, plain, output.as_deref()),
+        Command::Codegen {
+            schema,
+            name,
+            output,
+        } => codegen(&schema, &name, output.as_deref()),
+        Command::Compat {
+            old,
+            new,
+            samples,
+            keyless,
+            require,
+            json,
+        } => compat(&old, &new, samples, keyless, require, json),
+    };
+
+    match result {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("headpack: {}", e);
+            ExitCode::from(2)
         }
-      },
-      "protected": false,
-      "followers_count": 262,
-      "friends_count": 252,
-      "listed_count": 0,
-      "created_at": "Sat Feb 16 13:40:25 +0000 2013",
-      "favourites_count": 235,
-      "utc_offset": null,
-      "time_zone": null,
-      "geo_enabled": false,
-      "verified": false,
-      "statuses_count": 1769,
-      "lang": "en",
-      "contributors_enabled": false,
-      "is_translator": false,
-      "is_translation_enabled": false,
-      "profile_background_color": "C0DEED",
-      "profile_background_image_url": "http://abs.twimg.com/images/themes/theme1/bg.png",
-      "profile_background_image_url_https": "https://abs.twimg.com/images/themes/theme1/bg.png",
-      "profile_background_tile": false,
-      "profile_image_url": "http://pbs.twimg.com/profile_images/497760886795153410/LDjAwR_y_normal.jpeg",
-      "profile_image_url_https": "https://pbs.twimg.com/profile_images/497760886795153410/LDjAwR_y_normal.jpeg",
-      "profile_banner_url": "https://pbs.twimg.com/profile_banners/1186275104/1409318784",
-      "profile_link_color": "0084B4",
-      "profile_sidebar_border_color": "C0DEED",
-      "profile_sidebar_fill_color": "DDEEF6",
-      "profile_text_color": "333333",
-      "profile_use_background_image": true,
-      "default_profile": true,
-      "default_profile_image": false,
-      "following": false,
-      "follow_request_sent": false,
-      "notifications": false
+    }
+}
+
+fn convert(
+    input: &Path,
+    from: Option<Format>,
+    to: Format,
+    floats: Option<Floats>,
+    json: &JsonOptions,
+    compact: bool,
+    output: Option<&Path>,
+) -> io::Result<ExitCode> {
+    let narrowing = floats.map(|floats| match floats {
+        Floats::Lossless => FloatNarrowing::Lossless,
+        Floats::Never => FloatNarrowing::Never,
+        Floats::Always => FloatNarrowing::Always,
     });
 
-    let dummyjson = json!(
-        {
-            "id": 10,
-            "title": "HP Pavilion 15-DK1056WM",
-            "description": "HP Pavilion 15-DK1056WM Gaming...",
-            "price": 1099,
-            "discountPercentage": 6.18,
-            "rating": 4.43,
-            "stock": 89,
-            "brand": "HP Pavilion",
-            "category": "laptops",
-            "thumbnail": "https://cdn.dummyjson.com/product-images/10/thumbnail.jpeg",
-            "images": [
-              "https://cdn.dummyjson.com/product-images/10/1.jpg",
-              "https://cdn.dummyjson.com/product-images/10/2.jpg",
-              "https://cdn.dummyjson.com/product-images/10/3.jpg",
-              "https://cdn.dummyjson.com/product-images/10/thumbnail.jpeg"
-            ]
-          }
-    );
-
-    let zling = json!(
-        {
-            "avatar": "/media/9ybevZcdBh-3Z2KRLBidT/avatar.png",
-            "bot": "false",
-            "email": "someone@example.com",
-            "id": "xoKM4W7NDqHjK_V0g9s3y",
-            "name": "someone#1234"
+    let options = FromJsonOptions {
+        prefer_uint: json.uint,
+        floats: narrowing.unwrap_or_default(),
+        integral_floats_as_ints: json.integral_floats,
+        base64_as_bytes: json.detect_bytes,
+        rfc3339_as_timestamps: json.detect_timestamps,
+        drop_nulls: json.drop_nulls,
+    };
+
+    let mut objects =
+        read_documents(&read_input(input)?, from, &options).map_err(|e| with_path(e, input))?;
+
+    // JSON was already read with these, but the other formats keep the widths they were written with
+    if let Some(narrowing) = narrowing {
+        for object in &mut objects {
+            object.narrow_floats(narrowing);
         }
-    );
-
-    let zling2 = json!(
-        [
-            {
-                "attachments": [
-                {
-                    "id": "s6NIiu2oOh1FEL0Xfjc7n",
-                    "name": "cat.jpg",
-                    "type": "image",
-                    "url": "/media/s6NIiu2oOh1FEL0Xfjc7n/cat.jpg"
-                }
-                ],
-                "author": {
-                "avatar": "/media/9ybevZcdBh-3Z2KRLBidT/avatar.png",
-                "id": "xoKM4W7NDqHjK_V0g9s3y",
-                "username": "someone#1234"
-                },
-                "content": "Good morning!",
-                "createdAt": "1970-01-01T00:00:00.000Z",
-                "id": "K1vqjuY8OqU0VO7oJlGpY"
-            },
-        ]
-    );
+    }
 
-    benchmark(easy_as_pi);
-    benchmark(messagepack_demo);
-    benchmark(twitter_json);
-    benchmark(dummyjson);
-    benchmark(zling);
-    benchmark(zling2);
+    write_output(output, &write_documents(objects, to, compact)?)?;
+    Ok(ExitCode::SUCCESS)
 }
 
-fn benchmark(json_object: Value) {
-    println!("-------------------------");
-    let js = json_object.clone().to_string().len();
-    print!("       JSON: {} bytes", js);
+// every message in `data`, read as `format` or as whatever it looks like
+fn read_documents(
+    data: &[u8],
+    format: Option<Format>,
+    options: &FromJsonOptions,
+) -> io::Result<Vec<Object>> {
+    let message = || VecDeque::from(data.to_vec());
+    let text = |data| std::str::from_utf8(data).map_err(invalid_data);
 
-    if js <= 200 {
-        println!(" {}", json_object.clone());
-    } else {
-        println!(" (too big to show)");
+    match format {
+        None => read_objects_with_options(data, options),
+        Some(Format::Json | Format::Ndjson) => serde_json::Deserializer::from_slice(data)
+            .into_iter()
+            .map(|json| Ok(Object::from_json_with_options(json?, options)))
+            .collect(),
+        Some(Format::ExtendedJson) => serde_json::Deserializer::from_slice(data)
+            .into_iter()
+            .map(|json| Object::from_extended_json(json?).map_err(invalid_data))
+            .collect(),
+        Some(Format::Headpack) => Ok(vec![headpack_try_decode(message()).map_err(invalid_data)?]),
+        Some(Format::Framed) => Ok(vec![
+            headpack_decode_framed(message()).map_err(invalid_data)?
+        ]),
+        Some(Format::Checksum) => Ok(vec![
+            headpack_decode_with_checksum(message()).map_err(invalid_data)?
+        ]),
+        Some(Format::Stream) => StreamReader::new(data).collect(),
+        Some(Format::Lines) => LinesReader::new(Cursor::new(data))?.collect(),
+        Some(Format::Container) => {
+            let reader = ContainerReader::new(data)?;
+            (0..reader.len()).filter_map(|i| reader.get(i)).collect()
+        }
+        Some(Format::Msgpack) => read_msgpack_values(data).map_err(invalid_data),
+        Some(Format::Cbor) => read_cbor_values(data).map_err(invalid_data),
+        Some(Format::Yaml) => read_yaml_documents(text(data)?).map_err(invalid_data),
+        Some(Format::Toml) => Ok(vec![Object::from_toml(text(data)?).map_err(invalid_data)?]),
+    }
+}
+
+fn write_documents(objects: Vec<Object>, format: Format, compact: bool) -> io::Result<Vec<u8>> {
+    if matches!(format, Format::Msgpack | Format::Cbor) {
+        let mut buf = Vec::new();
+
+        for object in objects {
+            match format {
+                Format::Msgpack => buf.extend(object.to_msgpack().map_err(invalid_data)?),
+                _ => buf.extend(object.to_cbor().map_err(invalid_data)?),
+            }
+        }
+
+        return Ok(buf);
     }
 
-    let mp = benchmark_messagepack(json_object.clone());
-    let hp = benchmark_headpack(json_object.clone());
+    if matches!(format, Format::Json | Format::Ndjson | Format::ExtendedJson) {
+        let mut text = String::new();
+
+        for object in objects {
+            let json = match format {
+                Format::ExtendedJson => object.into_extended_json(),
+                _ => object.into_json(),
+            };
+
+            match compact || format == Format::Ndjson {
+                true => text += &serde_json::to_string(&json)?,
+                false => text += &serde_json::to_string_pretty(&json)?,
+            }
+
+            text.push('\n');
+        }
+
+        return Ok(text.into_bytes());
+    }
 
-    let delta_mp = 100.0 - (hp as f32 / mp as f32 * 100.0);
-    println!("\nHeadPack is {:.2}% smaller than MessagePack", delta_mp);
+    if format == Format::Yaml {
+        let mut text = String::new();
 
-    let delta_js = 100.0 - (hp as f32 / js as f32 * 100.0);
-    println!("HeadPack is {:.2}% smaller than JSON", delta_js);
-    println!("-------------------------");
+        for (i, object) in objects.iter().enumerate() {
+            if i > 0 {
+                text += "---\n";
+            }
+
+            text += &object.to_yaml().map_err(invalid_data)?;
+        }
+
+        return Ok(text.into_bytes());
+    }
+
+    if format == Format::Toml {
+        let object = match <[Object; 1]>::try_from(objects) {
+            Ok([object]) => object,
+            Err(objects) => {
+                return Err(invalid_input(format!(
+                    "found {} documents, TOML can only hold one",
+                    objects.len()
+                )))
+            }
+        };
+
+        return Ok(object.to_toml().map_err(invalid_data)?.into_bytes());
+    }
+
+    for object in &objects {
+        check_message(object)?;
+    }
+
+    let single = |objects: Vec<Object>| match <[Object; 1]>::try_from(objects) {
+        Ok([object]) => Ok(object),
+        Err(objects) => Err(invalid_input(format!(
+            "found {} messages, use --to stream, lines or container to write more than one",
+            objects.len()
+        ))),
+    };
+
+    match format {
+        Format::Json
+        | Format::Ndjson
+        | Format::ExtendedJson
+        | Format::Msgpack
+        | Format::Cbor
+        | Format::Yaml
+        | Format::Toml => unreachable!(),
+        Format::Headpack => Ok(headpack_encode(single(objects)?)),
+        Format::Framed => Ok(headpack_encode_framed(single(objects)?)),
+        Format::Checksum => Ok(headpack_encode_with_checksum(single(objects)?)),
+        Format::Stream => {
+            let mut writer = StreamWriter::new(Vec::new());
+
+            for object in objects {
+                writer.write(object)?;
+            }
+
+            Ok(writer.into_inner())
+        }
+        Format::Lines => {
+            let mut writer = LinesWriter::new(Vec::new());
+
+            for object in objects {
+                writer.write(object)?;
+            }
+
+            writer.finish()
+        }
+        Format::Container => {
+            let mut writer = ContainerWriter::new(Cursor::new(Vec::new()))?;
+
+            for object in objects {
+                writer.write(object, None)?;
+            }
+
+            Ok(writer.finish()?.into_inner())
+        }
+    }
+}
+
+fn explain(input: &Path, output: Option<&Path>) -> io::Result<ExitCode> {
+    let text = mvencode::explain::explain(&read_input(input)?)
+        .map_err(|e| with_path(invalid_data(e), input))?;
+
+    write_output(output, text.as_bytes())?;
+    Ok(ExitCode::SUCCESS)
+}
+
+fn bench(
+    corpus: &Path,
+    options: &BenchOptions,
+    csv: bool,
+    output: Option<&Path>,
+) -> io::Result<ExitCode> {
+    let report = bench_corpus(corpus, options)?;
+
+    let text = match csv {
+        true => report.to_csv(),
+        false => report.to_table(),
+    };
+
+    write_output(output, text.as_bytes())?;
+    Ok(ExitCode::SUCCESS)
+}
+
+fn verify(corpus: &Path, json: bool) -> io::Result<ExitCode> {
+    let report = verify_corpus(corpus)?;
+
+    let text = match json {
+        true => serde_json::to_string_pretty(&report.to_json())? + "\n",
+        false => format!("{}\n", report),
+    };
+
+    write_output(None, text.as_bytes())?;
+
+    Ok(match report.is_ok() {
+        true => ExitCode::SUCCESS,
+        false => ExitCode::FAILURE,
+    })
+}
+
+fn infer(files: &[PathBuf], plain: bool, output: Option<&Path>) -> io::Result<ExitCode> {
+    let mut inference = SchemaInference::new();
+
+    for file in files {
+        inference
+            .add_bytes(&read_input(file)?)
+            .map_err(|e| with_path(e, file))?;
+    }
+
+    let schema = match plain {
+        true => inference.schema().to_json(),
+        false => inference.to_json(),
+    };
+
+    let mut text = serde_json::to_string_pretty(&schema)?;
+    text.push('\n');
+
+    write_output(output, text.as_bytes())?;
+    Ok(ExitCode::SUCCESS)
 }
 
-fn benchmark_headpack(json_object: Value) -> usize {
-    let object = Object::from_json(json_object.clone());
+fn codegen(schema: &Path, name: &str, output: Option<&Path>) -> io::Result<ExitCode> {
+    let code = generate_rust(&read_schema(schema)?, name).map_err(invalid_data)?;
 
-    let encoded = headpack_encode(object.clone());
+    write_output(output, code.as_bytes())?;
+    Ok(ExitCode::SUCCESS)
+}
+
+fn compat(
+    old: &Path,
+    new: &Path,
+    samples: bool,
+    keyless: bool,
+    require: Compatibility,
+    json: bool,
+) -> io::Result<ExitCode> {
+    let load = |path: &Path| match samples {
+        true => {
+            let mut inference = SchemaInference::new();
+            inference
+                .add_bytes(&read_input(path)?)
+                .map_err(|e| with_path(e, path))?;
+            Ok(inference.schema())
+        }
+        false => read_schema(path),
+    };
+
+    let report = match keyless {
+        true => check_keyless_compatibility(&load(old)?, &load(new)?),
+        false => check_compatibility(&load(old)?, &load(new)?),
+    };
+
+    let text = match json {
+        true => serde_json::to_string_pretty(&report.to_json())? + "\n",
+        false => {
+            let mut text = String::new();
+
+            for issue in &report.issues {
+                text += &format!("{}\n", issue);
+            }
+
+            text + &format!(
+                "backward compatible: {}, forward compatible: {}\n",
+                report.is_backward_compatible(),
+                report.is_forward_compatible()
+            )
+        }
+    };
+
+    write_output(None, text.as_bytes())?;
+
+    let direction = match require {
+        Compatibility::Backward => Direction::Backward,
+        Compatibility::Forward => Direction::Forward,
+        Compatibility::Both => Direction::Both,
+    };
 
-    let size = encoded.len();
-    println!("   HeadPack: {} bytes", size);
+    Ok(match report.is_compatible(direction) {
+        true => ExitCode::SUCCESS,
+        false => ExitCode::FAILURE,
+    })
+}
+
+fn read_schema(path: &Path) -> io::Result<Schema> {
+    let json: serde_json::Value =
+        serde_json::from_slice(&read_input(path)?).map_err(|e| with_path(e.into(), path))?;
+
+    Schema::from_json(&json).map_err(|e| with_path(invalid_data(e), path))
+}
+
+// the contents of `path`, or standard input if it's "-"
+fn read_input(path: &Path) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
 
-    let decoded = headpack_decode(VecDeque::from(encoded)).into_json();
+    if path == Path::new("-") {
+        io::stdin().read_to_end(&mut data)?;
+    } else {
+        data = fs::read(path).map_err(|e| with_path(e, path))?;
+    }
 
-    println!("expected: {}", json_object);
-    println!("     got: {}", decoded);
-    if json_object != decoded {
-        println!("ERROR: HeadPack failed to decode correctly! :(");
+    Ok(data)
+}
+
+// write to `path`, or standard output if there isn't one
+fn write_output(path: Option<&Path>, data: &[u8]) -> io::Result<()> {
+    match path {
+        Some(path) => fs::write(path, data).map_err(|e| with_path(e, path)),
+        None => io::stdout().write_all(data),
     }
+}
 
-    return size;
+fn invalid_data(e: impl std::error::Error + Send + Sync + 'static) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
 }
 
-fn benchmark_messagepack(json_object: Value) -> usize {
-    let encoded = rmp_serde::to_vec(&json_object).unwrap();
-    let size = encoded.len();
-    println!("MessagePack: {} bytes", size);
+fn invalid_input(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+fn with_path(e: io::Error, path: &Path) -> io::Error {
+    io::Error::new(e.kind(), format!("{}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::CommandFactory;
+    use serde_json::json;
+
+    use super::*;
+
+    fn documents(count: usize) -> Vec<Object> {
+        (0..count)
+            .map(|i| Object::from_json(json!({"id": i, "name": "x", "tags": [true, false]})))
+            .collect()
+    }
+
+    #[test]
+    fn arguments_are_consistent() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn every_format_round_trips() {
+        let options = FromJsonOptions::default();
 
-    let decoded: Value = rmp_serde::from_slice(&encoded).unwrap();
+        for format in Format::value_variants() {
+            let name = format.to_possible_value().unwrap().get_name().to_string();
+            let count = match format {
+                Format::Headpack | Format::Framed | Format::Checksum | Format::Toml => 1,
+                _ => 2,
+            };
 
-    if json_object != decoded {
-        println!("ERROR: MessagePack failed to decode correctly!");
+            let data = write_documents(documents(count), *format, false).unwrap();
+            let read = read_documents(&data, Some(*format), &options).unwrap();
+
+            // MessagePack and CBOR have no signed type for non-negative integers, so they come back as UInt
+            match format {
+                Format::Msgpack | Format::Cbor => {
+                    let json = |objects: Vec<Object>| -> Vec<_> {
+                        objects.into_iter().map(Object::into_json).collect()
+                    };
+                    assert_eq!(json(read), json(documents(count)), "--to {}", name);
+                    continue;
+                }
+                _ => assert_eq!(read, documents(count), "--to {}", name),
+            }
+
+            // and without being told what it is, for the formats that can be told apart
+            if !matches!(
+                format,
+                Format::Stream | Format::Msgpack | Format::Cbor | Format::Yaml | Format::Toml
+            ) {
+                let read = read_documents(&data, None, &options).unwrap();
+                assert_eq!(read, documents(count), "--to {} read as anything", name);
+            }
+        }
     }
 
-    return size;
+    #[test]
+    fn malformed_input_is_an_error() {
+        let options = FromJsonOptions::default();
+
+        for format in Format::value_variants() {
+            let name = format.to_possible_value().unwrap().get_name().to_string();
+            let mut data = write_documents(documents(1), *format, true).unwrap();
+            data.truncate(data.len() / 2);
+
+            assert!(
+                read_documents(&data, Some(*format), &options).is_err(),
+                "--from {}",
+                name
+            );
+        }
+
+        for format in [
+            Format::Headpack,
+            Format::Framed,
+            Format::Checksum,
+            Format::Toml,
+        ] {
+            let error = write_documents(documents(2), format, false).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        }
+    }
 }
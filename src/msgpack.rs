@@ -0,0 +1,650 @@
+use std::collections::VecDeque;
+use std::fmt::{self, Display, Formatter};
+
+use rmp::encode;
+use rmp::Marker;
+
+use crate::decode::{headpack_try_decode, DecodeError};
+use crate::encode::headpack_encode;
+use crate::object::{Object, Value, FIRST_USER_DEFINED_ID, LAST_USER_DEFINED_ID};
+use crate::pointer::escape_pointer_token;
+
+/*
+    MessagePack <-> HeadPack without going through serde_json::Value, which has no room for
+    binary data or extension types.
+
+    MessagePack                              HeadPack
+    nil, bool, str, bin, float 32, float 64  Null, Bool, String, Bytes, Float32, Float64
+    positive fixint, uint 8 to uint 64       UInt
+    negative fixint, int 8 to int 64         SInt
+    array, map (with str keys)               List, Map
+    timestamp extension (type -1)            Timestamp32, if it fits without nanoseconds
+    extension types 0 to 24                  UserDefined 39 to 63
+
+    Writing MessagePack picks the smallest encoding within an integer's own family, so SInts stay
+    SInts even when they aren't negative. Paths in errors are JSON pointers, the root is "".
+
+    HeadPack doesn't store the length of a UserDefined, readers have to know it from the id.
+    This decoder reads `id` bytes, so extension data can only be turned into a HeadPack message
+    if its length matches.
+*/
+
+pub const TIMESTAMP_EXT_TYPE: i8 = -1;
+
+// how deep arrays and maps can nest, the same limit serde_json has
+pub const MAX_DEPTH: usize = 128;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MsgpackError {
+    // the input ends in the middle of a value
+    Truncated,
+    // 0xc1, which MessagePack never uses
+    ReservedMarker { offset: usize },
+    // there's more input after the value
+    TrailingBytes { offset: usize },
+    InvalidUtf8 { path: String },
+    NonStringKey { path: String },
+    // a timestamp with nanoseconds or outside what a Timestamp32 can hold
+    UnsupportedTimestamp { path: String },
+    // an extension type that no UserDefined id stands for
+    UnsupportedExtType { path: String, ext_type: i8 },
+    // an integer that needs more than MessagePack's 64 bits
+    IntegerOutOfRange { path: String },
+    // a UserDefined id outside 39 to 63, which no extension type stands for
+    InvalidUserDefinedId { path: String, id: u8 },
+    // UserDefined data that isn't as long as HeadPack will read for its id
+    UserDefinedLength { path: String, id: u8, length: usize },
+    // HeadPack messages have to be a map or a list
+    NotAMessage { found: &'static str },
+    // arrays and maps nested more than MAX_DEPTH deep
+    TooDeep { path: String },
+    // the HeadPack message to convert doesn't decode
+    HeadPack(DecodeError),
+}
+
+impl Display for MsgpackError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let at = |path: &str| match path.is_empty() {
+            true => "at the root".to_string(),
+            false => format!("at {}", path),
+        };
+
+        match self {
+            MsgpackError::Truncated => write!(f, "MessagePack data is truncated"),
+            MsgpackError::ReservedMarker { offset } => {
+                write!(f, "reserved MessagePack marker 0xc1 at byte {}", offset)
+            }
+            MsgpackError::TrailingBytes { offset } => {
+                write!(f, "unexpected data after the MessagePack value at byte {}", offset)
+            }
+            MsgpackError::InvalidUtf8 { path } => write!(f, "invalid UTF-8 {}", at(path)),
+            MsgpackError::NonStringKey { path } => {
+                write!(f, "map key {} isn't a string", at(path))
+            }
+            MsgpackError::UnsupportedTimestamp { path } => write!(
+                f,
+                "timestamp {} doesn't fit in a Timestamp32, it needs whole seconds from 1970 to 2106",
+                at(path)
+            ),
+            MsgpackError::UnsupportedExtType { path, ext_type } => write!(
+                f,
+                "extension type {} {} has no UserDefined id, only types 0 to {} do",
+                ext_type,
+                at(path),
+                LAST_USER_DEFINED_ID - FIRST_USER_DEFINED_ID
+            ),
+            MsgpackError::IntegerOutOfRange { path } => {
+                write!(f, "integer {} doesn't fit in 64 bits", at(path))
+            }
+            MsgpackError::InvalidUserDefinedId { path, id } => write!(
+                f,
+                "UserDefined id {} {} is outside {} to {}",
+                id,
+                at(path),
+                FIRST_USER_DEFINED_ID,
+                LAST_USER_DEFINED_ID
+            ),
+            MsgpackError::UserDefinedLength { path, id, length } => write!(
+                f,
+                "UserDefined {} {} has {} bytes of data, HeadPack reads {} for that id",
+                id,
+                at(path),
+                length,
+                id
+            ),
+            MsgpackError::NotAMessage { found } => write!(
+                f,
+                "can't encode a {} on its own, messages have to be a Map or a List",
+                found
+            ),
+            MsgpackError::TooDeep { path } => write!(
+                f,
+                "arrays and maps nest more than {} deep {}",
+                MAX_DEPTH,
+                at(path)
+            ),
+            MsgpackError::HeadPack(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for MsgpackError {}
+
+impl From<DecodeError> for MsgpackError {
+    fn from(e: DecodeError) -> Self {
+        MsgpackError::HeadPack(e)
+    }
+}
+
+// MessagePack to a HeadPack message
+pub fn transcode_from_msgpack(data: &[u8]) -> Result<Vec<u8>, MsgpackError> {
+    let object = Object::from_msgpack(data)?;
+
+    if !matches!(object.value, Value::Map(_) | Value::List(_)) {
+        return Err(MsgpackError::NotAMessage {
+            found: object.value.type_name(),
+        });
+    }
+
+    check_user_defined(&object, "")?;
+
+    Ok(headpack_encode(object))
+}
+
+// a HeadPack message to MessagePack
+pub fn transcode_to_msgpack(message: &[u8]) -> Result<Vec<u8>, MsgpackError> {
+    headpack_try_decode(VecDeque::from(message.to_vec()))?.to_msgpack()
+}
+
+// every MessagePack value in `data`, one after the other
+pub fn read_msgpack_values(data: &[u8]) -> Result<Vec<Object>, MsgpackError> {
+    let mut reader = Reader::new(data);
+    let mut objects = Vec::new();
+
+    while reader.position < data.len() {
+        objects.push(reader.read("")?);
+    }
+
+    Ok(objects)
+}
+
+impl Object {
+    pub fn from_msgpack(data: &[u8]) -> Result<Self, MsgpackError> {
+        let mut reader = Reader::new(data);
+        let object = reader.read("")?;
+
+        if reader.position != data.len() {
+            return Err(MsgpackError::TrailingBytes {
+                offset: reader.position,
+            });
+        }
+
+        Ok(object)
+    }
+
+    pub fn to_msgpack(&self) -> Result<Vec<u8>, MsgpackError> {
+        let mut buf = Vec::new();
+        write(self, "", &mut buf)?;
+
+        Ok(buf)
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+    // how many arrays and maps the value being read is inside of
+    depth: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Reader {
+            data,
+            position: 0,
+            depth: 0,
+        }
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8], MsgpackError> {
+        let bytes = self
+            .data
+            .get(self.position..self.position + length)
+            .ok_or(MsgpackError::Truncated)?;
+
+        self.position += length;
+        Ok(bytes)
+    }
+
+    // a big endian unsigned number `width` bytes long
+    fn number(&mut self, width: usize) -> Result<u64, MsgpackError> {
+        Ok(self
+            .take(width)?
+            .iter()
+            .fold(0, |n, byte| n << 8 | *byte as u64))
+    }
+
+    fn read(&mut self, path: &str) -> Result<Object, MsgpackError> {
+        let offset = self.position;
+
+        let object = match Marker::from_u8(self.take(1)?[0]) {
+            Marker::Null => Object::null(),
+            Marker::True => Object::bool(true),
+            Marker::False => Object::bool(false),
+            Marker::FixPos(n) => Object::uint(n as u128),
+            Marker::U8 => Object::uint(self.number(1)? as u128),
+            Marker::U16 => Object::uint(self.number(2)? as u128),
+            Marker::U32 => Object::uint(self.number(4)? as u128),
+            Marker::U64 => Object::uint(self.number(8)? as u128),
+            Marker::FixNeg(n) => Object::sint(n as i128),
+            Marker::I8 => Object::sint(self.number(1)? as i8 as i128),
+            Marker::I16 => Object::sint(self.number(2)? as i16 as i128),
+            Marker::I32 => Object::sint(self.number(4)? as i32 as i128),
+            Marker::I64 => Object::sint(self.number(8)? as i64 as i128),
+            Marker::F32 => Object::float32(f32::from_bits(self.number(4)? as u32)),
+            Marker::F64 => Object::float64(f64::from_bits(self.number(8)?)),
+            Marker::FixStr(n) => self.string(n as usize, path)?,
+            Marker::Str8 => {
+                let length = self.number(1)? as usize;
+                self.string(length, path)?
+            }
+            Marker::Str16 => {
+                let length = self.number(2)? as usize;
+                self.string(length, path)?
+            }
+            Marker::Str32 => {
+                let length = self.number(4)? as usize;
+                self.string(length, path)?
+            }
+            Marker::Bin8 => {
+                let length = self.number(1)? as usize;
+                Object::bytes(self.take(length)?.to_vec())
+            }
+            Marker::Bin16 => {
+                let length = self.number(2)? as usize;
+                Object::bytes(self.take(length)?.to_vec())
+            }
+            Marker::Bin32 => {
+                let length = self.number(4)? as usize;
+                Object::bytes(self.take(length)?.to_vec())
+            }
+            Marker::FixArray(n) => self.list(n as usize, path)?,
+            Marker::Array16 => {
+                let length = self.number(2)? as usize;
+                self.list(length, path)?
+            }
+            Marker::Array32 => {
+                let length = self.number(4)? as usize;
+                self.list(length, path)?
+            }
+            Marker::FixMap(n) => self.map(n as usize, path)?,
+            Marker::Map16 => {
+                let length = self.number(2)? as usize;
+                self.map(length, path)?
+            }
+            Marker::Map32 => {
+                let length = self.number(4)? as usize;
+                self.map(length, path)?
+            }
+            Marker::FixExt1 => self.ext(1, path)?,
+            Marker::FixExt2 => self.ext(2, path)?,
+            Marker::FixExt4 => self.ext(4, path)?,
+            Marker::FixExt8 => self.ext(8, path)?,
+            Marker::FixExt16 => self.ext(16, path)?,
+            Marker::Ext8 => {
+                let length = self.number(1)? as usize;
+                self.ext(length, path)?
+            }
+            Marker::Ext16 => {
+                let length = self.number(2)? as usize;
+                self.ext(length, path)?
+            }
+            Marker::Ext32 => {
+                let length = self.number(4)? as usize;
+                self.ext(length, path)?
+            }
+            Marker::Reserved => return Err(MsgpackError::ReservedMarker { offset }),
+        };
+
+        Ok(object)
+    }
+
+    fn string(&mut self, length: usize, path: &str) -> Result<Object, MsgpackError> {
+        let string = String::from_utf8(self.take(length)?.to_vec()).map_err(|_| {
+            MsgpackError::InvalidUtf8 {
+                path: path.to_string(),
+            }
+        })?;
+
+        Ok(Object::string(string))
+    }
+
+    // go into an array or map, which has to be left again with `leave`
+    fn enter(&mut self, path: &str) -> Result<(), MsgpackError> {
+        if self.depth == MAX_DEPTH {
+            return Err(MsgpackError::TooDeep {
+                path: path.to_string(),
+            });
+        }
+
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn leave(&mut self) {
+        self.depth -= 1;
+    }
+
+    fn list(&mut self, length: usize, path: &str) -> Result<Object, MsgpackError> {
+        self.enter(path)?;
+
+        // every element takes at least a byte, so don't trust a length longer than what's left
+        let mut elements = Vec::with_capacity(length.min(self.data.len() - self.position));
+
+        for i in 0..length {
+            elements.push(self.read(&format!("{}/{}", path, i))?);
+        }
+
+        self.leave();
+        Ok(Object::list(elements))
+    }
+
+    fn map(&mut self, length: usize, path: &str) -> Result<Object, MsgpackError> {
+        self.enter(path)?;
+
+        let mut items = Vec::with_capacity(length.min(self.data.len() - self.position));
+
+        for _ in 0..length {
+            let key = match self.read(path)?.value {
+                Value::String { string, .. } => string,
+                _ => {
+                    return Err(MsgpackError::NonStringKey {
+                        path: path.to_string(),
+                    })
+                }
+            };
+
+            let value = self.read(&format!("{}/{}", path, escape_pointer_token(&key)))?;
+            items.push((key, value));
+        }
+
+        self.leave();
+        Ok(Object::map(items))
+    }
+
+    fn ext(&mut self, length: usize, path: &str) -> Result<Object, MsgpackError> {
+        let ext_type = self.take(1)?[0] as i8;
+        let data = self.take(length)?;
+
+        if ext_type == TIMESTAMP_EXT_TYPE {
+            let unsupported = || MsgpackError::UnsupportedTimestamp {
+                path: path.to_string(),
+            };
+
+            // timestamp 32 is just seconds, timestamp 64 has 30 bits of nanoseconds then 34 bits
+            // of seconds, timestamp 96 has 32 bits of nanoseconds then signed 64 bit seconds
+            let (nanoseconds, seconds) = match length {
+                4 => (0, u32::from_be_bytes(data.try_into().unwrap()) as i64),
+                8 => {
+                    let n = u64::from_be_bytes(data.try_into().unwrap());
+                    (n >> 34, (n & 0x3_ffff_ffff) as i64)
+                }
+                12 => (
+                    u32::from_be_bytes(data[..4].try_into().unwrap()) as u64,
+                    i64::from_be_bytes(data[4..].try_into().unwrap()),
+                ),
+                _ => return Err(unsupported()),
+            };
+
+            if nanoseconds != 0 {
+                return Err(unsupported());
+            }
+
+            return u32::try_from(seconds)
+                .map(Object::timestamp32)
+                .map_err(|_| unsupported());
+        }
+
+        if !(0..=(LAST_USER_DEFINED_ID - FIRST_USER_DEFINED_ID) as i8).contains(&ext_type) {
+            return Err(MsgpackError::UnsupportedExtType {
+                path: path.to_string(),
+                ext_type,
+            });
+        }
+
+        Ok(Object {
+            length: data.len(),
+            value: Value::UserDefined {
+                id: FIRST_USER_DEFINED_ID + ext_type as u8,
+                data: data.to_vec(),
+            },
+        })
+    }
+}
+
+fn write(object: &Object, path: &str, buf: &mut Vec<u8>) -> Result<(), MsgpackError> {
+    let out_of_range = || MsgpackError::IntegerOutOfRange {
+        path: path.to_string(),
+    };
+
+    // writing to a Vec can't fail
+    match &object.value {
+        Value::Null => encode::write_nil(buf).unwrap(),
+        Value::Bool(b) => encode::write_bool(buf, *b).unwrap(),
+        Value::UInt(u) => {
+            encode::write_uint(buf, u64::try_from(*u).map_err(|_| out_of_range())?).unwrap();
+        }
+        Value::SInt(i) => {
+            let i = i64::try_from(*i).map_err(|_| out_of_range())?;
+
+            // `write_sint` would write positive numbers as uints
+            match i {
+                -32..=-1 => encode::write_nfix(buf, i as i8).unwrap(),
+                _ if i8::try_from(i).is_ok() => encode::write_i8(buf, i as i8).unwrap(),
+                _ if i16::try_from(i).is_ok() => encode::write_i16(buf, i as i16).unwrap(),
+                _ if i32::try_from(i).is_ok() => encode::write_i32(buf, i as i32).unwrap(),
+                _ => encode::write_i64(buf, i).unwrap(),
+            }
+        }
+        Value::Float32(f) => encode::write_f32(buf, *f).unwrap(),
+        Value::Float64(f) => encode::write_f64(buf, *f).unwrap(),
+        Value::String { string, .. } => encode::write_str(buf, string).unwrap(),
+        Value::Bytes(bytes) => encode::write_bin(buf, bytes).unwrap(),
+        Value::Timestamp32(t) => {
+            encode::write_ext_meta(buf, 4, TIMESTAMP_EXT_TYPE).unwrap();
+            buf.extend_from_slice(&t.to_be_bytes());
+        }
+        Value::UserDefined { id, data } => {
+            if !(FIRST_USER_DEFINED_ID..=LAST_USER_DEFINED_ID).contains(id) {
+                return Err(MsgpackError::InvalidUserDefinedId {
+                    path: path.to_string(),
+                    id: *id,
+                });
+            }
+
+            encode::write_ext_meta(buf, data.len() as u32, (id - FIRST_USER_DEFINED_ID) as i8)
+                .unwrap();
+            buf.extend_from_slice(data);
+        }
+        Value::Map(items) => {
+            encode::write_map_len(buf, items.len() as u32).unwrap();
+
+            for (key, value) in items {
+                encode::write_str(buf, key).unwrap();
+                write(
+                    value,
+                    &format!("{}/{}", path, escape_pointer_token(key)),
+                    buf,
+                )?;
+            }
+        }
+        Value::List(elements) | Value::IntSeq { elements, .. } => {
+            encode::write_array_len(buf, elements.len() as u32).unwrap();
+
+            for (i, element) in elements.iter().enumerate() {
+                write(element, &format!("{}/{}", path, i), buf)?;
+            }
+        }
+        Value::Run(_) => unreachable!("runs only exist inside encoded messages"),
+    }
+
+    Ok(())
+}
+
+// HeadPack reads `id` bytes of data for a UserDefined, so anything else wouldn't survive
+fn check_user_defined(object: &Object, path: &str) -> Result<(), MsgpackError> {
+    match &object.value {
+        Value::UserDefined { id, data } if data.len() != *id as usize => {
+            Err(MsgpackError::UserDefinedLength {
+                path: path.to_string(),
+                id: *id,
+                length: data.len(),
+            })
+        }
+        Value::Map(items) => items.iter().try_for_each(|(key, value)| {
+            check_user_defined(value, &format!("{}/{}", path, escape_pointer_token(key)))
+        }),
+        Value::List(elements) => elements
+            .iter()
+            .enumerate()
+            .try_for_each(|(i, element)| check_user_defined(element, &format!("{}/{}", path, i))),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user_defined(id: u8, length: usize) -> Object {
+        Object {
+            length,
+            value: Value::UserDefined {
+                id,
+                data: vec![7; length],
+            },
+        }
+    }
+
+    // one of every type MessagePack can hold
+    fn message() -> Object {
+        Object::map(vec![
+            ("null".to_string(), Object::null()),
+            ("bool".to_string(), Object::bool(true)),
+            ("uint".to_string(), Object::uint(u64::MAX as u128)),
+            ("sint".to_string(), Object::sint(5)),
+            ("negative".to_string(), Object::sint(-70_000)),
+            ("float32".to_string(), Object::float32(0.5)),
+            ("float64".to_string(), Object::float64(0.1)),
+            ("string".to_string(), Object::string("é".to_string())),
+            ("bytes".to_string(), Object::bytes(vec![0, 255])),
+            ("timestamp".to_string(), Object::timestamp32(1_700_000_000)),
+            ("user".to_string(), user_defined(40, 40)),
+            (
+                "list".to_string(),
+                Object::list(vec![Object::map(Vec::new()), Object::list(Vec::new())]),
+            ),
+        ])
+    }
+
+    #[test]
+    fn objects_round_trip() {
+        let msgpack = message().to_msgpack().unwrap();
+        assert_eq!(Object::from_msgpack(&msgpack), Ok(message()));
+
+        let transcoded = transcode_from_msgpack(&msgpack).unwrap();
+        assert_eq!(transcode_to_msgpack(&transcoded), Ok(msgpack.clone()));
+
+        let values = read_msgpack_values(&[msgpack.clone(), msgpack].concat()).unwrap();
+        assert_eq!(values, vec![message(), message()]);
+    }
+
+    #[test]
+    fn other_encoders_are_understood() {
+        let json = serde_json::json!({"a": [1, -1, 300, "x", null, 2.5], "b": {"c": false}});
+        let msgpack = rmp_serde::to_vec(&json).unwrap();
+
+        assert_eq!(Object::from_msgpack(&msgpack).unwrap().into_json(), json);
+    }
+
+    #[test]
+    fn malformed_msgpack_is_an_error() {
+        let path = |p: &str| p.to_string();
+        let nested = [vec![0x91; MAX_DEPTH + 1], vec![0xc0]].concat();
+
+        for (data, error) in [
+            (vec![], MsgpackError::Truncated),
+            (vec![0x92, 0x01], MsgpackError::Truncated),
+            (vec![0xdc, 0xff, 0xff], MsgpackError::Truncated),
+            (vec![0x91, 0xc1], MsgpackError::ReservedMarker { offset: 1 }),
+            (vec![0xc0, 0xc0], MsgpackError::TrailingBytes { offset: 1 }),
+            (
+                vec![0x91, 0xa1, 0xff],
+                MsgpackError::InvalidUtf8 { path: path("/0") },
+            ),
+            (
+                vec![0x81, 0x01, 0x01],
+                MsgpackError::NonStringKey { path: path("") },
+            ),
+            (
+                vec![0x81, 0xa1, b'a', 0xd4, 0x7f, 0x00],
+                MsgpackError::UnsupportedExtType {
+                    path: path("/a"),
+                    ext_type: 127,
+                },
+            ),
+            (
+                vec![0xd7, 0xff, 0, 0, 0, 4, 0, 0, 0, 0],
+                MsgpackError::UnsupportedTimestamp { path: path("") },
+            ),
+            (
+                nested,
+                MsgpackError::TooDeep {
+                    path: path(&"/0".repeat(MAX_DEPTH)),
+                },
+            ),
+        ] {
+            assert_eq!(Object::from_msgpack(&data), Err(error), "{:02x?}", data);
+        }
+
+        assert_eq!(
+            transcode_from_msgpack(&[0x01]),
+            Err(MsgpackError::NotAMessage { found: "UInt" })
+        );
+        assert_eq!(
+            transcode_from_msgpack(&[0x91, 0xd4, 0x01, 0x00]),
+            Err(MsgpackError::UserDefinedLength {
+                path: path("/0"),
+                id: 40,
+                length: 1,
+            })
+        );
+    }
+
+    #[test]
+    fn objects_msgpack_cant_hold_are_errors() {
+        let list = |object| Object::list(vec![object]);
+
+        assert_eq!(
+            list(Object::uint(u64::MAX as u128 + 1)).to_msgpack(),
+            Err(MsgpackError::IntegerOutOfRange {
+                path: "/0".to_string()
+            })
+        );
+        assert_eq!(
+            list(user_defined(1, 1)).to_msgpack(),
+            Err(MsgpackError::InvalidUserDefinedId {
+                path: "/0".to_string(),
+                id: 1,
+            })
+        );
+    }
+
+    #[test]
+    fn malformed_headpack_messages_are_errors() {
+        assert!(matches!(
+            transcode_to_msgpack(&[0xff, 0xff, 0xff]),
+            Err(MsgpackError::HeadPack(DecodeError::Truncated))
+        ));
+    }
+}
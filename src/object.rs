@@ -1,5 +1,6 @@
 use std::fmt::{self, Debug, Formatter};
 
+use crate::decode::DecodeError;
 use crate::encode::{sint_to_bytes, uint_to_bytes};
 
 #[derive(Debug, Clone, Copy)]
@@ -23,7 +24,7 @@ impl From<u8> for ValueClass {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Value {
     String { string: String, encode_class: bool },
     Bytes(Vec<u8>),
@@ -40,8 +41,32 @@ pub enum Value {
     Timestamp32(u32),
 
     UserDefined { id: u8, data: Vec<u8> },
+
+    // a list of integers packed as zig-zag deltas, only ever chosen by the encoder
+    // the decoder turns this back into a regular list
+    IntSeq { signed: bool, elements: Vec<Object> },
+
+    // the next object in a list repeats this many times, only ever chosen by the encoder
+    // the decoder expands this back into the repeated elements
+    Run(usize),
 }
 
+// the ids a UserDefined object can have, the decoder reads as many bytes of data as the id
+pub const FIRST_USER_DEFINED_ID: u8 = 39;
+pub const LAST_USER_DEFINED_ID: u8 = 63;
+
+// fixed lengths from this value onwards are extended objects:
+// the lowest 2 bits (after subtracting the base) select the kind, the rest is the length
+pub const EXTENDED_LENGTH_BASE: usize = 64;
+
+pub const EXTENDED_KIND_SINT_SEQ: usize = 0;
+pub const EXTENDED_KIND_UINT_SEQ: usize = 1;
+pub const EXTENDED_KIND_RUN: usize = 2;
+
+// how much the runs in a message can add to it when they're expanded, in `expanded_size` units
+// a few bytes can ask for a run of any length, so this is what keeps decoding them in bounds
+pub const MAX_RUN_EXPANSION: usize = 1 << 24;
+
 impl Value {
     pub fn get_class_2bit(&self) -> u8 {
         match self {
@@ -58,7 +83,27 @@ impl Value {
             | Value::Float64(_)
             | Value::Null
             | Value::Timestamp32(_)
-            | Value::UserDefined { id: _, data: _ } => 0b11,
+            | Value::UserDefined { id: _, data: _ }
+            | Value::IntSeq { .. }
+            | Value::Run(_) => 0b11,
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::String { .. } => "String",
+            Value::Bytes(_) => "Bytes",
+            Value::Map(_) => "Map",
+            Value::List(_) | Value::IntSeq { .. } => "List",
+            Value::Bool(_) => "Bool",
+            Value::SInt(_) => "SInt",
+            Value::UInt(_) => "UInt",
+            Value::Float32(_) => "Float32",
+            Value::Float64(_) => "Float64",
+            Value::Null => "Null",
+            Value::Timestamp32(_) => "Timestamp32",
+            Value::UserDefined { .. } => "UserDefined",
+            Value::Run(_) => "Run",
         }
     }
 }
@@ -87,7 +132,7 @@ impl Value {
 //     }
 // }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub struct Object {
     pub value: Value,
     pub length: usize,
@@ -119,6 +164,24 @@ impl Object {
         self.value.get_class_2bit()
     }
 
+    // what a copy of this object costs: one for it and everything in it (map keys too),
+    // plus the bytes of every string, bytes and user defined object
+    pub fn expanded_size(&self) -> usize {
+        match &self.value {
+            Value::Map(items) => {
+                1 + items
+                    .iter()
+                    .map(|(key, value)| 1 + key.len() + value.expanded_size())
+                    .sum::<usize>()
+            }
+            Value::List(elements) | Value::IntSeq { elements, .. } => {
+                1 + elements.iter().map(Object::expanded_size).sum::<usize>()
+            }
+            Value::String { .. } | Value::Bytes(_) | Value::UserDefined { .. } => 1 + self.length,
+            _ => 1,
+        }
+    }
+
     pub fn string(s: String) -> Self {
         Object {
             length: s.len(),
@@ -186,7 +249,8 @@ impl Object {
 
     pub fn uint(u: u128) -> Self {
         Object {
-            length: uint_to_bytes(u).len(),
+            // 0 is written as one byte, see `length_code`
+            length: uint_to_bytes(u).len().max(1),
             value: Value::UInt(u),
         }
     }
@@ -224,8 +288,12 @@ impl Object {
         class: the ValueClass decoded from the classes section
         length: the length of the object, decoded from the length section
         WARNING: the length is a &mut reference because it SHOULD get updated by this function
+        fails for the one extended object kind that doesn't exist
     */
-    pub fn from_class_and_length(class: ValueClass, length: &mut usize) -> Self {
+    pub fn from_class_and_length(
+        class: ValueClass,
+        length: &mut usize,
+    ) -> Result<Self, DecodeError> {
         let value: Value = match class {
             ValueClass::String => Value::String {
                 string: String::new(),
@@ -234,12 +302,13 @@ impl Object {
             ValueClass::Bytes => Value::Bytes(Vec::new()),
             ValueClass::Collection => {
                 // check lower bit of length
+                // the length comes from the message, so it isn't trusted with an allocation
                 if *length & 1 == 1 {
                     *length >>= 1;
-                    Value::List(Vec::with_capacity(*length))
+                    Value::List(Vec::new())
                 } else {
                     *length >>= 1;
-                    Value::Map(Vec::with_capacity(*length))
+                    Value::Map(Vec::new())
                 }
             }
             ValueClass::Fixed => match length {
@@ -272,14 +341,43 @@ impl Object {
                     *length = 4;
                     Value::Timestamp32(0)
                 }
-                _ => Value::UserDefined {
+                39..=63 => Value::UserDefined {
                     id: *length as u8,
                     data: Vec::new(),
                 },
+                _ => {
+                    let extended = *length - EXTENDED_LENGTH_BASE;
+                    *length = extended >> 2;
+
+                    match extended & 0b11 {
+                        EXTENDED_KIND_SINT_SEQ => Value::IntSeq {
+                            signed: true,
+                            elements: Vec::new(),
+                        },
+                        EXTENDED_KIND_UINT_SEQ => Value::IntSeq {
+                            signed: false,
+                            elements: Vec::new(),
+                        },
+                        EXTENDED_KIND_RUN => {
+                            let count = *length;
+                            *length = 0;
+                            Value::Run(count)
+                        }
+                        _ => {
+                            return Err(DecodeError::Malformed(format!(
+                                "unknown extended object kind in length {}",
+                                extended + EXTENDED_LENGTH_BASE
+                            )))
+                        }
+                    }
+                }
             },
         };
 
-        Object { value, length: *length }
+        Ok(Object {
+            value,
+            length: *length,
+        })
     }
 }
 
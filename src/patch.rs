@@ -0,0 +1,508 @@
+use std::fmt::{self, Display, Formatter};
+
+use crate::object::{Object, Value};
+use crate::pointer::{list_index, resolve_mut, split_pointer, PointerError};
+
+/*
+    JSON Merge Patch (RFC 7386) and JSON Patch (RFC 6902) applied directly to Objects.
+    Paths are JSON Pointers (RFC 6901), e.g. "/author/avatar" or "/attachments/0".
+*/
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum PatchOp {
+    Add { path: String, value: Object },
+    Remove { path: String },
+    Replace { path: String, value: Object },
+    Move { from: String, path: String },
+    Copy { from: String, path: String },
+    Test { path: String, value: Object },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PatchError {
+    // the path isn't a valid JSON pointer
+    InvalidPointer(String),
+    // nothing exists at the path (or its parent, for add)
+    PathNotFound(String),
+    // a list index is not a number, or is out of range
+    InvalidIndex(String),
+    // a move would put a value inside of itself
+    MoveIntoChild(String),
+    // the value at the path isn't the one a test expected
+    TestFailed(String),
+    // an operation carried in an Object is malformed
+    InvalidOp(String),
+}
+
+impl Display for PatchError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            PatchError::InvalidPointer(p) => write!(f, "invalid JSON pointer {:?}", p),
+            PatchError::PathNotFound(p) => write!(f, "path {:?} does not exist", p),
+            PatchError::InvalidIndex(p) => write!(f, "invalid list index in {:?}", p),
+            PatchError::MoveIntoChild(p) => write!(f, "cannot move {:?} into itself", p),
+            PatchError::TestFailed(p) => write!(f, "test failed at {:?}", p),
+            PatchError::InvalidOp(reason) => write!(f, "invalid patch operation: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for PatchError {}
+
+impl From<PointerError> for PatchError {
+    fn from(e: PointerError) -> Self {
+        match e {
+            PointerError::InvalidPointer(p) => PatchError::InvalidPointer(p),
+            PointerError::InvalidIndex(p) => PatchError::InvalidIndex(p),
+            PointerError::NotFound(p) => PatchError::PathNotFound(p),
+        }
+    }
+}
+
+impl Object {
+    // RFC 7386: maps are merged key by key, a Null value removes the key, anything else replaces the target
+    pub fn apply_merge_patch(&mut self, patch: &Object) {
+        let patch_items = match &patch.value {
+            Value::Map(items) => items,
+            _ => {
+                *self = patch.clone();
+                return;
+            }
+        };
+
+        if !matches!(self.value, Value::Map(_)) {
+            *self = Object::map(Vec::new());
+        }
+
+        if let Value::Map(ref mut items) = self.value {
+            for (key, patch_value) in patch_items {
+                let existing = items.iter().position(|(k, _)| k == key);
+
+                match (existing, &patch_value.value) {
+                    (Some(i), Value::Null) => {
+                        items.remove(i);
+                    }
+                    (None, Value::Null) => {}
+                    (Some(i), _) => items[i].1.apply_merge_patch(patch_value),
+                    (None, _) => {
+                        let mut value = Object::null();
+                        value.apply_merge_patch(patch_value);
+                        items.push((key.clone(), value));
+                    }
+                }
+            }
+
+            self.length = items.len();
+        }
+    }
+
+    // RFC 6902: either every operation applies, or the object is left untouched
+    pub fn apply_patch(&mut self, ops: &[PatchOp]) -> Result<(), PatchError> {
+        let mut patched = self.clone();
+
+        for op in ops {
+            patched.apply_patch_op(op)?;
+        }
+
+        *self = patched;
+        Ok(())
+    }
+
+    fn apply_patch_op(&mut self, op: &PatchOp) -> Result<(), PatchError> {
+        match op {
+            PatchOp::Add { path, value } => add(self, path, value.clone()),
+            PatchOp::Remove { path } => remove(self, path).map(|_| ()),
+            PatchOp::Replace { path, value } => {
+                let target = resolve_mut(self, path)?;
+                *target = value.clone();
+                Ok(())
+            }
+            PatchOp::Move { from, path } => {
+                if path.starts_with(&format!("{}/", from)) {
+                    return Err(PatchError::MoveIntoChild(from.clone()));
+                }
+
+                let value = remove(self, from)?;
+                add(self, path, value)
+            }
+            PatchOp::Copy { from, path } => {
+                let value = resolve_mut(self, from)?.clone();
+                add(self, path, value)
+            }
+            PatchOp::Test { path, value } => {
+                if json_equal(resolve_mut(self, path)?, value) {
+                    Ok(())
+                } else {
+                    Err(PatchError::TestFailed(path.clone()))
+                }
+            }
+        }
+    }
+}
+
+impl PatchOp {
+    // carry a patch as a list of maps, the same shape RFC 6902 gives it in JSON
+    pub fn to_object(ops: &[PatchOp]) -> Object {
+        let mut list = Vec::with_capacity(ops.len());
+
+        for op in ops {
+            let (name, path, from, value) = match op {
+                PatchOp::Add { path, value } => ("add", path, None, Some(value)),
+                PatchOp::Remove { path } => ("remove", path, None, None),
+                PatchOp::Replace { path, value } => ("replace", path, None, Some(value)),
+                PatchOp::Move { from, path } => ("move", path, Some(from), None),
+                PatchOp::Copy { from, path } => ("copy", path, Some(from), None),
+                PatchOp::Test { path, value } => ("test", path, None, Some(value)),
+            };
+
+            let mut items = vec![
+                ("op".to_string(), Object::from(name)),
+                ("path".to_string(), Object::from(path.as_str())),
+            ];
+
+            if let Some(from) = from {
+                items.push(("from".to_string(), Object::from(from.as_str())));
+            }
+
+            if let Some(value) = value {
+                items.push(("value".to_string(), value.clone()));
+            }
+
+            list.push(Object::map(items));
+        }
+
+        Object::list(list)
+    }
+
+    pub fn from_object(object: &Object) -> Result<Vec<PatchOp>, PatchError> {
+        let list = match &object.value {
+            Value::List(list) => list,
+            _ => return Err(PatchError::InvalidOp("patch must be a list".to_string())),
+        };
+
+        let mut ops = Vec::with_capacity(list.len());
+
+        for op in list {
+            let items = match &op.value {
+                Value::Map(items) => items,
+                _ => return Err(PatchError::InvalidOp("operation must be a map".to_string())),
+            };
+
+            let field = |name: &str| items.iter().find(|(k, _)| k == name).map(|(_, v)| v);
+
+            let string_field = |name: &str| match field(name).map(|v| &v.value) {
+                Some(Value::String { string, .. }) => Ok(string.clone()),
+                _ => Err(PatchError::InvalidOp(format!(
+                    "operation is missing string field {:?}",
+                    name
+                ))),
+            };
+
+            let value_field = |name: &str| {
+                field(name).cloned().ok_or_else(|| {
+                    PatchError::InvalidOp(format!("operation is missing field {:?}", name))
+                })
+            };
+
+            let path = string_field("path")?;
+
+            ops.push(match string_field("op")?.as_str() {
+                "add" => PatchOp::Add {
+                    path,
+                    value: value_field("value")?,
+                },
+                "remove" => PatchOp::Remove { path },
+                "replace" => PatchOp::Replace {
+                    path,
+                    value: value_field("value")?,
+                },
+                "move" => PatchOp::Move {
+                    from: string_field("from")?,
+                    path,
+                },
+                "copy" => PatchOp::Copy {
+                    from: string_field("from")?,
+                    path,
+                },
+                "test" => PatchOp::Test {
+                    path,
+                    value: value_field("value")?,
+                },
+                other => return Err(PatchError::InvalidOp(format!("unknown op {:?}", other))),
+            });
+        }
+
+        Ok(ops)
+    }
+}
+
+fn add(root: &mut Object, pointer: &str, value: Object) -> Result<(), PatchError> {
+    if pointer.is_empty() {
+        *root = value;
+        return Ok(());
+    }
+
+    let (parent, token) = split_pointer(pointer)?;
+    let parent = resolve_mut(root, parent)?;
+
+    match parent.value {
+        Value::Map(ref mut items) => {
+            match items.iter_mut().find(|(k, _)| *k == token) {
+                Some((_, existing)) => *existing = value,
+                None => items.push((token, value)),
+            }
+
+            parent.length = items.len();
+        }
+        Value::List(ref mut elements) => {
+            let i = if token == "-" {
+                elements.len()
+            } else {
+                // inserting right after the last element is allowed
+                list_index(&token, elements.len() + 1)
+                    .ok_or_else(|| PatchError::InvalidIndex(pointer.to_string()))?
+            };
+
+            elements.insert(i, value);
+            parent.length = elements.len();
+        }
+        _ => return Err(PatchError::PathNotFound(pointer.to_string())),
+    }
+
+    Ok(())
+}
+
+fn remove(root: &mut Object, pointer: &str) -> Result<Object, PatchError> {
+    // make sure errors say why the value couldn't be removed
+    resolve_mut(root, pointer)?;
+
+    root.remove_pointer(pointer)
+        .ok_or_else(|| PatchError::InvalidPointer(pointer.to_string()))
+}
+
+// equality as JSON sees it: numbers compare by value whatever their width, maps ignore key order
+pub fn json_equal(a: &Object, b: &Object) -> bool {
+    match (&a.value, &b.value) {
+        (Value::String { string: a, .. }, Value::String { string: b, .. }) => a == b,
+        (Value::Map(a), Value::Map(b)) => {
+            a.len() == b.len()
+                && a.iter().all(|(key, a_value)| {
+                    b.iter()
+                        .find(|(k, _)| k == key)
+                        .is_some_and(|(_, b_value)| json_equal(a_value, b_value))
+                })
+        }
+        (Value::List(a), Value::List(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| json_equal(a, b))
+        }
+        _ => match (as_number(a), as_number(b)) {
+            (Some(a), Some(b)) => a == b,
+            _ => a.value == b.value,
+        },
+    }
+}
+
+#[derive(PartialEq)]
+enum Number {
+    Int(i128),
+    UInt(u128),
+    Float(f64),
+}
+
+fn as_number(object: &Object) -> Option<Number> {
+    let number = match object.value {
+        Value::SInt(i) => Number::Int(i),
+        Value::UInt(u) => Number::UInt(u),
+        Value::Float32(f) => Number::Float(f as f64),
+        Value::Float64(f) => Number::Float(f),
+        _ => return None,
+    };
+
+    // bring everything that fits to the same representation, so that 1, 1u and 1.0 compare equal
+    Some(match number {
+        Number::UInt(u) if u <= i128::MAX as u128 => Number::Int(u as i128),
+        Number::Float(f) if f.fract() == 0.0 && f.abs() < 1e38 => Number::Int(f as i128),
+        n => n,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn patch(ops: serde_json::Value) -> Vec<PatchOp> {
+        PatchOp::from_object(&Object::from_json(ops)).unwrap()
+    }
+
+    fn apply(
+        document: serde_json::Value,
+        ops: serde_json::Value,
+    ) -> Result<serde_json::Value, PatchError> {
+        let mut document = Object::from_json(document);
+        document.apply_patch(&patch(ops))?;
+        Ok(document.into_json())
+    }
+
+    #[test]
+    fn merge_patches_follow_rfc_7386() {
+        let mut document = Object::from_json(json!({
+            "title": "Goodbye!",
+            "author": {"givenName": "John", "familyName": "Doe"},
+            "tags": ["example", "sample"],
+            "content": "This will be unchanged"
+        }));
+
+        document.apply_merge_patch(&Object::from_json(json!({
+            "title": "Hello!",
+            "phoneNumber": "+01-123-456-7890",
+            "author": {"familyName": null},
+            "tags": ["example"]
+        })));
+
+        assert_eq!(
+            document.into_json(),
+            json!({
+                "title": "Hello!",
+                "author": {"givenName": "John"},
+                "tags": ["example"],
+                "content": "This will be unchanged",
+                "phoneNumber": "+01-123-456-7890"
+            })
+        );
+
+        let mut scalar = Object::from_json(json!([1]));
+        scalar.apply_merge_patch(&Object::from_json(json!({"a": {"b": null, "c": 1}})));
+        assert_eq!(scalar.into_json(), json!({"a": {"c": 1}}));
+    }
+
+    #[test]
+    fn patches_follow_rfc_6902() {
+        let document = json!({"foo": ["bar", "baz"], "qux": {"baz": "hi"}});
+
+        let cases = [
+            (
+                json!([{"op": "add", "path": "/foo/1", "value": "x"}]),
+                json!({"foo": ["bar", "x", "baz"], "qux": {"baz": "hi"}}),
+            ),
+            (
+                json!([{"op": "add", "path": "/foo/-", "value": 1}]),
+                json!({"foo": ["bar", "baz", 1], "qux": {"baz": "hi"}}),
+            ),
+            (
+                json!([{"op": "remove", "path": "/qux/baz"}]),
+                json!({"foo": ["bar", "baz"], "qux": {}}),
+            ),
+            (
+                json!([{"op": "replace", "path": "/foo/0", "value": null}]),
+                json!({"foo": [null, "baz"], "qux": {"baz": "hi"}}),
+            ),
+            (
+                json!([{"op": "move", "from": "/qux/baz", "path": "/foo/0"}]),
+                json!({"foo": ["hi", "bar", "baz"], "qux": {}}),
+            ),
+            (
+                json!([{"op": "copy", "from": "/foo", "path": "/qux/foo"}]),
+                json!({"foo": ["bar", "baz"], "qux": {"baz": "hi", "foo": ["bar", "baz"]}}),
+            ),
+            (
+                json!([
+                    {"op": "test", "path": "/foo", "value": ["bar", "baz"]},
+                    {"op": "add", "path": "", "value": 1}
+                ]),
+                json!(1),
+            ),
+        ];
+
+        for (ops, expected) in cases {
+            assert_eq!(
+                apply(document.clone(), ops.clone()),
+                Ok(expected),
+                "{}",
+                ops
+            );
+        }
+
+        // the round trip through an Object keeps every operation
+        let ops = patch(json!([
+            {"op": "add", "path": "/a", "value": 1},
+            {"op": "remove", "path": "/a"},
+            {"op": "replace", "path": "/b", "value": [2]},
+            {"op": "move", "from": "/b", "path": "/c"},
+            {"op": "copy", "from": "/c", "path": "/d"},
+            {"op": "test", "path": "/d", "value": {"e": null}}
+        ]));
+        assert_eq!(PatchOp::from_object(&PatchOp::to_object(&ops)), Ok(ops));
+    }
+
+    #[test]
+    fn failed_patches_are_errors_and_change_nothing() {
+        let document = json!({"a": [1, 2], "b": {"c": "d"}});
+
+        let cases = [
+            (
+                json!([{"op": "remove", "path": "/x"}]),
+                PatchError::PathNotFound("/x".to_string()),
+            ),
+            (
+                json!([{"op": "add", "path": "/x/y", "value": 1}]),
+                PatchError::PathNotFound("/x".to_string()),
+            ),
+            (
+                json!([{"op": "add", "path": "/a/3", "value": 1}]),
+                PatchError::InvalidIndex("/a/3".to_string()),
+            ),
+            (
+                json!([{"op": "replace", "path": "a", "value": 1}]),
+                PatchError::InvalidPointer("a".to_string()),
+            ),
+            (
+                json!([{"op": "move", "from": "/b", "path": "/b/e"}]),
+                PatchError::MoveIntoChild("/b".to_string()),
+            ),
+            (
+                json!([
+                    {"op": "add", "path": "/z", "value": 1},
+                    {"op": "test", "path": "/a/0", "value": 2}
+                ]),
+                PatchError::TestFailed("/a/0".to_string()),
+            ),
+        ];
+
+        for (ops, error) in cases {
+            let mut object = Object::from_json(document.clone());
+            assert_eq!(
+                object.apply_patch(&patch(ops.clone())),
+                Err(error),
+                "{}",
+                ops
+            );
+            assert_eq!(object.into_json(), document);
+        }
+    }
+
+    #[test]
+    fn malformed_patches_are_errors() {
+        let cases = [
+            json!({"op": "add"}),
+            json!([1]),
+            json!([{"op": "add", "path": "/a"}]),
+            json!([{"op": "move", "path": "/a"}]),
+            json!([{"op": "remove"}]),
+            json!([{"op": 1, "path": "/a"}]),
+            json!([{"op": "delete", "path": "/a"}]),
+        ];
+
+        for ops in cases {
+            assert!(
+                matches!(
+                    PatchOp::from_object(&Object::from_json(ops.clone())),
+                    Err(PatchError::InvalidOp(_))
+                ),
+                "{}",
+                ops
+            );
+        }
+    }
+}
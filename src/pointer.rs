@@ -0,0 +1,321 @@
+use std::fmt::{self, Display, Formatter};
+
+use crate::object::{Object, Value};
+
+/*
+    JSON Pointers (RFC 6901), e.g. "/author/avatar" or "/attachments/0".
+    "" is the whole object, "~1" stands for a '/' in a key and "~0" for a '~'.
+*/
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PointerError {
+    // the pointer is neither empty nor starts with '/'
+    InvalidPointer(String),
+    // a list index is not a number, or is out of range
+    InvalidIndex(String),
+    // nothing exists at the pointer (or the parent isn't a map or list)
+    NotFound(String),
+}
+
+impl Display for PointerError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            PointerError::InvalidPointer(p) => write!(f, "invalid JSON pointer {:?}", p),
+            PointerError::InvalidIndex(p) => write!(f, "invalid list index in {:?}", p),
+            PointerError::NotFound(p) => write!(f, "path {:?} does not exist", p),
+        }
+    }
+}
+
+impl std::error::Error for PointerError {}
+
+impl Object {
+    pub fn pointer(&self, pointer: &str) -> Option<&Object> {
+        let mut current = self;
+
+        for token in parse_pointer(pointer).ok()? {
+            current = match &current.value {
+                Value::Map(items) => items.iter().find(|(k, _)| *k == token).map(|(_, v)| v)?,
+                Value::List(elements) => &elements[list_index(&token, elements.len())?],
+                _ => return None,
+            };
+        }
+
+        Some(current)
+    }
+
+    // note that changing the number of items in a returned map or list won't update its length
+    pub fn pointer_mut(&mut self, pointer: &str) -> Option<&mut Object> {
+        resolve_mut(self, pointer).ok()
+    }
+
+    // set the value at `pointer`, creating any maps missing along the way, and return the old value
+    // the last token may also be "-" or the length of a list, to append to it
+    pub fn set_pointer(
+        &mut self,
+        pointer: &str,
+        value: Object,
+    ) -> Result<Option<Object>, PointerError> {
+        let mut tokens = parse_pointer(pointer)?;
+
+        let last = match tokens.pop() {
+            Some(last) => last,
+            None => return Ok(Some(std::mem::replace(self, value))),
+        };
+
+        let mut current = self;
+
+        for token in tokens {
+            current = match current.value {
+                Value::Map(ref mut items) => {
+                    let i = match items.iter().position(|(k, _)| *k == token) {
+                        Some(i) => i,
+                        None => {
+                            items.push((token, Object::map(Vec::new())));
+                            current.length = items.len();
+                            items.len() - 1
+                        }
+                    };
+
+                    &mut items[i].1
+                }
+                Value::List(ref mut elements) => {
+                    let i = list_index(&token, elements.len())
+                        .ok_or_else(|| PointerError::InvalidIndex(pointer.to_string()))?;
+
+                    &mut elements[i]
+                }
+                _ => return Err(PointerError::NotFound(pointer.to_string())),
+            };
+        }
+
+        let previous = match current.value {
+            Value::Map(ref mut items) => match items.iter_mut().find(|(k, _)| *k == last) {
+                Some((_, existing)) => Some(std::mem::replace(existing, value)),
+                None => {
+                    items.push((last, value));
+                    None
+                }
+            },
+            Value::List(ref mut elements) => {
+                if last == "-" || list_index(&last, elements.len() + 1) == Some(elements.len()) {
+                    elements.push(value);
+                    None
+                } else {
+                    let i = list_index(&last, elements.len())
+                        .ok_or_else(|| PointerError::InvalidIndex(pointer.to_string()))?;
+
+                    Some(std::mem::replace(&mut elements[i], value))
+                }
+            }
+            _ => return Err(PointerError::NotFound(pointer.to_string())),
+        };
+
+        sync_length(current);
+        Ok(previous)
+    }
+
+    // remove the value at `pointer` from its map or list and return it
+    pub fn remove_pointer(&mut self, pointer: &str) -> Option<Object> {
+        let (parent, last) = split_pointer(pointer).ok()?;
+        let parent = self.pointer_mut(parent)?;
+
+        let removed = match parent.value {
+            Value::Map(ref mut items) => {
+                let i = items.iter().position(|(k, _)| *k == last)?;
+                items.remove(i).1
+            }
+            Value::List(ref mut elements) => elements.remove(list_index(&last, elements.len())?),
+            _ => return None,
+        };
+
+        sync_length(parent);
+        Some(removed)
+    }
+}
+
+// split a JSON pointer into its unescaped reference tokens
+pub fn parse_pointer(pointer: &str) -> Result<Vec<String>, PointerError> {
+    if pointer.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    if !pointer.starts_with('/') {
+        return Err(PointerError::InvalidPointer(pointer.to_string()));
+    }
+
+    Ok(pointer[1..]
+        .split('/')
+        .map(|token| token.replace("~1", "/").replace("~0", "~"))
+        .collect())
+}
+
+// split a pointer into the pointer of its parent and its last (unescaped) token
+pub fn split_pointer(pointer: &str) -> Result<(&str, String), PointerError> {
+    let split = pointer
+        .rfind('/')
+        .ok_or_else(|| PointerError::InvalidPointer(pointer.to_string()))?;
+
+    let last = parse_pointer(&pointer[split..])?.pop().unwrap();
+
+    Ok((&pointer[..split], last))
+}
+
+// escape a map key or list index so it can be part of a JSON pointer
+pub fn escape_pointer_token(token: &str) -> String {
+    token.replace('~', "~0").replace('/', "~1")
+}
+
+// a list index below `len`, without signs or leading zeros
+pub fn list_index(token: &str, len: usize) -> Option<usize> {
+    let valid = !token.is_empty()
+        && token.bytes().all(|b| b.is_ascii_digit())
+        && (token == "0" || !token.starts_with('0'));
+
+    match token.parse::<usize>() {
+        Ok(i) if valid && i < len => Some(i),
+        _ => None,
+    }
+}
+
+pub fn resolve_mut<'a>(
+    root: &'a mut Object,
+    pointer: &str,
+) -> Result<&'a mut Object, PointerError> {
+    let mut current = root;
+
+    for token in parse_pointer(pointer)? {
+        current = match current.value {
+            Value::Map(ref mut items) => items
+                .iter_mut()
+                .find(|(k, _)| *k == token)
+                .map(|(_, v)| v)
+                .ok_or_else(|| PointerError::NotFound(pointer.to_string()))?,
+            Value::List(ref mut elements) => {
+                let i = list_index(&token, elements.len())
+                    .ok_or_else(|| PointerError::InvalidIndex(pointer.to_string()))?;
+
+                &mut elements[i]
+            }
+            _ => return Err(PointerError::NotFound(pointer.to_string())),
+        };
+    }
+
+    Ok(current)
+}
+
+fn sync_length(object: &mut Object) {
+    match &object.value {
+        Value::Map(items) => object.length = items.len(),
+        Value::List(elements) => object.length = elements.len(),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn document() -> Object {
+        Object::from_json(json!({
+            "author": {"name": "ann", "a/b": 1, "m~n": 2},
+            "attachments": ["x", "y"]
+        }))
+    }
+
+    fn get(object: &Object, pointer: &str) -> Option<serde_json::Value> {
+        object.pointer(pointer).map(|o| o.clone().into_json())
+    }
+
+    #[test]
+    fn pointers_follow_rfc_6901() {
+        let document = document();
+
+        assert_eq!(get(&document, ""), Some(document.clone().into_json()));
+        assert_eq!(get(&document, "/author/name"), Some(json!("ann")));
+        assert_eq!(get(&document, "/author/a~1b"), Some(json!(1)));
+        assert_eq!(get(&document, "/author/m~0n"), Some(json!(2)));
+        assert_eq!(get(&document, "/attachments/1"), Some(json!("y")));
+        assert_eq!(get(&document, "/attachments/2"), None);
+        assert_eq!(get(&document, "/attachments/01"), None);
+        assert_eq!(get(&document, "/author/name/0"), None);
+        assert_eq!(get(&document, "author"), None);
+
+        assert_eq!(escape_pointer_token("a/~b"), "a~1~0b");
+        assert_eq!(parse_pointer("/a~01").unwrap(), vec!["a~1"]);
+        assert_eq!(split_pointer("/a/b~1c").unwrap(), ("/a", "b/c".to_string()));
+    }
+
+    #[test]
+    fn set_and_remove_update_lengths() {
+        let mut document = document();
+
+        let old = document.set_pointer("/author/name", Object::from_json(json!("bob")));
+        assert_eq!(old.unwrap().map(|o| o.into_json()), Some(json!("ann")));
+
+        assert_eq!(
+            document.set_pointer("/new/deep", Object::from_json(json!(true))),
+            Ok(None)
+        );
+        assert_eq!(
+            document.set_pointer("/attachments/-", Object::from_json(json!("z"))),
+            Ok(None)
+        );
+        assert_eq!(
+            document.set_pointer("/attachments/3", Object::from_json(json!("w"))),
+            Ok(None)
+        );
+        assert_eq!(document.pointer("/attachments").unwrap().length, 4);
+        assert_eq!(document.length, 3);
+
+        let removed = document
+            .remove_pointer("/attachments/0")
+            .map(|o| o.into_json());
+        assert_eq!(removed, Some(json!("x")));
+        assert_eq!(document.remove_pointer("/author/missing"), None);
+        assert_eq!(
+            document.remove_pointer("/new/deep").map(|o| o.into_json()),
+            Some(json!(true))
+        );
+        assert_eq!(document.pointer("/new").unwrap().length, 0);
+
+        assert_eq!(
+            document.into_json(),
+            json!({
+                "author": {"name": "bob", "a/b": 1, "m~n": 2},
+                "attachments": ["y", "z", "w"],
+                "new": {}
+            })
+        );
+    }
+
+    #[test]
+    fn invalid_pointers_are_errors() {
+        let mut document = document();
+        let value = || Object::from_json(json!(null));
+
+        assert_eq!(
+            document.set_pointer("author", value()),
+            Err(PointerError::InvalidPointer("author".to_string()))
+        );
+        assert_eq!(
+            document.set_pointer("/attachments/5", value()),
+            Err(PointerError::InvalidIndex("/attachments/5".to_string()))
+        );
+        assert_eq!(
+            document.set_pointer("/attachments/x/y", value()),
+            Err(PointerError::InvalidIndex("/attachments/x/y".to_string()))
+        );
+        assert_eq!(
+            document.set_pointer("/author/name/x", value()),
+            Err(PointerError::NotFound("/author/name/x".to_string()))
+        );
+        assert_eq!(
+            resolve_mut(&mut document, "/nothing").err(),
+            Some(PointerError::NotFound("/nothing".to_string()))
+        );
+        assert!(split_pointer("").is_err());
+    }
+}
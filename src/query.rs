@@ -0,0 +1,1037 @@
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::fmt::{self, Display, Formatter};
+
+use crate::decode::{collapse_collections, decode_header, read_data, DecodeError};
+use crate::object::{Object, Value, MAX_RUN_EXPANSION};
+use crate::patch::json_equal;
+
+/*
+    A small JSONPath-like query language:
+
+    $                   the root
+    .name  ['name']     a map key
+    [0]  [-1]           a list index, negative ones count from the end
+    [1:3]  [:2]  [-2:]  a slice of a list
+    .*  [*]             every map value or list element
+    [a,'b',2]           several of the above at once
+    ..name  ..*  ..[0]  the same, applied to the current object and everything inside it
+    [?(expr)]           every map value or list element for which expr is true
+
+    Filter expressions compare values of the element being tested (@) with literals:
+    @.type == "image", @.size >= 10, @.tags[0] != null, @.optional (it exists), !(...), && and ||
+*/
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Query {
+    segments: Vec<Segment>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Segment {
+    recursive: bool,
+    selectors: Vec<Selector>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Selector {
+    Name(String),
+    Index(i64),
+    Slice(Option<i64>, Option<i64>),
+    Wildcard,
+    Filter(Expr),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Expr {
+    Or(Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Compare(Operand, Comparison, Operand),
+    Exists(Operand),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Operand {
+    // a path relative to the element being tested
+    Current(Query),
+    Literal(Object),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryError {
+    pub position: usize,
+    pub message: String,
+}
+
+impl Display for QueryError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid query at {}: {}", self.position, self.message)
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+impl Query {
+    pub fn parse(query: &str) -> Result<Query, QueryError> {
+        let mut parser = Parser {
+            chars: query.chars().collect(),
+            position: 0,
+        };
+
+        parser.expect('$')?;
+        let query = parser.parse_segments()?;
+
+        if parser.position < parser.chars.len() {
+            return Err(parser.error("unexpected character"));
+        }
+
+        Ok(query)
+    }
+
+    pub fn select<'a>(&self, root: &'a Object) -> Vec<&'a Object> {
+        self.evaluate(root)
+    }
+
+    // run the query against an encoded message, only putting together the parts of it that are needed
+    pub fn select_encoded(&self, message: &[u8]) -> Result<Vec<Object>, DecodeError> {
+        let encoded = EncodedMessage::new(message)?;
+
+        Ok(self
+            .evaluate(EncodedNode {
+                message: &encoded,
+                kind: NodeKind::Root,
+            })
+            .into_iter()
+            .map(|node| node.to_object().into_owned())
+            .collect())
+    }
+
+    fn evaluate<N: Node>(&self, root: N) -> Vec<N> {
+        let mut current = vec![root];
+
+        for segment in &self.segments {
+            let mut next = Vec::new();
+
+            for node in current {
+                if segment.recursive {
+                    for descendant in descendants(node) {
+                        segment.select_into(descendant, &mut next);
+                    }
+                } else {
+                    segment.select_into(node, &mut next);
+                }
+            }
+
+            current = next;
+        }
+
+        current
+    }
+}
+
+impl Object {
+    pub fn query(&self, query: &str) -> Result<Vec<&Object>, QueryError> {
+        Ok(Query::parse(query)?.select(self))
+    }
+}
+
+impl Segment {
+    fn select_into<N: Node>(&self, node: N, into: &mut Vec<N>) {
+        let children = match node.children() {
+            Some(children) => children,
+            None => return,
+        };
+
+        let is_list = matches!(children.first(), Some((Key::Index, _)));
+
+        for selector in &self.selectors {
+            match selector {
+                Selector::Name(name) => into.extend(
+                    children
+                        .iter()
+                        .filter(|(key, _)| matches!(key, Key::Name(k) if k == name))
+                        .map(|(_, child)| child.clone()),
+                ),
+                Selector::Index(i) if is_list => {
+                    if let Some(i) = resolve_index(*i, children.len()) {
+                        into.push(children[i].1.clone());
+                    }
+                }
+                Selector::Slice(start, end) if is_list => {
+                    let len = children.len() as i64;
+                    let clamp = |i: i64| if i < 0 { (len + i).max(0) } else { i.min(len) };
+
+                    let start = clamp(start.unwrap_or(0));
+                    let end = clamp(end.unwrap_or(len));
+
+                    for i in start..end {
+                        into.push(children[i as usize].1.clone());
+                    }
+                }
+                Selector::Index(_) | Selector::Slice(_, _) => {}
+                Selector::Wildcard => into.extend(children.iter().map(|(_, c)| c.clone())),
+                Selector::Filter(expr) => into.extend(
+                    children
+                        .iter()
+                        .filter(|(_, child)| expr.test(child))
+                        .map(|(_, c)| c.clone()),
+                ),
+            }
+        }
+    }
+}
+
+fn resolve_index(i: i64, len: usize) -> Option<usize> {
+    let i = if i < 0 { len as i64 + i } else { i };
+
+    if i >= 0 && (i as usize) < len {
+        Some(i as usize)
+    } else {
+        None
+    }
+}
+
+// a node and everything inside it, parents before children
+fn descendants<N: Node>(node: N) -> Vec<N> {
+    let mut all = Vec::new();
+    let mut stack = vec![node];
+
+    while let Some(node) = stack.pop() {
+        if let Some(children) = node.children() {
+            stack.extend(children.into_iter().rev().map(|(_, c)| c));
+        }
+
+        all.push(node);
+    }
+
+    all
+}
+
+impl Expr {
+    fn test<N: Node>(&self, node: &N) -> bool {
+        match self {
+            Expr::Or(a, b) => a.test(node) || b.test(node),
+            Expr::And(a, b) => a.test(node) && b.test(node),
+            Expr::Not(e) => !e.test(node),
+            Expr::Exists(operand) => operand.resolve(node).is_some(),
+            Expr::Compare(a, comparison, b) => {
+                match (a.resolve(node), b.resolve(node)) {
+                    (Some(a), Some(b)) => compare(&a, *comparison, &b),
+                    // a missing value is only ever "not equal" to something
+                    _ => *comparison == Comparison::NotEqual,
+                }
+            }
+        }
+    }
+}
+
+impl Operand {
+    fn resolve<N: Node>(&self, node: &N) -> Option<Object> {
+        match self {
+            Operand::Current(query) => query
+                .evaluate(node.clone())
+                .first()
+                .map(|n| n.to_object().into_owned()),
+            Operand::Literal(object) => Some(object.clone()),
+        }
+    }
+}
+
+fn compare(a: &Object, comparison: Comparison, b: &Object) -> bool {
+    use std::cmp::Ordering;
+
+    let ordering = match (&a.value, &b.value) {
+        (Value::String { string: a, .. }, Value::String { string: b, .. }) => Some(a.cmp(b)),
+        _ => match (as_f64(a), as_f64(b)) {
+            (Some(a), Some(b)) => a.partial_cmp(&b),
+            _ => None,
+        },
+    };
+
+    match comparison {
+        Comparison::Equal => json_equal(a, b),
+        Comparison::NotEqual => !json_equal(a, b),
+        Comparison::Less => ordering == Some(Ordering::Less),
+        Comparison::LessOrEqual => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+        Comparison::Greater => ordering == Some(Ordering::Greater),
+        Comparison::GreaterOrEqual => {
+            matches!(ordering, Some(Ordering::Greater | Ordering::Equal))
+        }
+    }
+}
+
+fn as_f64(object: &Object) -> Option<f64> {
+    match object.value {
+        Value::SInt(i) => Some(i as f64),
+        Value::UInt(u) => Some(u as f64),
+        Value::Float32(f) => Some(f as f64),
+        Value::Float64(f) => Some(f),
+        Value::Timestamp32(t) => Some(t as f64),
+        _ => None,
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    position: usize,
+}
+
+impl Parser {
+    fn error(&self, message: &str) -> QueryError {
+        QueryError {
+            position: self.position,
+            message: message.to_string(),
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    fn peek_str(&self, s: &str) -> bool {
+        s.chars()
+            .enumerate()
+            .all(|(i, c)| self.chars.get(self.position + i) == Some(&c))
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.position += 1;
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), QueryError> {
+        if self.peek() == Some(c) {
+            self.position += 1;
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected {:?}", c)))
+        }
+    }
+
+    fn parse_segments(&mut self) -> Result<Query, QueryError> {
+        let mut segments = Vec::new();
+
+        loop {
+            let recursive = if self.peek_str("..") {
+                self.position += 2;
+                true
+            } else if self.peek() == Some('.') {
+                self.position += 1;
+                false
+            } else if self.peek() == Some('[') {
+                segments.push(Segment {
+                    recursive: false,
+                    selectors: self.parse_bracket()?,
+                });
+                continue;
+            } else {
+                break;
+            };
+
+            let selectors = match self.peek() {
+                Some('[') if recursive => self.parse_bracket()?,
+                Some('*') => {
+                    self.position += 1;
+                    vec![Selector::Wildcard]
+                }
+                _ => vec![Selector::Name(self.parse_name()?)],
+            };
+
+            segments.push(Segment {
+                recursive,
+                selectors,
+            });
+        }
+
+        Ok(Query { segments })
+    }
+
+    fn parse_name(&mut self) -> Result<String, QueryError> {
+        let start = self.position;
+
+        while self
+            .peek()
+            .is_some_and(|c| c.is_alphanumeric() || c == '_' || c == '-' || c == '$')
+        {
+            self.position += 1;
+        }
+
+        if start == self.position {
+            return Err(self.error("expected a name"));
+        }
+
+        Ok(self.chars[start..self.position].iter().collect())
+    }
+
+    fn parse_bracket(&mut self) -> Result<Vec<Selector>, QueryError> {
+        self.expect('[')?;
+        let mut selectors = Vec::new();
+
+        loop {
+            self.skip_whitespace();
+            selectors.push(self.parse_selector()?);
+            self.skip_whitespace();
+
+            match self.peek() {
+                Some(',') => self.position += 1,
+                Some(']') => {
+                    self.position += 1;
+                    return Ok(selectors);
+                }
+                _ => return Err(self.error("expected ',' or ']'")),
+            }
+        }
+    }
+
+    fn parse_selector(&mut self) -> Result<Selector, QueryError> {
+        match self.peek() {
+            Some('*') => {
+                self.position += 1;
+                Ok(Selector::Wildcard)
+            }
+            Some('\'') | Some('"') => Ok(Selector::Name(self.parse_string()?)),
+            Some('?') => {
+                self.position += 1;
+                self.expect('(')?;
+                let expr = self.parse_or()?;
+                self.skip_whitespace();
+                self.expect(')')?;
+                Ok(Selector::Filter(expr))
+            }
+            _ => {
+                let start = self.parse_optional_int()?;
+
+                if self.peek() != Some(':') {
+                    return start
+                        .map(Selector::Index)
+                        .ok_or_else(|| self.error("expected a selector"));
+                }
+
+                self.position += 1;
+                let end = self.parse_optional_int()?;
+
+                Ok(Selector::Slice(start, end))
+            }
+        }
+    }
+
+    fn parse_optional_int(&mut self) -> Result<Option<i64>, QueryError> {
+        let start = self.position;
+
+        if self.peek() == Some('-') {
+            self.position += 1;
+        }
+
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.position += 1;
+        }
+
+        if start == self.position {
+            return Ok(None);
+        }
+
+        let digits: String = self.chars[start..self.position].iter().collect();
+
+        digits
+            .parse()
+            .map(Some)
+            .map_err(|_| self.error("invalid integer"))
+    }
+
+    fn parse_string(&mut self) -> Result<String, QueryError> {
+        let quote = self.peek().unwrap();
+        self.position += 1;
+
+        let mut string = String::new();
+
+        loop {
+            match self.peek() {
+                None => return Err(self.error("unterminated string")),
+                Some(c) if c == quote => {
+                    self.position += 1;
+                    return Ok(string);
+                }
+                Some('\\') => {
+                    self.position += 1;
+
+                    match self.peek() {
+                        Some(c) => string.push(c),
+                        None => return Err(self.error("unterminated string")),
+                    }
+
+                    self.position += 1;
+                }
+                Some(c) => {
+                    string.push(c);
+                    self.position += 1;
+                }
+            }
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, QueryError> {
+        let mut expr = self.parse_and()?;
+
+        loop {
+            self.skip_whitespace();
+
+            if !self.peek_str("||") {
+                return Ok(expr);
+            }
+
+            self.position += 2;
+            expr = Expr::Or(Box::new(expr), Box::new(self.parse_and()?));
+        }
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, QueryError> {
+        let mut expr = self.parse_unary()?;
+
+        loop {
+            self.skip_whitespace();
+
+            if !self.peek_str("&&") {
+                return Ok(expr);
+            }
+
+            self.position += 2;
+            expr = Expr::And(Box::new(expr), Box::new(self.parse_unary()?));
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, QueryError> {
+        self.skip_whitespace();
+
+        match self.peek() {
+            Some('!') if !self.peek_str("!=") => {
+                self.position += 1;
+                Ok(Expr::Not(Box::new(self.parse_unary()?)))
+            }
+            Some('(') => {
+                self.position += 1;
+                let expr = self.parse_or()?;
+                self.skip_whitespace();
+                self.expect(')')?;
+                Ok(expr)
+            }
+            _ => self.parse_comparison(),
+        }
+    }
+
+    fn parse_comparison(&mut self) -> Result<Expr, QueryError> {
+        let left = self.parse_operand()?;
+        self.skip_whitespace();
+
+        let comparison = [
+            ("==", Comparison::Equal),
+            ("!=", Comparison::NotEqual),
+            ("<=", Comparison::LessOrEqual),
+            (">=", Comparison::GreaterOrEqual),
+            ("<", Comparison::Less),
+            (">", Comparison::Greater),
+        ]
+        .into_iter()
+        .find(|(token, _)| self.peek_str(token));
+
+        let (token, comparison) = match comparison {
+            Some(found) => found,
+            None => return Ok(Expr::Exists(left)),
+        };
+
+        self.position += token.len();
+        self.skip_whitespace();
+        let right = self.parse_operand()?;
+
+        Ok(Expr::Compare(left, comparison, right))
+    }
+
+    fn parse_operand(&mut self) -> Result<Operand, QueryError> {
+        self.skip_whitespace();
+
+        match self.peek() {
+            Some('@') => {
+                self.position += 1;
+                Ok(Operand::Current(self.parse_segments()?))
+            }
+            Some('\'') | Some('"') => Ok(Operand::Literal(Object::string(self.parse_string()?))),
+            Some(c) if c == '-' || c.is_ascii_digit() => {
+                let start = self.position;
+                self.position += 1;
+
+                while self
+                    .peek()
+                    .is_some_and(|c| c.is_ascii_digit() || matches!(c, '.' | 'e' | 'E' | '+' | '-'))
+                {
+                    self.position += 1;
+                }
+
+                let number: String = self.chars[start..self.position].iter().collect();
+
+                let json = serde_json::from_str(&number).map_err(|_| QueryError {
+                    position: start,
+                    message: "invalid number".to_string(),
+                })?;
+
+                Ok(Operand::Literal(Object::from_json(json)))
+            }
+            _ => {
+                for (word, object) in [
+                    ("true", Object::bool(true)),
+                    ("false", Object::bool(false)),
+                    ("null", Object::null()),
+                ] {
+                    if self.peek_str(word) {
+                        self.position += word.len();
+                        return Ok(Operand::Literal(object));
+                    }
+                }
+
+                Err(self.error("expected a value"))
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Key {
+    Name(String),
+    Index,
+}
+
+// what a query can walk over: decoded objects, or objects still inside an encoded message
+trait Node: Clone {
+    // map entries or list elements, None if this isn't a collection
+    fn children(&self) -> Option<Vec<(Key, Self)>>;
+
+    fn to_object(&self) -> Cow<'_, Object>;
+}
+
+impl Node for &Object {
+    fn children(&self) -> Option<Vec<(Key, Self)>> {
+        match &self.value {
+            Value::Map(items) => Some(
+                items
+                    .iter()
+                    .map(|(k, v)| (Key::Name(k.clone()), v))
+                    .collect(),
+            ),
+            Value::List(elements) => Some(elements.iter().map(|e| (Key::Index, e)).collect()),
+            _ => None,
+        }
+    }
+
+    fn to_object(&self) -> Cow<'_, Object> {
+        Cow::Borrowed(self)
+    }
+}
+
+// a message decoded into a flat list, with maps and lists only put together when they're needed
+struct EncodedMessage {
+    objects: Vec<Object>,
+    is_root_map: bool,
+    // index of the first object after each object and everything inside it
+    ends: Vec<usize>,
+}
+
+impl EncodedMessage {
+    // everything that could go wrong putting an object together is checked here
+    fn new(message: &[u8]) -> Result<Self, DecodeError> {
+        let mut buf = VecDeque::from(message.to_vec());
+        let (mut objects, is_root_map) = decode_header(&mut buf)?;
+
+        read_data(&mut objects, &mut buf)?;
+
+        let mut ends = vec![0; objects.len()];
+        let mut i = 0;
+        while i < objects.len() {
+            i = compute_ends(&objects, i, &mut ends)?;
+        }
+
+        let encoded = EncodedMessage {
+            objects,
+            is_root_map,
+            ends,
+        };
+
+        // the same limit `collapse_collections` puts on runs, for the whole message at once
+        let mut expansion: usize = 0;
+
+        for (i, object) in encoded.objects.iter().enumerate() {
+            if let Value::Run(count) = object.value {
+                let size = (count - 1).saturating_mul(encoded.expanded_size(i + 1));
+                expansion = expansion.saturating_add(size);
+            }
+        }
+
+        if expansion > MAX_RUN_EXPANSION {
+            return Err(DecodeError::Malformed(format!(
+                "runs expand to more than {} objects and bytes",
+                MAX_RUN_EXPANSION
+            )));
+        }
+
+        Ok(encoded)
+    }
+
+    fn key(&self, i: usize) -> String {
+        match &self.objects[i].value {
+            Value::String { string, .. } => string.clone(),
+            _ => unreachable!("map keys are always strings"),
+        }
+    }
+
+    // `Object::expanded_size` of the object at `i`, without putting it together
+    fn expanded_size(&self, i: usize) -> usize {
+        let object = &self.objects[i];
+
+        match &object.value {
+            Value::Map(_) => self
+                .children(i + 1, true, Some(object.length), self.ends[i])
+                .into_iter()
+                .fold(1, |size, (key, j)| {
+                    let key_length = match key {
+                        Key::Name(name) => name.len(),
+                        Key::Index => 0,
+                    };
+
+                    size.saturating_add(1 + key_length)
+                        .saturating_add(self.expanded_size(j))
+                }),
+            Value::List(_) => {
+                let mut size: usize = 1;
+                let mut j = i + 1;
+
+                while j < self.ends[i] {
+                    size = match self.objects[j].value {
+                        Value::Run(count) => {
+                            j += 1;
+                            size.saturating_add(count.saturating_mul(self.expanded_size(j)))
+                        }
+                        _ => size.saturating_add(self.expanded_size(j)),
+                    };
+
+                    j = self.ends[j];
+                }
+
+                size
+            }
+            _ => object.expanded_size(),
+        }
+    }
+
+    // the object at `i` and everything inside it
+    fn decode(&self, i: usize) -> Object {
+        let mut collapsed = Vec::with_capacity(1);
+
+        collapse_collections(
+            &mut self.objects[i..self.ends[i]].iter().cloned(),
+            &mut collapsed,
+            1,
+        )
+        .expect("the message was checked when it was read");
+
+        collapsed.pop().unwrap()
+    }
+
+    // the children of a collection spanning objects[start..end]
+    fn children(
+        &self,
+        mut i: usize,
+        is_map: bool,
+        count: Option<usize>,
+        end: usize,
+    ) -> Vec<(Key, usize)> {
+        let mut children = Vec::new();
+
+        while i < end && count.is_none_or(|c| children.len() < c) {
+            if is_map {
+                children.push((Key::Name(self.key(i)), i + 1));
+                i = self.ends[i + 1];
+            } else if let Value::Run(repeats) = self.objects[i].value {
+                for _ in 0..repeats {
+                    children.push((Key::Index, i + 1));
+                }
+
+                i = self.ends[i + 1];
+            } else {
+                children.push((Key::Index, i));
+                i = self.ends[i];
+            }
+        }
+
+        children
+    }
+}
+
+// fill in `ends` for the object at `i` and everything inside it, returning ends[i]
+fn compute_ends(objects: &[Object], i: usize, ends: &mut [usize]) -> Result<usize, DecodeError> {
+    let mut j = i + 1;
+
+    match objects[i].value {
+        Value::Map(_) => {
+            for _ in 0..objects[i].length {
+                ends[j] = j + 1; // key
+                j = compute_ends(objects, j + 1, ends)?;
+            }
+        }
+        Value::List(_) => {
+            let mut remaining = objects[i].length;
+
+            while remaining > 0 {
+                let taken = match objects[j].value {
+                    Value::Run(repeats) => repeats,
+                    _ => 1,
+                };
+
+                if taken > remaining {
+                    return Err(DecodeError::Malformed(format!(
+                        "a run of {} elements in a list with {} left",
+                        taken, remaining
+                    )));
+                }
+
+                j = compute_ends(objects, j, ends)?;
+                remaining -= taken;
+            }
+        }
+        Value::Run(_) => j = compute_ends(objects, j, ends)?,
+        _ => {}
+    }
+
+    ends[i] = j;
+    Ok(j)
+}
+
+#[derive(Clone)]
+struct EncodedNode<'m> {
+    message: &'m EncodedMessage,
+    kind: NodeKind,
+}
+
+#[derive(Clone)]
+enum NodeKind {
+    Root,
+    // index into the flat list of objects
+    Flat(usize),
+    // already decoded, e.g. elements of an IntSeq
+    Decoded(Object),
+}
+
+impl<'m> EncodedNode<'m> {
+    fn with_kind(&self, kind: NodeKind) -> Self {
+        EncodedNode {
+            message: self.message,
+            kind,
+        }
+    }
+}
+
+impl Node for EncodedNode<'_> {
+    fn children(&self) -> Option<Vec<(Key, Self)>> {
+        let message = self.message;
+
+        let children = match &self.kind {
+            NodeKind::Root => message.children(0, message.is_root_map, None, message.objects.len()),
+            NodeKind::Flat(i) => match message.objects[*i].value {
+                Value::Map(_) | Value::List(_) => {
+                    let is_map = matches!(message.objects[*i].value, Value::Map(_));
+                    let count = message.objects[*i].length;
+                    message.children(i + 1, is_map, Some(count), message.ends[*i])
+                }
+                Value::IntSeq { .. } => {
+                    let decoded = message.decode(*i);
+                    return self.with_kind(NodeKind::Decoded(decoded)).children();
+                }
+                _ => return None,
+            },
+            NodeKind::Decoded(object) => {
+                return Some(
+                    object
+                        .children()?
+                        .into_iter()
+                        .map(|(k, c)| (k, self.with_kind(NodeKind::Decoded(c.clone()))))
+                        .collect(),
+                );
+            }
+        };
+
+        Some(
+            children
+                .into_iter()
+                .map(|(k, i)| (k, self.with_kind(NodeKind::Flat(i))))
+                .collect(),
+        )
+    }
+
+    fn to_object(&self) -> Cow<'_, Object> {
+        match &self.kind {
+            NodeKind::Root => {
+                let children = self.children().unwrap_or_default();
+
+                Cow::Owned(if self.message.is_root_map {
+                    Object::map(
+                        children
+                            .into_iter()
+                            .map(|(k, c)| match k {
+                                Key::Name(name) => (name, c.to_object().into_owned()),
+                                Key::Index => unreachable!(),
+                            })
+                            .collect(),
+                    )
+                } else {
+                    Object::list(
+                        children
+                            .into_iter()
+                            .map(|(_, c)| c.to_object().into_owned())
+                            .collect(),
+                    )
+                })
+            }
+            NodeKind::Flat(i) => Cow::Owned(self.message.decode(*i)),
+            NodeKind::Decoded(object) => Cow::Borrowed(object),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::decode::headpack_try_decode;
+    use crate::encode::headpack_encode;
+
+    fn document() -> Object {
+        Object::from_json(json!({
+            "items": [
+                {"name": "a", "size": 5, "tags": ["x", "y"]},
+                {"name": "b", "size": 20},
+                {"name": "c", "size": 20},
+                {"name": "c", "size": 20},
+                {"name": "c", "size": 20},
+            ],
+            "ids": [1, 2, 3, 4, 5, 6, 7, 8],
+            "zeros": [0, 0, 0, 0, 0, 0],
+        }))
+    }
+
+    #[test]
+    fn encoded_messages_give_the_same_results() {
+        let object = document();
+        let message = headpack_encode(object.clone());
+
+        for query in [
+            "$.items[*].name",
+            "$.items[?(@.size >= 10)].name",
+            "$..tags[-1]",
+            "$.ids[2:4]",
+            "$.zeros[*]",
+            "$..*",
+        ] {
+            let query = Query::parse(query).unwrap();
+            let expected: Vec<Object> = query.select(&object).into_iter().cloned().collect();
+
+            assert_eq!(query.select_encoded(&message), Ok(expected));
+        }
+    }
+
+    #[test]
+    fn missing_values_are_only_not_equal() {
+        let object = document();
+
+        let equal = Query::parse("$.items[?(@.missing == @.other)]").unwrap();
+        assert!(equal.select(&object).is_empty());
+
+        let not_equal = Query::parse("$.items[?(@.missing != @.other)]").unwrap();
+        assert_eq!(not_equal.select(&object).len(), 5);
+    }
+
+    #[test]
+    fn malformed_messages_are_errors() {
+        let message = headpack_encode(document());
+        let query = Query::parse("$..*").unwrap();
+
+        for length in 0..message.len() {
+            let truncated = &message[..length];
+            let expected = headpack_try_decode(VecDeque::from(truncated.to_vec()));
+
+            // whatever the decoder makes of it, the query mustn't panic
+            match query.select_encoded(truncated) {
+                Ok(_) => assert!(expected.is_ok()),
+                Err(e) => assert!(expected.is_err(), "{}", e),
+            }
+        }
+
+        assert!(query.select_encoded(&[0xff, 0xff, 0xff]).is_err());
+    }
+
+    #[test]
+    fn queries_select_what_they_describe() {
+        let object = document();
+
+        let cases = [
+            ("$.items[0].name", json!(["a"])),
+            ("$['items'][-1]['size']", json!([20])),
+            ("$.ids[:2]", json!([1, 2])),
+            ("$.ids[-2:]", json!([7, 8])),
+            ("$.ids[0,'x',7]", json!([1, 8])),
+            ("$..tags.*", json!(["x", "y"])),
+            ("$.items[?(@.tags)].name", json!(["a"])),
+            ("$.items[?(!(@.size > 5))].name", json!(["a"])),
+            (
+                "$.items[?(@.name == 'c' && @.size == 20 || @.name == \"a\")].size",
+                json!([5, 20, 20, 20]),
+            ),
+            ("$.items[?(@.tags[0] == 'x')].name", json!(["a"])),
+            // a missing value isn't equal to anything
+            (
+                "$.items[?(@.tags[0] != 'x')].name",
+                json!(["b", "c", "c", "c"]),
+            ),
+            ("$.missing[*]", json!([])),
+        ];
+
+        for (query, expected) in cases {
+            let selected: Vec<_> = object
+                .query(query)
+                .unwrap()
+                .into_iter()
+                .map(|o| o.clone().into_json())
+                .collect();
+
+            assert_eq!(serde_json::Value::from(selected), expected, "{}", query);
+        }
+    }
+
+    #[test]
+    fn malformed_queries_are_errors() {
+        for (query, position) in [
+            ("", 0),
+            ("items", 0),
+            ("$.", 2),
+            ("$[", 2),
+            ("$[0", 3),
+            ("$['a]", 5),
+            ("$[?(@.a ==)]", 10),
+            ("$[?(@.a == 1]", 12),
+            ("$.a b", 3),
+        ] {
+            let e = Query::parse(query).unwrap_err();
+            assert_eq!(e.position, position, "{:?}: {}", query, e);
+        }
+    }
+}
@@ -0,0 +1,885 @@
+use std::fmt::{self, Display, Formatter};
+
+use serde_json::json;
+
+use crate::decode::DecodeError;
+use crate::encode::EncodeError;
+use crate::object::{Object, Value};
+use crate::patch::json_equal;
+use crate::pointer::escape_pointer_token;
+
+/*
+    A subset of JSON Schema (type, required, properties, items, enum, minimum, maximum, maxLength)
+    that also knows about HeadPack's own types. On top of the JSON types ("null", "boolean", "string",
+    "object", "array", "integer" and "number"), "type" may be one of:
+
+    "bytes", "sint", "uint", "float32", "float64", "timestamp32" or "userdefined"
+
+    "integer" accepts both SInt and UInt, "number" accepts any of SInt, UInt, Float32 and Float64,
+    and "float64" also accepts Float32, since widening a float never loses anything.
+*/
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Schema {
+    // empty means any type
+    pub types: Vec<SchemaType>,
+    // in the order they were declared
+    pub properties: Vec<(String, Schema)>,
+    pub required: Vec<String>,
+    pub items: Option<Box<Schema>>,
+    pub enum_values: Option<Vec<Object>>,
+    pub minimum: Option<f64>,
+    pub maximum: Option<f64>,
+    // in characters for strings, in bytes for bytes
+    pub max_length: Option<usize>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SchemaType {
+    Null,
+    Bool,
+    String,
+    Bytes,
+    Map,
+    List,
+    Integer,
+    SInt,
+    UInt,
+    Number,
+    Float32,
+    Float64,
+    Timestamp32,
+    UserDefined,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    // JSON pointer to the offending value
+    pub path: String,
+    pub kind: ViolationKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ViolationKind {
+    WrongType {
+        expected: Vec<SchemaType>,
+        found: &'static str,
+    },
+    MissingField(String),
+    NotInEnum,
+    BelowMinimum(f64),
+    AboveMaximum(f64),
+    TooLong {
+        max_length: usize,
+        length: usize,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaError {
+    // JSON pointer to the offending part of the schema
+    pub path: String,
+    pub message: String,
+}
+
+impl Display for SchemaError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid schema at {:?}: {}", self.path, self.message)
+    }
+}
+
+impl std::error::Error for SchemaError {}
+
+// data that can't be written (or read back) keylessly with a given schema
+#[derive(Debug, Clone, PartialEq)]
+pub enum SchemaMismatch {
+    // the object breaks the schema
+    Invalid(Vec<Violation>),
+    // a map has a key the schema doesn't declare, so it would be lost
+    UnknownField(String),
+    // the schema allows both a map with properties and a list at this path, so they can't be told apart
+    Ambiguous(String),
+    // a keyless message doesn't have the shape the schema gives it
+    Malformed(String),
+    // a map has the same key twice, so only one of them could come back
+    DuplicateKey(String),
+    // the keyless form couldn't be written as a message
+    Encode(EncodeError),
+    // the bytes aren't a message at all
+    Decode(DecodeError),
+}
+
+impl Display for SchemaMismatch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            SchemaMismatch::Invalid(violations) => {
+                write!(f, "object doesn't match the schema")?;
+
+                for violation in violations {
+                    write!(f, "\n  {}", violation)?;
+                }
+
+                Ok(())
+            }
+            SchemaMismatch::UnknownField(p) => write!(f, "{:?} is not in the schema", p),
+            SchemaMismatch::Ambiguous(p) => {
+                write!(f, "schema at {:?} allows both a map and a list", p)
+            }
+            SchemaMismatch::Malformed(p) => {
+                write!(f, "keyless message doesn't match the schema at {:?}", p)
+            }
+            SchemaMismatch::DuplicateKey(p) => write!(f, "{:?} appears more than once", p),
+            SchemaMismatch::Encode(e) => write!(f, "{}", e),
+            SchemaMismatch::Decode(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for SchemaMismatch {}
+
+impl From<EncodeError> for SchemaMismatch {
+    fn from(e: EncodeError) -> Self {
+        SchemaMismatch::Encode(e)
+    }
+}
+
+impl From<DecodeError> for SchemaMismatch {
+    fn from(e: DecodeError) -> Self {
+        SchemaMismatch::Decode(e)
+    }
+}
+
+impl SchemaType {
+    pub const ALL: [SchemaType; 14] = [
+        SchemaType::Null,
+        SchemaType::Bool,
+        SchemaType::String,
+        SchemaType::Bytes,
+        SchemaType::Map,
+        SchemaType::List,
+        SchemaType::Integer,
+        SchemaType::SInt,
+        SchemaType::UInt,
+        SchemaType::Number,
+        SchemaType::Float32,
+        SchemaType::Float64,
+        SchemaType::Timestamp32,
+        SchemaType::UserDefined,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SchemaType::Null => "null",
+            SchemaType::Bool => "boolean",
+            SchemaType::String => "string",
+            SchemaType::Bytes => "bytes",
+            SchemaType::Map => "object",
+            SchemaType::List => "array",
+            SchemaType::Integer => "integer",
+            SchemaType::SInt => "sint",
+            SchemaType::UInt => "uint",
+            SchemaType::Number => "number",
+            SchemaType::Float32 => "float32",
+            SchemaType::Float64 => "float64",
+            SchemaType::Timestamp32 => "timestamp32",
+            SchemaType::UserDefined => "userdefined",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<SchemaType> {
+        SchemaType::ALL.into_iter().find(|t| t.name() == name)
+    }
+
+    pub fn matches(&self, value: &Value) -> bool {
+        matches!(
+            (self, value),
+            (SchemaType::Null, Value::Null)
+                | (SchemaType::Bool, Value::Bool(_))
+                | (SchemaType::String, Value::String { .. })
+                | (SchemaType::Bytes, Value::Bytes(_))
+                | (SchemaType::Map, Value::Map(_))
+                | (SchemaType::List, Value::List(_) | Value::IntSeq { .. })
+                | (SchemaType::Integer, Value::SInt(_) | Value::UInt(_))
+                | (SchemaType::SInt, Value::SInt(_))
+                | (SchemaType::UInt, Value::UInt(_))
+                | (
+                    SchemaType::Number,
+                    Value::SInt(_) | Value::UInt(_) | Value::Float32(_) | Value::Float64(_),
+                )
+                | (SchemaType::Float32, Value::Float32(_))
+                | (SchemaType::Float64, Value::Float32(_) | Value::Float64(_))
+                | (SchemaType::Timestamp32, Value::Timestamp32(_))
+                | (SchemaType::UserDefined, Value::UserDefined { .. })
+        )
+    }
+}
+
+impl Display for Violation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let path = if self.path.is_empty() {
+            "/"
+        } else {
+            &self.path
+        };
+
+        match &self.kind {
+            ViolationKind::WrongType { expected, found } => {
+                let expected: Vec<&str> = expected.iter().map(|t| t.name()).collect();
+                write!(
+                    f,
+                    "{}: expected {}, found {}",
+                    path,
+                    expected.join(" or "),
+                    found
+                )
+            }
+            ViolationKind::MissingField(field) => {
+                write!(f, "{}: missing required field {:?}", path, field)
+            }
+            ViolationKind::NotInEnum => write!(f, "{}: not one of the allowed values", path),
+            ViolationKind::BelowMinimum(min) => write!(f, "{}: less than minimum {}", path, min),
+            ViolationKind::AboveMaximum(max) => {
+                write!(f, "{}: greater than maximum {}", path, max)
+            }
+            ViolationKind::TooLong { max_length, length } => write!(
+                f,
+                "{}: length {} is over the maximum of {}",
+                path, length, max_length
+            ),
+        }
+    }
+}
+
+impl Schema {
+    // a schema that accepts exactly the given types
+    pub fn of_type(types: &[SchemaType]) -> Self {
+        Schema {
+            types: types.to_vec(),
+            ..Default::default()
+        }
+    }
+
+    pub fn property(&self, name: &str) -> Option<&Schema> {
+        self.properties
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, s)| s)
+    }
+
+    pub fn is_required(&self, name: &str) -> bool {
+        self.required.iter().any(|r| r == name)
+    }
+
+    pub fn from_json(json: &serde_json::Value) -> Result<Self, SchemaError> {
+        parse_schema(json, String::new())
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut map = serde_json::Map::new();
+
+        match self.types.as_slice() {
+            [] => {}
+            [t] => {
+                map.insert("type".to_string(), json!(t.name()));
+            }
+            types => {
+                let names: Vec<&str> = types.iter().map(|t| t.name()).collect();
+                map.insert("type".to_string(), json!(names));
+            }
+        }
+
+        if !self.properties.is_empty() {
+            let mut properties = serde_json::Map::new();
+
+            for (name, schema) in &self.properties {
+                properties.insert(name.clone(), schema.to_json());
+            }
+
+            map.insert(
+                "properties".to_string(),
+                serde_json::Value::Object(properties),
+            );
+        }
+
+        if !self.required.is_empty() {
+            map.insert("required".to_string(), json!(self.required));
+        }
+
+        if let Some(items) = &self.items {
+            map.insert("items".to_string(), items.to_json());
+        }
+
+        if let Some(values) = &self.enum_values {
+            let values: Vec<serde_json::Value> =
+                values.iter().map(|v| v.clone().into_json()).collect();
+            map.insert("enum".to_string(), json!(values));
+        }
+
+        if let Some(minimum) = self.minimum {
+            map.insert("minimum".to_string(), json!(minimum));
+        }
+
+        if let Some(maximum) = self.maximum {
+            map.insert("maximum".to_string(), json!(maximum));
+        }
+
+        if let Some(max_length) = self.max_length {
+            map.insert("maxLength".to_string(), json!(max_length));
+        }
+
+        serde_json::Value::Object(map)
+    }
+
+    // every way `object` breaks the schema, an empty list means it's valid
+    pub fn validate(&self, object: &Object) -> Vec<Violation> {
+        let mut violations = Vec::new();
+        self.validate_into(object, String::new(), &mut violations);
+        violations
+    }
+
+    pub fn is_valid(&self, object: &Object) -> bool {
+        self.validate(object).is_empty()
+    }
+
+    fn validate_into(&self, object: &Object, path: String, violations: &mut Vec<Violation>) {
+        let mut violation = |kind| {
+            violations.push(Violation {
+                path: path.clone(),
+                kind,
+            })
+        };
+
+        if !self.types.is_empty() && !self.types.iter().any(|t| t.matches(&object.value)) {
+            violation(ViolationKind::WrongType {
+                expected: self.types.clone(),
+                found: object.value.type_name(),
+            });
+
+            // nothing else about it can be checked meaningfully
+            return;
+        }
+
+        if let Some(values) = &self.enum_values {
+            if !values.iter().any(|v| json_equal(v, object)) {
+                violation(ViolationKind::NotInEnum);
+            }
+        }
+
+        if let Some(n) = as_f64(&object.value) {
+            if let Some(minimum) = self.minimum.filter(|min| n < *min) {
+                violation(ViolationKind::BelowMinimum(minimum));
+            }
+
+            if let Some(maximum) = self.maximum.filter(|max| n > *max) {
+                violation(ViolationKind::AboveMaximum(maximum));
+            }
+        }
+
+        let length = match &object.value {
+            Value::String { string, .. } => Some(string.chars().count()),
+            Value::Bytes(bytes) => Some(bytes.len()),
+            _ => None,
+        };
+
+        if let (Some(length), Some(max_length)) = (length, self.max_length) {
+            if length > max_length {
+                violation(ViolationKind::TooLong { max_length, length });
+            }
+        }
+
+        match &object.value {
+            Value::Map(items) => {
+                for field in &self.required {
+                    if !items.iter().any(|(k, _)| k == field) {
+                        violation(ViolationKind::MissingField(field.clone()));
+                    }
+                }
+
+                for (key, value) in items {
+                    if let Some(schema) = self.property(key) {
+                        let key_path = format!("{}/{}", path, escape_pointer_token(key));
+                        schema.validate_into(value, key_path, violations);
+                    }
+                }
+            }
+            Value::List(elements) => {
+                if let Some(items) = &self.items {
+                    for (i, element) in elements.iter().enumerate() {
+                        items.validate_into(element, format!("{}/{}", path, i), violations);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/*
+    Keyless form: every map whose schema declares properties becomes a list of its values, ordered
+    by property name (comparing bytes) whatever order the schema declares them in, since that depends
+    on how the schema was read. If some of the properties are optional, the list starts with a
+    presence bitmap (Bytes, one bit per optional property in the same order, most significant bit
+    first) and only the values that are present follow it.
+*/
+impl Schema {
+    // turn `object` into its keyless form, checking that it matches the schema first
+    pub fn strip_keys(&self, object: Object) -> Result<Object, SchemaMismatch> {
+        let violations = self.validate(&object);
+
+        if !violations.is_empty() {
+            return Err(SchemaMismatch::Invalid(violations));
+        }
+
+        self.strip_keys_at(object, String::new())
+    }
+
+    // turn a keyless object back into the one it was made from
+    pub fn restore_keys(&self, object: Object) -> Result<Object, SchemaMismatch> {
+        self.restore_keys_at(object, String::new())
+    }
+
+    fn is_keyless(&self, path: &str) -> Result<bool, SchemaMismatch> {
+        if self.properties.is_empty() {
+            return Ok(false);
+        }
+
+        if self.types.contains(&SchemaType::List) {
+            return Err(SchemaMismatch::Ambiguous(path.to_string()));
+        }
+
+        Ok(true)
+    }
+
+    // the properties in the order their values are written in keyless form
+    fn keyless_properties(&self) -> Vec<&(String, Schema)> {
+        let mut properties: Vec<_> = self.properties.iter().collect();
+        properties.sort_by(|(a, _), (b, _)| a.cmp(b));
+        properties
+    }
+
+    fn optional_count(&self) -> usize {
+        self.properties
+            .iter()
+            .filter(|(name, _)| !self.is_required(name))
+            .count()
+    }
+
+    fn strip_keys_at(&self, object: Object, path: String) -> Result<Object, SchemaMismatch> {
+        match object.value {
+            Value::Map(mut items) if self.is_keyless(&path)? => {
+                if let Some((key, _)) = items.iter().find(|(k, _)| self.property(k).is_none()) {
+                    return Err(SchemaMismatch::UnknownField(format!(
+                        "{}/{}",
+                        path,
+                        escape_pointer_token(key)
+                    )));
+                }
+
+                for (i, (key, _)) in items.iter().enumerate() {
+                    if items[..i].iter().any(|(k, _)| k == key) {
+                        return Err(SchemaMismatch::DuplicateKey(format!(
+                            "{}/{}",
+                            path,
+                            escape_pointer_token(key)
+                        )));
+                    }
+                }
+
+                let mut bitmap = vec![0; self.optional_count().div_ceil(8)];
+                let mut optional = 0;
+                let mut values = Vec::with_capacity(items.len() + 1);
+
+                for (name, schema) in self.keyless_properties() {
+                    let present = items.iter().position(|(k, _)| k == name);
+
+                    if !self.is_required(name) {
+                        if present.is_some() {
+                            bitmap[optional / 8] |= 0x80 >> (optional % 8);
+                        }
+
+                        optional += 1;
+                    }
+
+                    if let Some(i) = present {
+                        let value = items.swap_remove(i).1;
+                        let value_path = format!("{}/{}", path, escape_pointer_token(name));
+                        values.push(schema.strip_keys_at(value, value_path)?);
+                    }
+                }
+
+                if optional > 0 {
+                    values.insert(0, Object::bytes(bitmap));
+                }
+
+                Ok(Object::list(values))
+            }
+            // the schema doesn't rule out a list here (it has no type), but it would come back as a map
+            Value::List(_) if self.is_keyless(&path)? => Err(SchemaMismatch::Ambiguous(path)),
+            Value::List(elements) => match &self.items {
+                Some(items) => Ok(Object::list(
+                    elements
+                        .into_iter()
+                        .enumerate()
+                        .map(|(i, e)| items.strip_keys_at(e, format!("{}/{}", path, i)))
+                        .collect::<Result<_, _>>()?,
+                )),
+                None => Ok(Object::list(elements)),
+            },
+            value => Ok(Object {
+                value,
+                length: object.length,
+            }),
+        }
+    }
+
+    fn restore_keys_at(&self, object: Object, path: String) -> Result<Object, SchemaMismatch> {
+        let malformed = || SchemaMismatch::Malformed(path.clone());
+
+        match object.value {
+            Value::List(elements) if self.is_keyless(&path)? => {
+                let mut elements = elements.into_iter();
+
+                let optional_count = self.optional_count();
+
+                let bitmap = if optional_count > 0 {
+                    match elements.next().map(|b| b.value) {
+                        Some(Value::Bytes(b)) if b.len() == optional_count.div_ceil(8) => b,
+                        _ => return Err(malformed()),
+                    }
+                } else {
+                    Vec::new()
+                };
+
+                let mut optional = 0;
+                let mut items = Vec::with_capacity(self.properties.len());
+
+                for (name, schema) in self.keyless_properties() {
+                    let present = if self.is_required(name) {
+                        true
+                    } else {
+                        optional += 1;
+                        bitmap[(optional - 1) / 8] & (0x80 >> ((optional - 1) % 8)) != 0
+                    };
+
+                    if present {
+                        let value = elements.next().ok_or_else(malformed)?;
+                        let value_path = format!("{}/{}", path, escape_pointer_token(name));
+                        items.push((name.clone(), schema.restore_keys_at(value, value_path)?));
+                    }
+                }
+
+                if elements.next().is_some() {
+                    return Err(malformed());
+                }
+
+                Ok(Object::map(items))
+            }
+            // keyless messages don't have maps where the schema declares properties
+            Value::Map(_) if self.is_keyless(&path)? => Err(malformed()),
+            Value::List(elements) => match &self.items {
+                Some(items) => Ok(Object::list(
+                    elements
+                        .into_iter()
+                        .enumerate()
+                        .map(|(i, e)| items.restore_keys_at(e, format!("{}/{}", path, i)))
+                        .collect::<Result<_, _>>()?,
+                )),
+                None => Ok(Object::list(elements)),
+            },
+            value => Ok(Object {
+                value,
+                length: object.length,
+            }),
+        }
+    }
+}
+
+fn as_f64(value: &Value) -> Option<f64> {
+    match value {
+        Value::SInt(i) => Some(*i as f64),
+        Value::UInt(u) => Some(*u as f64),
+        Value::Float32(f) => Some(*f as f64),
+        Value::Float64(f) => Some(*f),
+        Value::Timestamp32(t) => Some(*t as f64),
+        _ => None,
+    }
+}
+
+fn parse_schema(json: &serde_json::Value, path: String) -> Result<Schema, SchemaError> {
+    let error = |keyword: &str, message: &str| SchemaError {
+        path: format!("{}/{}", path, keyword),
+        message: message.to_string(),
+    };
+
+    let map = match json {
+        // `true` accepts anything, like an empty schema
+        serde_json::Value::Bool(true) => return Ok(Schema::default()),
+        serde_json::Value::Object(map) => map,
+        _ => {
+            return Err(SchemaError {
+                path,
+                message: "a schema must be an object".to_string(),
+            })
+        }
+    };
+
+    let mut schema = Schema::default();
+
+    if let Some(types) = map.get("type") {
+        let names = match types {
+            serde_json::Value::String(name) => vec![name.as_str()],
+            serde_json::Value::Array(names) => names
+                .iter()
+                .map(|n| n.as_str().ok_or_else(|| error("type", "must be a string")))
+                .collect::<Result<_, _>>()?,
+            _ => return Err(error("type", "must be a string or an array of strings")),
+        };
+
+        for name in names {
+            schema.types.push(
+                SchemaType::from_name(name)
+                    .ok_or_else(|| error("type", &format!("unknown type {:?}", name)))?,
+            );
+        }
+    }
+
+    if let Some(properties) = map.get("properties") {
+        let properties = properties
+            .as_object()
+            .ok_or_else(|| error("properties", "must be an object"))?;
+
+        for (name, property) in properties {
+            let property_path = format!("{}/properties/{}", path, escape_pointer_token(name));
+            schema
+                .properties
+                .push((name.clone(), parse_schema(property, property_path)?));
+        }
+    }
+
+    if let Some(required) = map.get("required") {
+        schema.required = required
+            .as_array()
+            .and_then(|r| r.iter().map(|n| n.as_str().map(str::to_string)).collect())
+            .ok_or_else(|| error("required", "must be an array of strings"))?;
+    }
+
+    if let Some(items) = map.get("items") {
+        schema.items = Some(Box::new(parse_schema(items, format!("{}/items", path))?));
+    }
+
+    if let Some(values) = map.get("enum") {
+        let values = values
+            .as_array()
+            .ok_or_else(|| error("enum", "must be an array"))?;
+
+        schema.enum_values = Some(values.iter().cloned().map(Object::from_json).collect());
+    }
+
+    if let Some(minimum) = map.get("minimum") {
+        schema.minimum = Some(
+            minimum
+                .as_f64()
+                .ok_or_else(|| error("minimum", "must be a number"))?,
+        );
+    }
+
+    if let Some(maximum) = map.get("maximum") {
+        schema.maximum = Some(
+            maximum
+                .as_f64()
+                .ok_or_else(|| error("maximum", "must be a number"))?,
+        );
+    }
+
+    if let Some(max_length) = map.get("maxLength") {
+        schema.max_length = Some(
+            max_length
+                .as_u64()
+                .ok_or_else(|| error("maxLength", "must be a non-negative integer"))?
+                as usize,
+        );
+    }
+
+    Ok(schema)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn schema(json: serde_json::Value) -> Schema {
+        Schema::from_json(&json).unwrap()
+    }
+
+    fn object(json: serde_json::Value) -> Object {
+        Object::from_json(json)
+    }
+
+    // a keyless map, `bitmap` first if there is one
+    fn keyless(bitmap: &[u8], values: &[serde_json::Value]) -> Object {
+        let bitmap = (!bitmap.is_empty()).then(|| Object::bytes(bitmap.to_vec()));
+        let values = values.iter().cloned().map(Object::from_json);
+
+        Object::list(bitmap.into_iter().chain(values).collect())
+    }
+
+    #[test]
+    fn violations_point_at_the_offending_values() {
+        let schema = schema(json!({
+            "type": "object",
+            "properties": {
+                "id": {"type": "integer", "minimum": 1},
+                "name": {"type": "string", "maxLength": 3},
+                "kind": {"enum": ["a", "b"]},
+                "tags": {"items": {"type": "string"}},
+                "a/b": {"type": "float64", "maximum": 1}
+            },
+            "required": ["id", "name"]
+        }));
+
+        assert_eq!(
+            schema.validate(&object(json!({"id": 1, "name": "ann", "a/b": 0.5}))),
+            vec![]
+        );
+
+        assert_eq!(
+            schema.validate(&object(json!({
+                "id": 0,
+                "name": "anna",
+                "kind": "c",
+                "tags": ["x", 1],
+                "a/b": 2.0
+            }))),
+            vec![
+                Violation {
+                    path: "/id".to_string(),
+                    kind: ViolationKind::BelowMinimum(1.0),
+                },
+                Violation {
+                    path: "/name".to_string(),
+                    kind: ViolationKind::TooLong {
+                        max_length: 3,
+                        length: 4,
+                    },
+                },
+                Violation {
+                    path: "/kind".to_string(),
+                    kind: ViolationKind::NotInEnum,
+                },
+                Violation {
+                    path: "/tags/1".to_string(),
+                    kind: ViolationKind::WrongType {
+                        expected: vec![SchemaType::String],
+                        found: "SInt",
+                    },
+                },
+                Violation {
+                    path: "/a~1b".to_string(),
+                    kind: ViolationKind::AboveMaximum(1.0),
+                },
+            ]
+        );
+
+        assert_eq!(
+            schema.validate(&object(json!({"name": -1}))),
+            vec![
+                Violation {
+                    path: String::new(),
+                    kind: ViolationKind::MissingField("id".to_string()),
+                },
+                Violation {
+                    path: "/name".to_string(),
+                    kind: ViolationKind::WrongType {
+                        expected: vec![SchemaType::String],
+                        found: "SInt",
+                    },
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn schemas_round_trip_through_json() {
+        let json = json!({
+            "type": ["object", "null"],
+            "properties": {"b": {"type": "integer"}, "a": {"items": {"type": "bytes"}}},
+            "required": ["b"],
+            "enum": [null],
+            "minimum": 0.0,
+            "maximum": 10.0,
+            "maxLength": 2
+        });
+
+        assert_eq!(schema(json.clone()).to_json(), json);
+        assert_eq!(schema(json!(true)), Schema::default());
+    }
+
+    #[test]
+    fn invalid_schemas_are_errors() {
+        for (json, path) in [
+            (json!(1), ""),
+            (json!({"type": "integerr"}), "/type"),
+            (json!({"type": [1]}), "/type"),
+            (json!({"properties": []}), "/properties"),
+            (
+                json!({"properties": {"a/b": {"type": 1}}}),
+                "/properties/a~1b/type",
+            ),
+            (json!({"items": {"required": "a"}}), "/items/required"),
+            (json!({"enum": {}}), "/enum"),
+            (json!({"minimum": "0"}), "/minimum"),
+            (json!({"maxLength": -1}), "/maxLength"),
+        ] {
+            assert_eq!(Schema::from_json(&json).unwrap_err().path, path, "{}", json);
+        }
+    }
+
+    #[test]
+    fn keyless_values_are_ordered_by_name() {
+        let message = object(json!({"c": 3, "a": 1, "b": 2}));
+
+        for names in [["a", "b", "c"], ["c", "b", "a"], ["b", "a", "c"]] {
+            let properties: serde_json::Map<_, _> =
+                names.iter().map(|n| (n.to_string(), json!({}))).collect();
+            let schema = schema(json!({"properties": properties, "required": ["b", "c"]}));
+
+            let stripped = schema.strip_keys(message.clone()).unwrap();
+            assert_eq!(stripped, keyless(&[0x80], &[json!(1), json!(2), json!(3)]));
+
+            let restored = schema.restore_keys(stripped).unwrap();
+            assert_eq!(restored, object(json!({"a": 1, "b": 2, "c": 3})));
+        }
+    }
+
+    #[test]
+    fn malformed_keyless_messages_are_errors() {
+        let schema = schema(json!({
+            "properties": {"a": {}, "b": {"properties": {"c": {}}, "required": ["c"]}},
+            "required": ["b"]
+        }));
+
+        assert_eq!(
+            schema.restore_keys(keyless(&[0x80], &[json!(1), json!([2])])),
+            Ok(object(json!({"a": 1, "b": {"c": 2}})))
+        );
+
+        for (bitmap, values, path) in [
+            (&[][..], vec![], ""),
+            (&[][..], vec![json!(1), json!([2])], ""),
+            (&[0x80, 0][..], vec![json!(1), json!([2])], ""),
+            (&[0x80][..], vec![json!(1)], ""),
+            (&[0x00][..], vec![json!([2]), json!(3)], ""),
+            (&[0x00][..], vec![json!([2, 3])], "/b"),
+            (&[0x00][..], vec![json!([])], "/b"),
+        ] {
+            assert_eq!(
+                schema.restore_keys(keyless(bitmap, &values)),
+                Err(SchemaMismatch::Malformed(path.to_string())),
+                "{:?} {:?}",
+                bitmap,
+                values
+            );
+        }
+    }
+}
@@ -0,0 +1,219 @@
+use std::collections::VecDeque;
+use std::io::{self, ErrorKind, Read, Write};
+
+use crate::decode::{headpack_decode_partial, DecodeError};
+use crate::encode::{headpack_try_encode, write_varint};
+use crate::object::Object;
+
+/*
+    A stream is a sequence of messages, each prefixed by its length in bytes as a varint
+    (7 bits per byte, lowest group first, top bit set on every byte but the last).
+*/
+
+pub struct StreamWriter<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> StreamWriter<W> {
+    pub fn new(inner: W) -> Self {
+        StreamWriter { inner }
+    }
+
+    pub fn write(&mut self, object: Object) -> io::Result<()> {
+        self.write_encoded(&headpack_try_encode(object)?)
+    }
+
+    // write a message that is already encoded
+    pub fn write_encoded(&mut self, message: &[u8]) -> io::Result<()> {
+        let mut prefix = Vec::new();
+        write_varint(message.len() as u128, &mut prefix);
+
+        self.inner.write_all(&prefix)?;
+        self.inner.write_all(message)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+pub struct StreamReader<R: Read> {
+    inner: R,
+}
+
+impl<R: Read> StreamReader<R> {
+    pub fn new(inner: R) -> Self {
+        StreamReader { inner }
+    }
+
+    // read the next message without decoding it, or None if the stream ended cleanly
+    pub fn read_encoded(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let length = match self.read_length()? {
+            Some(length) => length,
+            None => return Ok(None),
+        };
+
+        // the length isn't trusted with an allocation, the buffer only grows as data arrives
+        let mut message = Vec::new();
+        (&mut self.inner)
+            .take(length as u64)
+            .read_to_end(&mut message)?;
+
+        if message.len() < length {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+
+        Ok(Some(message))
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    fn read_length(&mut self) -> io::Result<Option<usize>> {
+        let mut length: usize = 0;
+        let mut shift = 0;
+
+        loop {
+            let mut byte = [0];
+
+            let read = match self.inner.read(&mut byte) {
+                Ok(read) => read,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+
+            if read == 0 {
+                // running out in between messages is fine, in the middle of a prefix it isn't
+                return if shift == 0 {
+                    Ok(None)
+                } else {
+                    Err(ErrorKind::UnexpectedEof.into())
+                };
+            }
+
+            let group = (byte[0] & 0x7f) as usize;
+
+            // the last group can only use the bits that are left
+            if shift >= usize::BITS || group > usize::MAX >> shift {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    "message length prefix is too long",
+                ));
+            }
+
+            length |= group << shift;
+            shift += 7;
+
+            if byte[0] & 0x80 == 0 {
+                return Ok(Some(length));
+            }
+        }
+    }
+}
+
+impl<R: Read> Iterator for StreamReader<R> {
+    type Item = io::Result<Object>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.read_encoded() {
+            Ok(Some(message)) => {
+                Some(decode_frame(message).map_err(|e| io::Error::new(ErrorKind::InvalidData, e)))
+            }
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+// the message has to take up the whole frame, anything after it would be lost
+fn decode_frame(message: Vec<u8>) -> Result<Object, DecodeError> {
+    let mut buf = VecDeque::from(message);
+    let object = headpack_decode_partial(&mut buf)?;
+
+    if !buf.is_empty() {
+        return Err(DecodeError::Malformed(format!(
+            "{} bytes are left in the frame after the message",
+            buf.len()
+        )));
+    }
+
+    Ok(object)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::encode::headpack_encode;
+
+    fn read_all(stream: Vec<u8>) -> io::Result<Vec<Object>> {
+        StreamReader::new(Cursor::new(stream)).collect()
+    }
+
+    fn messages() -> Vec<Object> {
+        vec![
+            Object::list(vec![Object::sint(1), Object::string("a".to_string())]),
+            Object::map(vec![("b".to_string(), Object::list(vec![]))]),
+            Object::list(vec![]),
+        ]
+    }
+
+    #[test]
+    fn streams_round_trip() {
+        let mut writer = StreamWriter::new(Vec::new());
+
+        for message in messages() {
+            writer.write(message).unwrap();
+        }
+
+        assert_eq!(read_all(writer.into_inner()).unwrap(), messages());
+        assert_eq!(read_all(Vec::new()).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn malformed_streams_are_errors() {
+        let message = headpack_encode(messages().remove(0));
+
+        let frame = |length: usize, message: &[u8]| {
+            let mut frame = Vec::new();
+            write_varint(length as u128, &mut frame);
+            frame.extend_from_slice(message);
+            frame
+        };
+
+        let mut trailing = message.clone();
+        trailing.push(0);
+
+        let cases = [
+            // bytes after the message inside its frame
+            (frame(trailing.len(), &trailing), ErrorKind::InvalidData),
+            // the frame ends before the message does
+            (frame(2, &message[..2]), ErrorKind::InvalidData),
+            // the stream ends before the frame does
+            (frame(message.len() + 1, &message), ErrorKind::UnexpectedEof),
+            // the stream ends in the middle of a length prefix
+            (vec![0x80], ErrorKind::UnexpectedEof),
+            // a length prefix longer than a usize
+            (vec![0xff; 11], ErrorKind::InvalidData),
+        ];
+
+        for (stream, kind) in cases {
+            let e = read_all(stream.clone()).unwrap_err();
+            assert_eq!(e.kind(), kind, "{:02x?}: {}", stream, e);
+        }
+    }
+}
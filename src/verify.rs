@@ -0,0 +1,474 @@
+use std::collections::VecDeque;
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde_json::json;
+
+use crate::bench::corpus_files;
+use crate::decode::headpack_try_decode;
+use crate::encode::headpack_try_encode;
+use crate::object::{Object, Value};
+use crate::pointer::escape_pointer_token;
+
+/*
+    Round-trip checks: every document is encoded, decoded again and compared with what went in.
+
+    Numbers compare by value, so a UInt that comes back as an SInt with the same value is fine,
+    but a float that lost precision or an integer that came back as something else is not. Paths
+    are JSON pointers into the document, the root is "".
+*/
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum MismatchKind {
+    // a float came back with a different value
+    FloatPrecision,
+    // an integer came back with a different value, e.g. it didn't fit in what it was written as
+    IntegerRange,
+    // the same map keys or list elements came back in a different order
+    Ordering,
+    // something came back as a different type
+    TypeChanged,
+    // same type, different value
+    ValueChanged,
+    // a map key or list element that didn't come back
+    Missing,
+    // a map key or list element that wasn't there before
+    Unexpected,
+    // the object couldn't be encoded, with the encode error
+    EncodeFailed(String),
+    // what was encoded didn't decode, with the decode error
+    DecodeFailed(String),
+}
+
+impl MismatchKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            MismatchKind::FloatPrecision => "float_precision",
+            MismatchKind::IntegerRange => "integer_range",
+            MismatchKind::Ordering => "ordering",
+            MismatchKind::TypeChanged => "type_changed",
+            MismatchKind::ValueChanged => "value_changed",
+            MismatchKind::Missing => "missing",
+            MismatchKind::Unexpected => "unexpected",
+            MismatchKind::EncodeFailed(_) => "encode_failed",
+            MismatchKind::DecodeFailed(_) => "decode_failed",
+        }
+    }
+}
+
+impl Display for MismatchKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            MismatchKind::FloatPrecision => write!(f, "float precision"),
+            MismatchKind::IntegerRange => write!(f, "integer range"),
+            MismatchKind::Ordering => write!(f, "ordering"),
+            MismatchKind::TypeChanged => write!(f, "type changed"),
+            MismatchKind::ValueChanged => write!(f, "value changed"),
+            MismatchKind::Missing => write!(f, "missing"),
+            MismatchKind::Unexpected => write!(f, "unexpected"),
+            MismatchKind::EncodeFailed(message) => write!(f, "failed to encode: {}", message),
+            MismatchKind::DecodeFailed(message) => write!(f, "failed to decode: {}", message),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mismatch {
+    pub path: String,
+    pub kind: MismatchKind,
+    // None if there's nothing there, on that side
+    pub expected: Option<Object>,
+    pub actual: Option<Object>,
+}
+
+impl Display for Mismatch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if let MismatchKind::EncodeFailed(_) | MismatchKind::DecodeFailed(_) = self.kind {
+            return write!(f, "{}", self.kind);
+        }
+
+        let describe = |object: &Option<Object>| match object {
+            Some(object) => format!(
+                "{} {}",
+                object.value.type_name(),
+                object.clone().into_json()
+            ),
+            None => "nothing".to_string(),
+        };
+
+        write!(
+            f,
+            "{}: expected {}, got {} ({})",
+            match self.path.is_empty() {
+                true => "(root)",
+                false => &self.path,
+            },
+            describe(&self.expected),
+            describe(&self.actual),
+            self.kind
+        )
+    }
+}
+
+// encode and decode `object`, and list every way what came back differs from it
+pub fn verify_round_trip(object: &Object) -> Vec<Mismatch> {
+    let encoded = match headpack_try_encode(object.clone()) {
+        Ok(encoded) => encoded,
+        Err(e) => {
+            return vec![Mismatch {
+                path: String::new(),
+                kind: MismatchKind::EncodeFailed(e.to_string()),
+                expected: None,
+                actual: None,
+            }];
+        }
+    };
+
+    let decoded = match headpack_try_decode(VecDeque::from(encoded)) {
+        Ok(decoded) => decoded,
+        Err(e) => {
+            return vec![Mismatch {
+                path: String::new(),
+                kind: MismatchKind::DecodeFailed(e.to_string()),
+                expected: None,
+                actual: None,
+            }];
+        }
+    };
+
+    let mut mismatches = Vec::new();
+    compare(object, &decoded, "", &mut mismatches);
+
+    mismatches
+}
+
+fn compare(expected: &Object, actual: &Object, path: &str, mismatches: &mut Vec<Mismatch>) {
+    let mut mismatch = |kind: MismatchKind| {
+        mismatches.push(Mismatch {
+            path: path.to_string(),
+            kind,
+            expected: Some(expected.clone()),
+            actual: Some(actual.clone()),
+        })
+    };
+
+    match (&expected.value, &actual.value) {
+        (Value::Map(expected_items), Value::Map(actual_items)) => {
+            let expected_keys: Vec<&String> = expected_items.iter().map(|(key, _)| key).collect();
+            let actual_keys: Vec<&String> = actual_items.iter().map(|(key, _)| key).collect();
+
+            if expected_keys != actual_keys
+                && expected_keys.len() == actual_keys.len()
+                && expected_keys.iter().all(|key| actual_keys.contains(key))
+            {
+                mismatch(MismatchKind::Ordering);
+            }
+
+            for (key, expected_value) in expected_items {
+                let child = format!("{}/{}", path, escape_pointer_token(key));
+
+                match actual_items.iter().find(|(k, _)| k == key) {
+                    Some((_, actual_value)) => {
+                        compare(expected_value, actual_value, &child, mismatches)
+                    }
+                    None => mismatches.push(Mismatch {
+                        path: child,
+                        kind: MismatchKind::Missing,
+                        expected: Some(expected_value.clone()),
+                        actual: None,
+                    }),
+                }
+            }
+
+            for (key, actual_value) in actual_items {
+                if !expected_items.iter().any(|(k, _)| k == key) {
+                    mismatches.push(Mismatch {
+                        path: format!("{}/{}", path, escape_pointer_token(key)),
+                        kind: MismatchKind::Unexpected,
+                        expected: None,
+                        actual: Some(actual_value.clone()),
+                    });
+                }
+            }
+        }
+        (Value::List(expected_elements), Value::List(actual_elements)) => {
+            if expected_elements != actual_elements
+                && is_permutation(expected_elements, actual_elements)
+            {
+                mismatch(MismatchKind::Ordering);
+                return;
+            }
+
+            for (i, expected_element) in expected_elements.iter().enumerate() {
+                let child = format!("{}/{}", path, i);
+
+                match actual_elements.get(i) {
+                    Some(actual_element) => {
+                        compare(expected_element, actual_element, &child, mismatches)
+                    }
+                    None => mismatches.push(Mismatch {
+                        path: child,
+                        kind: MismatchKind::Missing,
+                        expected: Some(expected_element.clone()),
+                        actual: None,
+                    }),
+                }
+            }
+
+            for (i, actual_element) in actual_elements
+                .iter()
+                .enumerate()
+                .skip(expected_elements.len())
+            {
+                mismatches.push(Mismatch {
+                    path: format!("{}/{}", path, i),
+                    kind: MismatchKind::Unexpected,
+                    expected: None,
+                    actual: Some(actual_element.clone()),
+                });
+            }
+        }
+        _ => {
+            if let Some(kind) = leaf_mismatch(expected, actual) {
+                mismatch(kind);
+            }
+        }
+    }
+}
+
+fn leaf_mismatch(expected: &Object, actual: &Object) -> Option<MismatchKind> {
+    match (&expected.value, &actual.value) {
+        (Value::SInt(_) | Value::UInt(_), Value::SInt(_) | Value::UInt(_)) => {
+            (integer(expected) != integer(actual)).then_some(MismatchKind::IntegerRange)
+        }
+        (Value::Float32(_) | Value::Float64(_), Value::Float32(_) | Value::Float64(_)) => {
+            let (a, b) = (float(expected), float(actual));
+
+            (a != b && !(a.is_nan() && b.is_nan())).then_some(MismatchKind::FloatPrecision)
+        }
+        (a, b) if std::mem::discriminant(a) != std::mem::discriminant(b) => {
+            Some(MismatchKind::TypeChanged)
+        }
+        (a, b) => (a != b).then_some(MismatchKind::ValueChanged),
+    }
+}
+
+// the same elements, each one used once, in any order
+fn is_permutation(a: &[Object], b: &[Object]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    let mut used = vec![false; b.len()];
+
+    a.iter().all(
+        |element| match (0..b.len()).find(|&i| !used[i] && b[i] == *element) {
+            Some(i) => {
+                used[i] = true;
+                true
+            }
+            None => false,
+        },
+    )
+}
+
+// the value of an SInt or UInt, None if it doesn't fit an i128
+fn integer(object: &Object) -> Option<i128> {
+    match object.value {
+        Value::SInt(i) => Some(i),
+        Value::UInt(u) => i128::try_from(u).ok(),
+        _ => unreachable!(),
+    }
+}
+
+fn float(object: &Object) -> f64 {
+    match object.value {
+        Value::Float32(f) => f as f64,
+        Value::Float64(f) => f,
+        _ => unreachable!(),
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FileVerification {
+    pub path: PathBuf,
+    pub documents: usize,
+    // the index of every document that didn't round-trip, with what went wrong
+    pub failures: Vec<(usize, Vec<Mismatch>)>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct VerifyReport {
+    pub files: Vec<FileVerification>,
+}
+
+// round-trip a single file, or every JSON and NDJSON file in a directory and its subdirectories
+pub fn verify_corpus(path: &Path) -> io::Result<VerifyReport> {
+    let mut files = Vec::new();
+
+    for file in corpus_files(path)? {
+        files.push(verify_file(&file)?);
+    }
+
+    Ok(VerifyReport { files })
+}
+
+pub fn verify_file(path: &Path) -> io::Result<FileVerification> {
+    let with_path = |e: io::Error| io::Error::new(e.kind(), format!("{}: {}", path.display(), e));
+
+    let data = fs::read(path).map_err(with_path)?;
+
+    let documents: Vec<serde_json::Value> = serde_json::Deserializer::from_slice(&data)
+        .into_iter()
+        .collect::<Result<_, _>>()
+        .map_err(|e| with_path(e.into()))?;
+
+    let mut failures = Vec::new();
+
+    for (i, document) in documents.iter().enumerate() {
+        let mismatches = verify_round_trip(&Object::from_json(document.clone()));
+
+        if !mismatches.is_empty() {
+            failures.push((i, mismatches));
+        }
+    }
+
+    Ok(FileVerification {
+        path: path.to_path_buf(),
+        documents: documents.len(),
+        failures,
+    })
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.files.iter().all(|file| file.failures.is_empty())
+    }
+
+    pub fn documents(&self) -> usize {
+        self.files.iter().map(|file| file.documents).sum()
+    }
+
+    pub fn failed_documents(&self) -> usize {
+        self.files.iter().map(|file| file.failures.len()).sum()
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let side = |object: &Option<Object>| match object {
+            Some(object) => json!({
+                "type": object.value.type_name(),
+                "value": object.clone().into_json(),
+            }),
+            None => serde_json::Value::Null,
+        };
+
+        let files: Vec<serde_json::Value> = self
+            .files
+            .iter()
+            .map(|file| {
+                let failures: Vec<serde_json::Value> = file
+                    .failures
+                    .iter()
+                    .map(|(document, mismatches)| {
+                        let mismatches: Vec<serde_json::Value> = mismatches
+                            .iter()
+                            .map(|mismatch| {
+                                let mut json = json!({
+                                    "path": mismatch.path,
+                                    "kind": mismatch.kind.name(),
+                                    "expected": side(&mismatch.expected),
+                                    "actual": side(&mismatch.actual),
+                                });
+
+                                if let MismatchKind::EncodeFailed(message)
+                                | MismatchKind::DecodeFailed(message) = &mismatch.kind
+                                {
+                                    json["message"] = json!(message);
+                                }
+
+                                json
+                            })
+                            .collect();
+
+                        json!({ "document": document, "mismatches": mismatches })
+                    })
+                    .collect();
+
+                json!({
+                    "path": file.path.display().to_string(),
+                    "documents": file.documents,
+                    "failures": failures,
+                })
+            })
+            .collect();
+
+        json!({
+            "ok": self.is_ok(),
+            "documents": self.documents(),
+            "failed_documents": self.failed_documents(),
+            "files": files,
+        })
+    }
+}
+
+impl Display for VerifyReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for file in &self.files {
+            for (document, mismatches) in &file.failures {
+                for mismatch in mismatches {
+                    writeln!(
+                        f,
+                        "{}: document {}: {}",
+                        file.path.display(),
+                        document,
+                        mismatch
+                    )?;
+                }
+            }
+        }
+
+        write!(
+            f,
+            "{} of {} documents in {} files round-tripped",
+            self.documents() - self.failed_documents(),
+            self.documents(),
+            self.files.len()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn documents_round_trip_without_mismatches() {
+        let document = Object::from_json(serde_json::json!({
+            "ids": [1001, 1002, 1003, 1005],
+            "names": ["a", "a", "a", "b"],
+            "nested": {"x": -0.5, "y": null, "z": [true, false]}
+        }));
+
+        assert_eq!(verify_round_trip(&document), vec![]);
+    }
+
+    #[test]
+    fn objects_that_cant_be_encoded_are_mismatches() {
+        let invalid = Object::list(vec![Object {
+            length: 1,
+            value: Value::UserDefined {
+                id: 1,
+                data: vec![0],
+            },
+        }]);
+
+        for object in [Object::sint(1), invalid] {
+            let mismatches = verify_round_trip(&object);
+
+            assert_eq!(mismatches.len(), 1);
+            assert!(matches!(mismatches[0].kind, MismatchKind::EncodeFailed(_)));
+        }
+    }
+}
@@ -0,0 +1,38 @@
+/*
+    A framed message is a regular HeadPack message preceded by MAGIC and a single version byte.
+    Raw messages have no prefix at all, so they can't be told apart from random bytes.
+*/
+
+// 0x89 is not valid ASCII or the start of a UTF-8 character, so text is never mistaken for a message
+pub const MAGIC: [u8; 3] = [0x89, b'H', b'P'];
+
+// length of MAGIC plus the version byte
+pub const PREFIX_LENGTH: usize = MAGIC.len() + 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Version(pub u8);
+
+impl Version {
+    pub const V1: Version = Version(1);
+
+    // the version written by this encoder
+    pub const CURRENT: Version = Version::V1;
+
+    pub fn is_supported(&self) -> bool {
+        *self >= Version::V1 && *self <= Version::CURRENT
+    }
+}
+
+// returns the format version of a framed message, or None if `buf` doesn't start with MAGIC
+pub fn detect(buf: &[u8]) -> Option<Version> {
+    if buf.len() < PREFIX_LENGTH || buf[..MAGIC.len()] != MAGIC {
+        return None;
+    }
+
+    Some(Version(buf[MAGIC.len()]))
+}
+
+pub fn write_prefix(version: Version, buf: &mut Vec<u8>) {
+    buf.extend_from_slice(&MAGIC);
+    buf.push(version.0);
+}